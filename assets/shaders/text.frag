@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0) in vec4 v_Color;
+layout(location = 1) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Color;
+
+layout(set = 0, binding = 1) uniform texture2D t_Color;
+layout(set = 0, binding = 2) uniform sampler t_Color_;
+
+void main() {
+    vec4 t_Font_Color = texture(sampler2D(t_Color, t_Color_), v_TexCoord);
+    o_Color = vec4(v_Color.rgb, t_Font_Color.r * v_Color.a);
+}
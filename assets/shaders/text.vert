@@ -0,0 +1,54 @@
+#version 450
+
+layout(location = 0) in vec2 a_Corner;
+layout(location = 1) in vec2 a_Pos;
+layout(location = 2) in vec2 a_Size;
+layout(location = 3) in vec4 a_TexRect;
+layout(location = 4) in vec3 a_World_Pos;
+layout(location = 5) in vec4 a_Color;
+layout(location = 6) in vec3 a_Rotation;
+layout(location = 0) out vec4 v_Color;
+layout(location = 1) out vec2 v_TexCoord;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 u_Proj;
+    vec4 u_Tint;
+    vec2 u_Screen_Size;
+    float u_Srgb;
+};
+
+void main() {
+    // Rotate the quad around its center, (cos, sin) of the angle is given
+    // followed by the screen relative flag.
+    vec2 v_Half_Size = 0.5 * a_Size;
+    vec2 v_Offset = (a_Corner - 0.5) * a_Size;
+    vec2 v_Pos = a_Pos + v_Half_Size + vec2(
+        v_Offset.x * a_Rotation.x - v_Offset.y * a_Rotation.y,
+        v_Offset.x * a_Rotation.y + v_Offset.y * a_Rotation.x
+    );
+    // On-screen offset from text origin.
+    vec2 v_Screen_Offset = vec2(
+        2.0 * v_Pos.x / u_Screen_Size.x - 1.0,
+        1.0 - 2.0 * v_Pos.y / u_Screen_Size.y
+    );
+    vec4 v_Screen_Pos = u_Proj * vec4(a_World_Pos, 1.0);
+    vec2 v_World_Offset = a_Rotation.z < 0.5
+        // Perspective divide to get normalized device coords.
+        ? vec2(
+            v_Screen_Pos.x / v_Screen_Pos.z + 1.0,
+            v_Screen_Pos.y / v_Screen_Pos.z - 1.0
+        ) : vec2(0.0, 0.0);
+
+    v_Color = a_Color * u_Tint;
+    // sRGB targets encode the output, so they are given linear colors.
+    vec3 v_Linear = mix(
+        v_Color.rgb / 12.92,
+        pow((v_Color.rgb + 0.055) / 1.055, vec3(2.4)),
+        step(vec3(0.04045), v_Color.rgb)
+    );
+    v_Color.rgb = mix(v_Color.rgb, v_Linear, u_Srgb);
+    v_TexCoord = a_TexRect.xy + a_Corner * a_TexRect.zw;
+    vec2 v_Ndc = v_World_Offset + v_Screen_Offset;
+    // Vulkan's Y axis points down.
+    gl_Position = vec4(v_Ndc.x, -v_Ndc.y, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec4 v_Color;
+layout(location = 1) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Color;
+
+layout(set = 0, binding = 1) uniform texture2D t_Color;
+layout(set = 0, binding = 2) uniform sampler t_Color_;
+
+float median(vec3 v) {
+    return max(min(v.r, v.g), min(max(v.r, v.g), v.b));
+}
+
+void main() {
+    float v_Distance = median(texture(sampler2D(t_Color, t_Color_), v_TexCoord).rgb) - 0.5;
+    float v_Alpha = clamp(v_Distance / max(fwidth(v_Distance), 0.0001) + 0.5, 0.0, 1.0);
+    o_Color = vec4(v_Color.rgb, v_Alpha * v_Color.a);
+}
@@ -21,14 +21,14 @@ fn main() {
 
     let hdpi = window.draw_size().width / window.size().width;
 
-    let mut normal_text = gfx_text::new(window.factory.clone())
-        .with_size((16.0 * hdpi) as u8).unwrap();
-    let mut big_text = gfx_text::new(window.factory.clone())
-        .with_size((20.0 * hdpi) as u8).unwrap();
-    let mut custom_font_text = gfx_text::new(window.factory.clone())
+    let mut normal_text = gfx_text::new()
+        .with_size((16.0 * hdpi) as u8).unwrap(&mut window.factory);
+    let mut big_text = gfx_text::new()
+        .with_size((20.0 * hdpi) as u8).unwrap(&mut window.factory);
+    let mut custom_font_text = gfx_text::new()
         .with_size((25.0 * hdpi) as u8)
         .with_font(FONT_PATH)
-        .unwrap();
+        .unwrap(&mut window.factory);
 
     let main_color = window.output_color.clone();
     let mut counter: u32 = 0;
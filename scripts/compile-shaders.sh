@@ -0,0 +1,9 @@
+#!/bin/sh
+# Regenerate SPIR-V binaries embedded into the library from the GLSL sources.
+# Requires glslangValidator from the Vulkan SDK.
+set -e
+cd "$(dirname "$0")/../assets/shaders"
+for stage in vert frag; do
+    glslangValidator -V "text.$stage" -o "text.$stage.spv"
+done
+glslangValidator -V text_msdf.frag -o text_msdf.frag.spv
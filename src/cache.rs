@@ -0,0 +1,120 @@
+//! Disk cache of the built font textures, see `RendererBuilder::with_cache_dir`.
+//! Textures are stored under a hash of the font data and all settings which
+//! affect the rasterized glyphs.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use font::{BitmapFont, FaceInfo, FontResult, FontSource};
+
+/// Load the font texture from the cache directory or build it and store
+/// into the cache.
+pub fn build(dir: &str, faces: &[FaceInfo], font_size: u8, chars: Option<&[char]>,
+             variations: &[([u8; 4], f32)], distance_range: u8, max_size: u16) -> FontResult {
+    let path = entry_path(dir, key(faces, font_size, chars, variations, distance_range, max_size)?);
+    if let Ok(font) = load(&path) {
+        return Ok(font);
+    }
+    let font = BitmapFont::from_faces(faces, font_size, chars, variations, distance_range, max_size)?;
+    // NOTE: Cache is just an optimization, e.g. read-only directory
+    // shouldn't prevent rendering.
+    let _ = store(&path, &font);
+    Ok(font)
+}
+
+fn key(faces: &[FaceInfo], font_size: u8, chars: Option<&[char]>,
+       variations: &[([u8; 4], f32)], distance_range: u8, max_size: u16) -> io::Result<u64> {
+    // NOTE: Hasher output may change between Rust releases, which only leads
+    // to rebuilding the texture once.
+    let mut hasher = DefaultHasher::new();
+    for face in faces {
+        match face.source {
+            FontSource::Path(path) => {
+                let mut data = Vec::new();
+                File::open(path)?.read_to_end(&mut data)?;
+                data.hash(&mut hasher);
+            },
+            FontSource::Data(data) => data.hash(&mut hasher),
+        }
+        face.index.hash(&mut hasher);
+        face.synthetic.hash(&mut hasher);
+        face.charmap.hash(&mut hasher);
+        face.glyphs.hash(&mut hasher);
+        face.outlines.hash(&mut hasher);
+    }
+    font_size.hash(&mut hasher);
+    chars.hash(&mut hasher);
+    distance_range.hash(&mut hasher);
+    // Limits the row width of the texture.
+    max_size.hash(&mut hasher);
+    for &(tag, value) in variations {
+        tag.hash(&mut hasher);
+        value.to_bits().hash(&mut hasher);
+    }
+    // All font glyphs are placed into the texture if shaping is enabled.
+    cfg!(feature = "shaping").hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+fn entry_path(dir: &str, key: u64) -> PathBuf {
+    Path::new(dir).join(format!("{:016x}.font", key))
+}
+
+fn load(path: &Path) -> io::Result<BitmapFont> {
+    BitmapFont::read_from(&mut BufReader::new(File::open(path)?))
+}
+
+fn store(path: &Path, font: &BitmapFont) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first, so concurrent runs never read
+    // incomplete entry.
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        font.write_to(&mut w)?;
+        w.flush()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::{Charmap, SyntheticStyle};
+
+    const FONT: &'static [u8] = include_bytes!("../assets/NotoSans-Regular.ttf");
+
+    fn face<'a>(outlines: &'a [u8]) -> FaceInfo<'a> {
+        FaceInfo {
+            source: FontSource::Data(FONT),
+            index: 0,
+            synthetic: SyntheticStyle::default(),
+            charmap: Charmap::default(),
+            glyphs: &[],
+            outlines: outlines,
+        }
+    }
+
+    #[test]
+    fn key_changes_with_settings() {
+        let chars = ['a', 'b'];
+        let base = key(&[face(&[])], 16, Some(&chars), &[], 0, 1024).unwrap();
+        assert_eq!(key(&[face(&[])], 16, Some(&chars), &[], 0, 1024).unwrap(), base);
+        let changed = [
+            key(&[face(&[])], 17, Some(&chars), &[], 0, 1024).unwrap(),
+            key(&[face(&[])], 16, Some(&['a', 'c']), &[], 0, 1024).unwrap(),
+            key(&[face(&[])], 16, None, &[], 0, 1024).unwrap(),
+            key(&[face(&[])], 16, Some(&chars), &[(*b"wght", 700.0)], 0, 1024).unwrap(),
+            key(&[face(&[2])], 16, Some(&chars), &[], 0, 1024).unwrap(),
+        ];
+        for (i, &changed) in changed.iter().enumerate() {
+            assert!(changed != base, "change {} keeps the key", i);
+        }
+        // Variation values are part of the key, not only the axes.
+        assert!(key(&[face(&[])], 16, Some(&chars), &[(*b"wght", 400.0)], 0, 1024).unwrap() != changed[3]);
+    }
+}
@@ -0,0 +1,121 @@
+//! Layered color glyphs of the OpenType `COLR` and `CPAL` tables, see
+//! `BitmapFont::find_color_layers`.
+//!
+//! Only the version 0 part of the `COLR` table is read: every color glyph is
+//! a stack of ordinary glyphs filled with palette colors, so the layers are
+//! rasterized like any other glyph and tinted when drawn. Gradients of the
+//! version 1 glyphs are not supported, their version 0 records are used if
+//! the font has them.
+
+use std::collections::HashMap;
+use font::ColorLayer;
+
+// Palette index of the layers filled with the text color.
+const FOREGROUND: u16 = 0xFFFF;
+
+/// Read layers of all color glyphs of the face with the given index in the
+/// font file data, colored with the first palette. Fonts without the tables
+/// or with malformed ones have no color glyphs.
+pub fn color_glyphs(data: &[u8], face_index: u32) -> HashMap<u32, Vec<ColorLayer>> {
+    parse(data, face_index).unwrap_or_default()
+}
+
+fn parse(data: &[u8], face_index: u32) -> Option<HashMap<u32, Vec<ColorLayer>>> {
+    let face = face_offset(data, face_index)?;
+    let colr = match find_table(data, face, b"COLR")? {
+        Some(colr) => colr,
+        None => return Some(HashMap::new()),
+    };
+    let palette = match find_table(data, face, b"CPAL")? {
+        Some(cpal) => first_palette(cpal)?,
+        None => Vec::new(),
+    };
+
+    let num_base_glyphs = read_u16(colr, 2)? as usize;
+    let base_glyphs = read_u32(colr, 4)? as usize;
+    let layers = read_u32(colr, 8)? as usize;
+    let num_layers = read_u16(colr, 12)? as usize;
+    let mut glyphs = HashMap::with_capacity(num_base_glyphs);
+    for i in 0..num_base_glyphs {
+        let record = base_glyphs + i * 6;
+        let glyph = read_u16(colr, record)?;
+        let first_layer = read_u16(colr, record + 2)? as usize;
+        let count = read_u16(colr, record + 4)? as usize;
+        if first_layer + count > num_layers {
+            return None;
+        }
+        let glyph_layers = (first_layer..first_layer + count).map(|layer| {
+            let record = layers + layer * 4;
+            let palette_index = read_u16(colr, record + 2)?;
+            Some(ColorLayer {
+                glyph: read_u16(colr, record)? as u32,
+                // NOTE: Indices past the palette are drawn with the text
+                // color too, as the specification suggests.
+                color: match palette_index {
+                    FOREGROUND => None,
+                    index => palette.get(index as usize).cloned(),
+                },
+            })
+        }).collect::<Option<Vec<_>>>()?;
+        glyphs.insert(glyph as u32, glyph_layers);
+    }
+    Some(glyphs)
+}
+
+// Colors of the first palette as straight RGBA.
+fn first_palette(cpal: &[u8]) -> Option<Vec<[f32; 4]>> {
+    let num_entries = read_u16(cpal, 2)? as usize;
+    let num_palettes = read_u16(cpal, 4)?;
+    let records = read_u32(cpal, 8)? as usize;
+    if num_palettes == 0 {
+        return Some(Vec::new());
+    }
+    let first_record = read_u16(cpal, 12)? as usize;
+    (0..num_entries).map(|i| {
+        // Records are stored as BGRA bytes.
+        let record = cpal.get(records + (first_record + i) * 4..)?.get(..4)?;
+        Some([
+            record[2] as f32 / 255.0,
+            record[1] as f32 / 255.0,
+            record[0] as f32 / 255.0,
+            record[3] as f32 / 255.0,
+        ])
+    }).collect()
+}
+
+// Offset of the table directory of the face, which may be in a collection.
+fn face_offset(data: &[u8], face_index: u32) -> Option<usize> {
+    if data.get(..4)? == b"ttcf" {
+        let num_faces = read_u32(data, 8)?;
+        if face_index >= num_faces {
+            return None;
+        }
+        read_u32(data, 12 + face_index as usize * 4).map(|offset| offset as usize)
+    } else {
+        Some(0)
+    }
+}
+
+// Data of the table with the tag, `Some(None)` if the face doesn't have it.
+fn find_table<'a>(data: &'a [u8], face: usize, tag: &[u8; 4]) -> Option<Option<&'a [u8]>> {
+    let num_tables = read_u16(data, face + 4)? as usize;
+    for i in 0..num_tables {
+        let record = face + 12 + i * 16;
+        if data.get(record..record + 4)? == tag {
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            return data.get(offset..offset.checked_add(length)?).map(Some);
+        }
+    }
+    Some(None)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some((read_u16(bytes, 0)? as u32) << 16 | read_u16(bytes, 2)? as u32)
+}
@@ -0,0 +1,164 @@
+//! Renderer settings loadable from data files, see `RendererConfig`.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use super::{Origin, RendererBuilder, DEFAULT_BUFFER_COUNT, DEFAULT_BUFFER_SIZE, DEFAULT_FONT_SIZE,
+            DEFAULT_MAX_BUFFER_SIZE, DEFAULT_TAB_WIDTH};
+use font::DEFAULT_MAX_SIZE;
+
+/// Renderer settings as plain data, so they may be kept in configuration
+/// files instead of builder calls. Deserializable with the `serde` feature,
+/// missing fields take the builder defaults.
+///
+/// # Examples
+///
+/// ```ignore
+/// // hud.toml:
+/// // font_path = "assets/hud.ttf"
+/// // size = 20
+/// // charset = "0123456789:"
+/// let config: gfx_text::RendererConfig = toml::from_str(&hud_toml).unwrap();
+/// let mut text = gfx_text::RendererBuilder::from_config(&config).build(&mut factory).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RendererConfig {
+    /// Path of the font file, the default font is used if not set.
+    pub font_path: Option<String>,
+    /// Index of the face in a font collection.
+    pub font_index: u32,
+    /// Font size in pixels.
+    pub size: u8,
+    /// Chars placed into the texture, all chars of the font if not set.
+    /// Written as a single string in configuration files.
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_chars",
+                                        serialize_with = "serialize_chars"))]
+    pub charset: Option<Vec<char>>,
+    /// Outline width and color, see `RendererBuilder::with_outline`.
+    pub outline: Option<(u8, [f32; 4])>,
+    /// Tab width in columns.
+    pub tab_width: u8,
+    /// OpenType features by their tags, see `RendererBuilder::with_features`.
+    pub features: Vec<(String, u32)>,
+    /// Variable font axes by their tags, see `RendererBuilder::with_variation`.
+    pub variations: Vec<(String, f32)>,
+    /// Whether to embolden glyph outlines.
+    pub synthetic_bold: bool,
+    /// Whether to slant glyph outlines.
+    pub synthetic_oblique: bool,
+    /// Directory of the font texture cache, see `RendererBuilder::with_cache_dir`.
+    pub cache_dir: Option<String>,
+    /// Glow radius and color, see `RendererBuilder::with_glow`.
+    pub glow: Option<(u8, [f32; 4])>,
+    /// Stroke width of hollow glyphs, zero for filled ones.
+    pub hollow: u8,
+    /// Range of the distance fields, zero for coverage, see
+    /// `RendererBuilder::with_msdf`.
+    pub msdf: u8,
+    /// Maximal width and height of the font texture.
+    pub max_texture_size: u16,
+    /// Initial instance buffer size in glyphs.
+    pub buffer_size: usize,
+    /// Maximal instance buffer size in glyphs.
+    pub max_buffer_size: usize,
+    /// Number of instance buffers used in turn.
+    pub buffer_count: usize,
+    /// Whether to store glyph instances in the packed format.
+    pub packed_instances: bool,
+    /// Frames of low usage before the buffer shrinks, zero to never shrink.
+    pub buffer_shrinking: usize,
+    /// Virtual screen width and height the text is laid out in.
+    pub virtual_resolution: Option<(u16, u16)>,
+    /// Origin of the screen coordinates.
+    pub origin: Origin,
+    /// Offset of all glyphs in target pixels, see
+    /// `RendererBuilder::with_pixel_offset`.
+    pub pixel_offset: [f32; 2],
+}
+
+impl Default for RendererConfig {
+    fn default() -> RendererConfig {
+        RendererConfig {
+            font_path: None,
+            font_index: 0,
+            size: DEFAULT_FONT_SIZE,
+            charset: None,
+            outline: None,
+            tab_width: DEFAULT_TAB_WIDTH,
+            features: Vec::new(),
+            variations: Vec::new(),
+            synthetic_bold: false,
+            synthetic_oblique: false,
+            cache_dir: None,
+            glow: None,
+            hollow: 0,
+            msdf: 0,
+            max_texture_size: DEFAULT_MAX_SIZE,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            packed_instances: false,
+            buffer_shrinking: 0,
+            virtual_resolution: None,
+            origin: Origin::TopLeft,
+            pixel_offset: [0.0, 0.0],
+        }
+    }
+}
+
+impl<'r> RendererBuilder<'r> {
+    /// Create a new text renderer builder with the settings of the config.
+    /// Other settings may still be changed with the builder methods.
+    pub fn from_config(config: &'r RendererConfig) -> Self {
+        let features: Vec<_> = config.features.iter().map(|&(ref tag, value)| (&tag[..], value)).collect();
+        let variations: Vec<_> = config.variations.iter().map(|&(ref tag, value)| (&tag[..], value)).collect();
+        let mut builder = RendererBuilder::new()
+            .with_size(config.size)
+            .with_tab_width(config.tab_width)
+            .with_features(&features)
+            .with_variation(&variations)
+            .with_synthetic_bold(config.synthetic_bold)
+            .with_synthetic_oblique(config.synthetic_oblique)
+            .with_hollow(config.hollow)
+            .with_msdf(config.msdf)
+            .with_max_texture_size(config.max_texture_size)
+            .with_buffer_size(config.buffer_size)
+            .with_max_buffer_size(config.max_buffer_size)
+            .with_buffer_count(config.buffer_count)
+            .with_packed_instances(config.packed_instances)
+            .with_buffer_shrinking(config.buffer_shrinking)
+            .with_origin(config.origin)
+            .with_pixel_offset(config.pixel_offset);
+        if let Some(ref path) = config.font_path {
+            builder = builder.with_font_index(path, config.font_index);
+        }
+        if let Some(ref chars) = config.charset {
+            builder = builder.with_chars(chars);
+        }
+        if let Some((width, color)) = config.outline {
+            builder = builder.with_outline(width, color);
+        }
+        if let Some(ref dir) = config.cache_dir {
+            builder = builder.with_cache_dir(dir);
+        }
+        if let Some((radius, color)) = config.glow {
+            builder = builder.with_glow(radius, color);
+        }
+        if let Some((width, height)) = config.virtual_resolution {
+            builder = builder.with_virtual_resolution(width, height);
+        }
+        builder
+    }
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_chars<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<char>>, D::Error> {
+    let chars: Option<String> = Deserialize::deserialize(deserializer)?;
+    Ok(chars.map(|chars| chars.chars().collect()))
+}
+
+#[cfg(feature = "serde")]
+fn serialize_chars<S: Serializer>(chars: &Option<Vec<char>>, serializer: S) -> Result<S::Ok, S::Error> {
+    chars.as_ref().map(|chars| chars.iter().collect::<String>()).serialize(serializer)
+}
@@ -0,0 +1,184 @@
+//! Scrolling console overlay for logs and debug output.
+
+use std::collections::VecDeque;
+use gfx::{CommandBuffer, Encoder, Factory, Resources};
+use gfx::handle::RenderTargetView;
+use super::{Color, Error, Renderer};
+
+const DEFAULT_MAX_LINES: usize = 1000;
+const DEFAULT_PADDING: i32 = 4;
+
+/// Severity of a console message, selects its color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LogLevel {
+    /// Verbose diagnostics
+    Debug,
+    /// Regular messages
+    Info,
+    /// Something unexpected but recoverable
+    Warn,
+    /// Something failed
+    Error,
+}
+
+impl LogLevel {
+    fn index(self) -> usize {
+        match self {
+            LogLevel::Debug => 0,
+            LogLevel::Info => 1,
+            LogLevel::Warn => 2,
+            LogLevel::Error => 3,
+        }
+    }
+}
+
+/// Console widget showing the last logged messages in a box, newest at the
+/// bottom. Messages are wrapped to the box width when they are logged.
+///
+/// # Examples
+///
+/// ```ignore
+/// let text = gfx_text::new().with_size(12).build(&mut factory).unwrap();
+/// let mut console = gfx_text::Console::new(text, [10, 10], 400, 10);
+/// console.log(gfx_text::LogLevel::Warn, "Texture not found: grass.png");
+///
+/// // In render loop:
+/// console.draw(&mut encoder, &color_output).unwrap();
+/// ```
+pub struct Console<R: Resources, F: Factory<R>> {
+    renderer: Renderer<R, F>,
+    // Wrapped lines, oldest first.
+    lines: VecDeque<(LogLevel, String)>,
+    max_lines: usize,
+    pos: [i32; 2],
+    width: i32,
+    visible_lines: usize,
+    // Number of newest lines scrolled out of view at the bottom.
+    scroll: usize,
+    colors: [[f32; 4]; 4],
+    background: Option<[f32; 4]>,
+}
+
+impl<R: Resources, F: Factory<R>> Console<R, F> {
+    /// Create a console drawing with the renderer in the box with the top
+    /// left corner at the given position, the given width in pixels and
+    /// height in lines.
+    pub fn new(renderer: Renderer<R, F>, pos: [i32; 2], width: i32, visible_lines: usize) -> Console<R, F> {
+        Console {
+            renderer: renderer,
+            lines: VecDeque::new(),
+            max_lines: DEFAULT_MAX_LINES,
+            pos: pos,
+            width: width,
+            visible_lines: visible_lines,
+            scroll: 0,
+            colors: [
+                [0.6, 0.6, 0.6, 1.0],
+                [1.0, 1.0, 1.0, 1.0],
+                [1.0, 0.8, 0.2, 1.0],
+                [1.0, 0.3, 0.3, 1.0],
+            ],
+            background: Some([0.0, 0.0, 0.0, 0.6]),
+        }
+    }
+
+    /// Set how many wrapped lines are kept, older ones are dropped.
+    pub fn set_max_lines(&mut self, max_lines: usize) {
+        self.max_lines = max_lines;
+        self.trim();
+    }
+
+    /// Set text color of the messages with the given level.
+    pub fn set_color<C: Into<Color>>(&mut self, level: LogLevel, color: C) {
+        let color = color.into().0;
+        self.colors[level.index()] = color;
+    }
+
+    /// Set color of the box behind the text, `None` to draw no box.
+    pub fn set_background<C: Into<Color>>(&mut self, background: Option<C>) {
+        self.background = background.map(|color| color.into().0);
+    }
+
+    /// Move the box to the given position.
+    pub fn set_position(&mut self, pos: [i32; 2]) {
+        self.pos = pos;
+    }
+
+    /// Add a message, multiline messages are allowed.
+    pub fn log(&mut self, level: LogLevel, msg: &str) {
+        let wrapped: Vec<String> = self.renderer.layout().wrap_lines(msg, self.width)
+            .into_iter()
+            .map(|line| if line.hyphenated {
+                format!("{}-", line.text)
+            } else {
+                line.text.to_owned()
+            })
+            .collect();
+        // Keep the view in place while scrolled back.
+        if self.scroll > 0 {
+            self.scroll += wrapped.len();
+        }
+        self.lines.extend(wrapped.into_iter().map(|line| (level, line)));
+        self.trim();
+    }
+
+    /// Scroll the view by the number of lines, positive values show older
+    /// lines.
+    pub fn scroll(&mut self, lines: isize) {
+        let max_scroll = self.lines.len().saturating_sub(self.visible_lines);
+        let scroll = self.scroll as isize + lines;
+        self.scroll = if scroll < 0 { 0 } else { (scroll as usize).min(max_scroll) };
+    }
+
+    /// Remove all messages.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.scroll = 0;
+    }
+
+    /// Return the renderer used for drawing, e.g. to add other text drawn
+    /// together with the console.
+    pub fn renderer(&mut self) -> &mut Renderer<R, F> {
+        &mut self.renderer
+    }
+
+    /// Draw the console to the render target.
+    pub fn draw<C: CommandBuffer<R>, T: ::gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>
+    ) -> Result<(), Error> {
+        // Everything is laid out top-down, as the renderer does internally.
+        let top_left = self.renderer.layout_pos(self.pos);
+        let line_height = self.renderer.layout().atlas().line_height();
+        if let Some(background) = self.background {
+            let height = self.visible_lines as i32 * line_height;
+            let rect = [
+                top_left[0] - DEFAULT_PADDING,
+                top_left[1] - DEFAULT_PADDING,
+                self.width + DEFAULT_PADDING * 2,
+                height + DEFAULT_PADDING * 2,
+            ];
+            let quad = self.renderer.layout().solid_quad(rect);
+            self.renderer.add_quads(&[quad], background);
+        }
+
+        let end = self.lines.len() - self.scroll;
+        let start = end.saturating_sub(self.visible_lines);
+        let mut y = top_left[1];
+        for &(level, ref line) in self.lines.iter().skip(start).take(end - start) {
+            let quads = self.renderer.layout().line_quads(line, [top_left[0], y]);
+            self.renderer.add_quads(&quads, self.colors[level.index()]);
+            y += line_height;
+        }
+        self.renderer.draw(encoder, target)
+    }
+
+    fn trim(&mut self) {
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+        let max_scroll = self.lines.len().saturating_sub(self.visible_lines);
+        self.scroll = self.scroll.min(max_scroll);
+    }
+}
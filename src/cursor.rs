@@ -0,0 +1,104 @@
+//! Cursor adding lines of text one after another, see `Renderer::cursor`.
+
+use std::fmt;
+use gfx::{Factory, Resources};
+use super::{Color, Origin, Renderer};
+
+/// Position where the next text is added to the renderer, moved past every
+/// added text and to the next line by the line height of the font. Text is
+/// added with the default color of the renderer unless changed, see
+/// `Renderer::set_default_color`.
+///
+/// The cursor implements `fmt::Write`, so text may be formatted right into
+/// it with `write!` and `writeln!`.
+///
+/// # Examples
+///
+/// ```ignore
+/// use std::fmt::Write;
+///
+/// let mut c = text.cursor([10, 10]);
+/// c.println("Debug");
+/// writeln!(c, "fps: {:.0}", fps).unwrap();
+/// c.print("pos: ");
+/// c.println(&format!("{:?}", pos));
+/// ```
+pub struct TextCursor<'a, R: Resources + 'a, F: Factory<R> + 'a> {
+    renderer: &'a mut Renderer<R, F>,
+    // Start of the current line.
+    line_start: [i32; 2],
+    // Distance from the line start to the pen.
+    advance: i32,
+    color: [f32; 4],
+}
+
+impl<'a, R: Resources, F: Factory<R>> TextCursor<'a, R, F> {
+    pub(crate) fn new(renderer: &'a mut Renderer<R, F>, pos: [i32; 2]) -> TextCursor<'a, R, F> {
+        let color = renderer.default_color;
+        TextCursor {
+            renderer: renderer,
+            line_start: pos,
+            advance: 0,
+            color: color,
+        }
+    }
+
+    /// Change color of the text added after this call.
+    pub fn set_color<C: Into<Color>>(&mut self, color: C) {
+        self.color = color.into().0;
+    }
+
+    /// Return position the next text is added at.
+    pub fn position(&self) -> [i32; 2] {
+        [self.line_start[0] + self.advance, self.line_start[1]]
+    }
+
+    /// Add the text at the cursor and move the cursor past it. Line breaks
+    /// in the text move the cursor to the start of the next line.
+    pub fn print(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(line) = lines.next() {
+            self.print_line(line);
+        }
+        for line in lines {
+            self.newline();
+            self.print_line(line);
+        }
+    }
+
+    /// Add the text at the cursor and move the cursor to the start of the
+    /// next line.
+    pub fn println(&mut self, text: &str) {
+        self.print(text);
+        self.newline();
+    }
+
+    /// Move the cursor to the start of the next line.
+    pub fn newline(&mut self) {
+        let line_height = self.renderer.layout.atlas().line_height();
+        // Lines go down the screen with either origin.
+        self.line_start[1] += match self.renderer.origin {
+            Origin::TopLeft => line_height,
+            Origin::BottomLeft => -line_height,
+        };
+        self.advance = 0;
+    }
+
+    fn print_line(&mut self, line: &str) {
+        if line.is_empty() {
+            return;
+        }
+        let pos = self.position();
+        self.renderer.add(line, pos, self.color);
+        // Pen advance, which unlike the measured width includes trailing
+        // spaces.
+        self.advance += self.renderer.layout.range_rect(line, [0, 0], 0..line.len())[2];
+    }
+}
+
+impl<'a, R: Resources, F: Factory<R>> fmt::Write for TextCursor<'a, R, F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.print(s);
+        Ok(())
+    }
+}
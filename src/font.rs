@@ -2,39 +2,174 @@
 //! Generates raw texture data for the given font and collects information
 //! about available font characters to map them into texture.
 
-use std::cmp::max;
-use std::iter::{repeat, FromIterator};
+use std::cmp::{max, min};
+use std::iter::{repeat_n, FromIterator};
 use std::collections::{HashMap, HashSet};
 use std::char::from_u32;
+use std::fs;
+use std::io::{self, Read, Write};
 use ::freetype as ft;
 use ::freetype::Error as FreetypeError;
 use ::freetype::Face;
+use colr;
+use msdf;
 
-#[derive(Debug)]
+/// Font glyphs packed into a single grayscale texture together with their
+/// metrics. Chars and glyphs are looked up by the number of the font face,
+/// the regular face is always the first one.
+#[derive(Clone, Debug)]
 pub struct BitmapFont {
     width: u16,
     height: u16,
-    chars: HashMap<char, BitmapChar>,
+    // Glyph index of every available char, for every face.
+    chars: Vec<HashMap<char, u32>>,
+    // Glyphs of all faces by face number and glyph index.
+    glyphs: HashMap<(usize, u32), BitmapChar>,
     image: Vec<u8>,
     font_height: u16,
+    // Distance from the top of the line to the baseline, the pixel size of
+    // the first (regular) face. It differs from the requested size if the
+    // nearest bitmap strike is selected.
+    baseline: u16,
+    // Texture coordinates of the center of the white block used to draw
+    // solid rectangles.
+    white_pixel: [f32; 2],
+    // Glyphs registered by user, shared by all faces. They are added at
+    // runtime, so they are not serialized.
+    custom: HashMap<char, BitmapChar>,
+    glow_radius: u8,
+    // Widths of the outlines stroked around the glyphs, for every face.
+    outline_widths: Vec<Vec<u8>>,
+    // Range of the multi-channel distance fields stored instead of glyph
+    // coverage, zero for coverage. The image is RGBA with distance fields.
+    distance_range: u8,
+    // Superscript and subscript metrics of the first (regular) face, if it
+    // has the OS/2 table.
+    script_metrics: Option<ScriptMetrics>,
 }
 
-#[derive(Debug)]
+/// Size and vertical offset of superscripts and subscripts recommended by
+/// the font, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScriptMetrics {
+    /// Font size of superscripts
+    pub superscript_size: i32,
+    /// Distance the baseline of superscripts is raised by
+    pub superscript_offset: i32,
+    /// Font size of subscripts
+    pub subscript_size: i32,
+    /// Distance the baseline of subscripts is lowered by
+    pub subscript_offset: i32,
+}
+
+/// Placement of a custom glyph image relative to the pen, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphMetrics {
+    /// Horizontal offset of the image from the pen
+    pub x_offset: i32,
+    /// Vertical offset of the image from the top of the line
+    pub y_offset: i32,
+    /// Horizontal pen advance
+    pub x_advance: i32,
+}
+
+/// Metrics and texture location of a single glyph, all sizes are in pixels.
+#[derive(Clone, Debug)]
 pub struct BitmapChar {
-    // Real glyph's coordinates in pixels.
+    /// Horizontal offset of the glyph bitmap from the pen.
     pub x_offset: i32,
+    /// Vertical offset of the glyph bitmap from the top of the line.
     pub y_offset: i32,
+    /// Horizontal pen advance.
     pub x_advance: i32,
+    /// Horizontal offset of the glyph bitmap from the pen on the center line
+    /// of the column, used in vertical layout.
+    pub v_x_offset: i32,
+    /// Vertical offset of the glyph bitmap from the pen in vertical layout.
+    pub v_y_offset: i32,
+    /// Vertical pen advance.
+    pub y_advance: i32,
+    /// Width of the glyph bitmap.
     pub width: i32,
+    /// Height of the glyph bitmap.
     pub height: i32,
-    // Precalculated scaled positions in texture.
+    /// Top left corner of the glyph in the texture, in texture coordinates.
     pub tex: [f32; 2],
+    /// Width of the glyph in texture coordinates.
     pub tex_width: f32,
+    /// Height of the glyph in texture coordinates.
     pub tex_height: f32,
+    /// Top left corner, width and height of the blurred copy of the glyph
+    /// extended by the glow radius on every side, in texture coordinates.
+    /// Only set if the font has glow, see `BitmapFont::add_glow`.
+    pub glow: Option<[f32; 4]>,
+    /// Texture rectangles of the stroked outlines of the glyph, extended by
+    /// the outline width on every side, one for every width of its face,
+    /// see `BitmapFont::get_outline_widths`. Empty for glyphs without an
+    /// outline.
+    pub outlines: Vec<[f32; 4]>,
+    /// Layers of the color glyph from the bottom, drawn instead of the glyph
+    /// itself. Empty for usual glyphs.
+    pub color_layers: Vec<ColorLayer>,
     // This field is used only while building the texture.
     data: Option<Vec<u8>>,
 }
 
+/// Layer of a color glyph of the OpenType `COLR` table: another glyph of the
+/// same face filled with a palette color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorLayer {
+    /// Index of the glyph drawn as the layer.
+    pub glyph: u32,
+    /// Color of the first palette, `None` if the layer is filled with the
+    /// text color.
+    pub color: Option<[f32; 4]>,
+}
+
+/// Styles synthesized from the regular glyph outlines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SyntheticStyle {
+    pub bold: bool,
+    pub oblique: bool,
+    // Width of the glyph outline stroke in pixels, drawn without fill if
+    // non-zero.
+    pub stroke: u8,
+}
+
+/// Charmap mapping chars to glyphs of the font, see
+/// `RendererBuilder::with_charmap`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Charmap {
+    /// Unicode, or Microsoft Symbol for fonts without the Unicode charmap
+    #[default]
+    Unicode,
+    /// Microsoft Symbol of symbol fonts like Wingdings, chars from U+0020 to
+    /// U+00FF also map to the glyphs of U+F020 to U+F0FF
+    MsSymbol,
+}
+
+
+/// Location of the font file data.
+#[derive(Clone, Copy, Debug)]
+pub enum FontSource<'a> {
+    Path(&'a str),
+    Data(&'a [u8]),
+}
+
+/// Font face to be placed into the texture.
+#[derive(Clone, Copy, Debug)]
+pub struct FaceInfo<'a> {
+    pub source: FontSource<'a>,
+    // Index of the face in a font collection.
+    pub index: u32,
+    pub synthetic: SyntheticStyle,
+    pub charmap: Charmap,
+    // Glyphs placed into the texture besides the ones of the chars.
+    pub glyphs: &'a [u32],
+    // Widths of the outlines stroked around the glyphs, in pixels.
+    pub outlines: &'a [u8],
+}
+
 /// Represents possible errors which may occur during the font loading.
 #[derive(Debug)]
 pub enum FontError {
@@ -44,37 +179,268 @@ pub enum FontError {
     EmptyFont,
     /// FreeType library error
     FreetypeError(FreetypeError),
+    /// Font file reading error
+    IoError(io::Error),
+    /// Texture is larger than the maximal size supported
+    AtlasTooLarge {
+        /// Width or height of the texture in pixels
+        needed: u32,
+        /// Maximal width and height of the texture in pixels
+        max: u32,
+    },
 }
 
 impl From<FreetypeError> for FontError {
     fn from(e: FreetypeError) -> FontError { FontError::FreetypeError(e) }
 }
 
+impl From<io::Error> for FontError {
+    fn from(e: io::Error) -> FontError { FontError::IoError(e) }
+}
+
 pub type FontResult = Result<BitmapFont, FontError>;
 
+/// Texture width and height limit used if none is given, supported by most
+/// devices.
+pub(crate) const DEFAULT_MAX_SIZE: u16 = 8192;
+
+/// Fail if the texture dimension exceeds the maximal size.
+pub(crate) fn check_size(size: usize, max_size: u16) -> Result<(), FontError> {
+    if size > max_size as usize {
+        return Err(FontError::AtlasTooLarge { needed: size as u32, max: max_size as u32 });
+    }
+    Ok(())
+}
+
+/// Open the face at the font size with the variations applied, so users may
+/// query what the crate doesn't wrap.
+#[cfg(feature = "freetype-face")]
+pub(crate) fn open_face(info: FaceInfo, font_size: u8, variations: &[([u8; 4], f32)]) -> Result<Face, FontError> {
+    use std::rc::Rc;
+
+    // NOTE: The face holds a reference to the library, so it outlives the
+    // library handle dropped here.
+    let library = ft::Library::init()?;
+    let mut face = match info.source {
+        FontSource::Path(path) => library.new_face(path, info.index as isize)?,
+        FontSource::Data(data) => library.new_memory_face(Rc::new(data.into()), info.index as isize)?,
+    };
+    BitmapFont::set_variations(&library, &mut face, variations)?;
+    BitmapFont::select_charmap(&mut face, info.charmap)?;
+    BitmapFont::set_size(&mut face, font_size)?;
+    Ok(face)
+}
+
+/// Find indices of the glyphs by their names in the face, `None` for names
+/// the face doesn't have.
+pub(crate) fn glyph_indices(info: FaceInfo, names: &[String]) -> Result<Vec<Option<u32>>, FontError> {
+    use std::ffi::CString;
+    use std::rc::Rc;
+
+    let library = ft::Library::init()?;
+    let mut face = match info.source {
+        FontSource::Path(path) => library.new_face(path, info.index as isize)?,
+        FontSource::Data(data) => library.new_memory_face(Rc::new(data.into()), info.index as isize)?,
+    };
+    if !face.has_glyph_names() {
+        return Ok(vec![None; names.len()]);
+    }
+    Ok(names.iter().map(|name| {
+        let name = match CString::new(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => return None,
+        };
+        // NOTE: FreeType doesn't modify the name.
+        let index = unsafe { ft::ffi::FT_Get_Name_Index(face.raw_mut(), name.as_ptr() as *mut _) };
+        // Index 0 is the missing glyph, also returned for unknown names.
+        match index {
+            0 => None,
+            index => Some(index),
+        }
+    }).collect())
+}
+
+// Opened face with the glyphs to place into the texture.
+struct FaceGlyphs<'a> {
+    face: Face,
+    synthetic: SyntheticStyle,
+    // Glyphs placed besides the ones of the chars.
+    glyphs: &'a [u32],
+    outlines: &'a [u8],
+    // Layers of the color glyphs by the glyph index.
+    color_glyphs: HashMap<u32, Vec<ColorLayer>>,
+}
+
 impl BitmapFont {
+    /// Build the font texture from the font file, using all chars of the font
+    /// if `chars` is not set.
     pub fn from_path(path: &str, font_size: u8, chars: Option<&[char]>) -> FontResult {
-        let library = ft::Library::init()?;
-        let face = library.new_face(path, 0)?;
-        Self::new(face, font_size, chars)
+        Self::from_faces(&[FaceInfo {
+            source: FontSource::Path(path),
+            index: 0,
+            synthetic: SyntheticStyle::default(),
+            charmap: Charmap::default(),
+            glyphs: &[],
+            outlines: &[],
+        }], font_size, chars, &[], 0, DEFAULT_MAX_SIZE)
     }
 
+    /// Build the font texture from the font file data, see `from_path`.
     pub fn from_bytes(data: &[u8], font_size: u8, chars: Option<&[char]>) -> FontResult {
+        Self::from_faces(&[FaceInfo {
+            source: FontSource::Data(data),
+            index: 0,
+            synthetic: SyntheticStyle::default(),
+            charmap: Charmap::default(),
+            glyphs: &[],
+            outlines: &[],
+        }], font_size, chars, &[], 0, DEFAULT_MAX_SIZE)
+    }
+
+    /// Build the font texture with glyphs of all given faces, they are
+    /// numbered in the given order. Multi-channel distance fields spanning
+    /// the range in pixels are stored instead of glyph coverage if the range
+    /// is non-zero. Glyphs are packed in a fixed order, so the same fonts
+    /// always give the same texture. Rows of glyphs are wrapped to fit the
+    /// maximal texture size, `FontError::AtlasTooLarge` is returned if they
+    /// don't fit anyway.
+    pub fn from_faces(faces: &[FaceInfo], font_size: u8, chars: Option<&[char]>,
+                      variations: &[([u8; 4], f32)], distance_range: u8, max_size: u16) -> FontResult {
         use std::rc::Rc;
 
+        // NOTE: Faces must be dropped before the library, which is done at
+        // the end of `new`.
         let library = ft::Library::init()?;
-        let face = library.new_memory_face(Rc::new(data.into()), 0)?;
-        Self::new(face, font_size, chars)
+        let mut ft_faces = Vec::with_capacity(faces.len());
+        for info in faces {
+            let mut face = match info.source {
+                FontSource::Path(path) => library.new_face(path, info.index as isize)?,
+                FontSource::Data(data) =>
+                    library.new_memory_face(Rc::new(data.into()), info.index as isize)?,
+            };
+            Self::set_variations(&library, &mut face, variations)?;
+            Self::select_charmap(&mut face, info.charmap)?;
+            let color_glyphs = match info.source {
+                FontSource::Path(path) => colr::color_glyphs(&fs::read(path)?, info.index),
+                FontSource::Data(data) => colr::color_glyphs(data, info.index),
+            };
+            ft_faces.push(FaceGlyphs {
+                face: face,
+                synthetic: info.synthetic,
+                glyphs: info.glyphs,
+                outlines: info.outlines,
+                color_glyphs: color_glyphs,
+            });
+        }
+        Self::new(&library, ft_faces, font_size, chars, distance_range, max_size)
+    }
+
+    /// Set design coordinates of the variable font axes given by their tags,
+    /// other axes are left at defaults.
+    fn set_variations(library: &ft::Library, face: &mut Face, variations: &[([u8; 4], f32)])
+                      -> Result<(), FontError> {
+        use std::{ptr, slice};
+
+        if variations.is_empty() {
+            return Ok(());
+        }
+        let tag_value = |tag: &[u8; 4]| tag.iter().fold(0, |acc, &b| acc << 8 | b as u32);
+        unsafe {
+            let mut mm_var = ptr::null_mut();
+            if ft::ffi::FT_Get_MM_Var(face.raw_mut(), &mut mm_var) != 0 {
+                // Not a variable font, nothing to set.
+                return Ok(());
+            }
+            let axes = slice::from_raw_parts((*mm_var).axis, (*mm_var).num_axis as usize);
+            // Coordinates are in 16.16 fixed point.
+            let coords: Vec<ft::ffi::FT_Fixed> = axes.iter().map(|axis| {
+                variations.iter()
+                    .find(|&(tag, _)| tag_value(tag) == axis.tag as u32)
+                    .map_or(axis.def, |&(_, value)| (value * 65536.0) as ft::ffi::FT_Fixed)
+            }).collect();
+            ft::ffi::FT_Done_MM_Var(library.raw(), mm_var);
+            let error = ft::ffi::FT_Set_Var_Design_Coordinates(
+                face.raw_mut(), coords.len() as ft::ffi::FT_UInt, coords.as_ptr());
+            if error != 0 {
+                return Err(FreetypeError::from(error).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Set pixel size of the scalable face, or select the embedded strike of
+    /// the nearest size if the face has bitmaps only, e.g. BDF or PCF fonts.
+    fn set_size(face: &mut Face, font_size: u8) -> Result<(), FontError> {
+        use std::slice;
+
+        if face.is_scalable() || !face.has_fixed_sizes() {
+            face.set_pixel_sizes(0, font_size as u32)?;
+            return Ok(());
+        }
+        let nearest = {
+            let raw = face.raw();
+            let sizes = unsafe { slice::from_raw_parts(raw.available_sizes, raw.num_fixed_sizes as usize) };
+            // Strike sizes are in 26.6 fixed point.
+            (0..sizes.len()).min_by_key(|&i| (((sizes[i].y_ppem + 32) >> 6) - font_size as ft::ffi::FT_Pos).abs())
+                .unwrap_or(0)
+        };
+        let error = unsafe { ft::ffi::FT_Select_Size(face.raw_mut(), nearest as ft::ffi::FT_Int) };
+        if error != 0 {
+            return Err(FreetypeError::from(error).into());
+        }
+        Ok(())
+    }
+
+    /// Select the charmap with the encoding. Fonts without the Unicode
+    /// charmap fall back to the symbol one, missing symbol charmap is an
+    /// error.
+    fn select_charmap(face: &mut Face, charmap: Charmap) -> Result<(), FontError> {
+        use std::slice;
+
+        let encodings: &[ft::ffi::FT_Encoding] = match charmap {
+            Charmap::Unicode => &[ft::ffi::FT_ENCODING_UNICODE, ft::ffi::FT_ENCODING_MS_SYMBOL],
+            Charmap::MsSymbol => &[ft::ffi::FT_ENCODING_MS_SYMBOL],
+        };
+        let found = {
+            let raw = face.raw();
+            let charmaps = unsafe { slice::from_raw_parts(raw.charmaps, raw.num_charmaps as usize) };
+            encodings.iter().filter_map(|&encoding| {
+                charmaps.iter().cloned().find(|&map| unsafe { (*map).encoding } == encoding)
+            }).next()
+        };
+        match found {
+            Some(map) => {
+                let error = unsafe { ft::ffi::FT_Set_Charmap(face.raw_mut(), map) };
+                if error != 0 {
+                    return Err(FreetypeError::from(error).into());
+                }
+            },
+            // Keep the charmap chosen by FreeType, if any.
+            None if charmap == Charmap::Unicode => (),
+            None => return Err(FreetypeError::InvalidCharMapHandle.into()),
+        }
+        Ok(())
+    }
+
+    // Whether the face uses the Microsoft Symbol charmap, see
+    // `Charmap::MsSymbol`.
+    fn is_symbol(face: &Face) -> bool {
+        let charmap = face.raw().charmap;
+        !charmap.is_null() && unsafe { (*charmap).encoding } == ft::ffi::FT_ENCODING_MS_SYMBOL
     }
 
     fn get_all_face_chars(face: &mut Face) -> HashSet<char> {
         let mut result = HashSet::new();
         let mut index = 0;
+        let symbol = Self::is_symbol(face);
         let face_ptr = face.raw_mut();
         unsafe {
             let mut code = ft::ffi::FT_Get_First_Char(face_ptr, &mut index);
             while index != 0 {
                 from_u32(code as u32).map(|ch| result.insert(ch));
+                if symbol && (0xF020..=0xF0FF).contains(&code) {
+                    from_u32(code as u32 - 0xF000).map(|ch| result.insert(ch));
+                }
                 code = ft::ffi::FT_Get_Next_Char(face_ptr, code, &mut index);
             }
         }
@@ -82,22 +448,10 @@ impl BitmapFont {
     }
 
     // FIXME(Kagami): Profile and optimize this function!
-    // TODO(Kagami): Limit too huge textures? We should keep it less than
-    // 8k X 8k in general.
-    // TODO(Kagami): Add bunch of asserts to check for negative values and
-    // overflows.
     /// Construct new BitMap font using provided parameters (this is general
-    /// method, called via `from_` helpers).
-    fn new(mut face: ft::Face, font_size: u8, chars: Option<&[char]>) -> FontResult {
-        let needed_chars = chars
-            .map(|sl| HashSet::from_iter(sl.iter().cloned()))
-            .unwrap_or_else(|| Self::get_all_face_chars(&mut face));
-        if needed_chars.is_empty() {
-            return Err(FontError::EmptyFont);
-        }
-
-        face.set_pixel_sizes(0, font_size as u32)?;
-
+    /// method, called via `from_faces`).
+    fn new(library: &ft::Library, faces: Vec<FaceGlyphs>, font_size: u8,
+           chars: Option<&[char]>, distance_range: u8, max_size: u16) -> FontResult {
         // FreeType representation of rendered glyph 'j':
         //
         // b_left   w
@@ -152,44 +506,227 @@ impl BitmapFont {
         // raw bitmap data. It gives us max character height and summary width
         // of all characters.
 
-        let chars_len = needed_chars.len();
-        let mut chars_info = HashMap::with_capacity(chars_len);
+        let mut face_chars = Vec::with_capacity(faces.len());
+        let mut chars_info = HashMap::new();
         let mut sum_image_width = 0;
         let mut max_ch_width = 0;
         let mut ch_box_height = 0;
+        let mut font_height = font_size as u16;
+        let mut baseline = font_size as i32;
+        let mut outline_widths = Vec::with_capacity(faces.len());
+        // Stroked outlines by glyph and the index of their width, placed
+        // after the glyphs.
+        let mut outline_images = Vec::new();
+        let stroker = library.new_stroker()?;
+        let channels = if distance_range > 0 { 4 } else { 1 };
+        let mut script_metrics = None;
 
-        // debug!("Start building the bitmap (chars: {})", chars_len);
-
-        for ch in needed_chars {
-            face.load_char(ch as usize, ft::face::LoadFlag::RENDER)?;
-            let glyph = face.glyph();
-            let bitmap = glyph.bitmap();
-            let ch_width = bitmap.width();
-            let ch_height = bitmap.rows();
-            let ch_x_offset = glyph.bitmap_left();
-            let ch_y_offset = font_size as i32 - glyph.bitmap_top();
-            let ch_x_advance = (glyph.advance().x >> 6) as i32;
-            let buffer = bitmap.buffer();
-            let ch_data = Vec::from(buffer);
-
-            chars_info.insert(ch, BitmapChar {
-                x_offset: ch_x_offset,
-                y_offset: ch_y_offset,
-                x_advance: ch_x_advance,
-                width: ch_width,
-                height: ch_height,
-                // We'll need to fix that fields later:
-                tex: [0.0, 0.0],
-                tex_width: 0.0,
-                tex_height: 0.0,
-                data: Some(ch_data),
-            });
+        for (face_number, face) in faces.into_iter().enumerate() {
+            let FaceGlyphs { mut face, synthetic, glyphs, outlines, mut color_glyphs } = face;
+            // Outlines are stroked around the coverage, distance fields may
+            // be outlined when drawn instead.
+            let outlines = if distance_range > 0 { &[][..] } else { outlines };
+            outline_widths.push(outlines.to_vec());
+            let needed_chars = chars
+                .map(|sl| HashSet::from_iter(sl.iter().cloned()))
+                .unwrap_or_else(|| Self::get_all_face_chars(&mut face));
+            if needed_chars.is_empty() {
+                return Err(FontError::EmptyFont);
+            }
+
+            // Glyphs are stored by index, so shaped text may refer them directly.
+            // Chars missing in the font are mapped to the "missing glyph" box.
+            let symbol = Self::is_symbol(&face);
+            let char_glyphs: HashMap<char, u32> = needed_chars.into_iter().map(|ch| {
+                let mut index = unsafe {
+                    ft::ffi::FT_Get_Char_Index(face.raw_mut(), ch as ft::ffi::FT_ULong)
+                };
+                if index == 0 && symbol && (' '..='\u{FF}').contains(&ch) {
+                    index = unsafe {
+                        ft::ffi::FT_Get_Char_Index(face.raw_mut(), 0xF000 | ch as ft::ffi::FT_ULong)
+                    };
+                }
+                (ch, index as u32)
+            }).collect();
+            #[allow(unused_mut)]
+            let mut needed_glyphs: HashSet<u32> = char_glyphs.values().chain(glyphs).cloned().collect();
+            face_chars.push(char_glyphs);
+            // Shaping may produce glyphs which no char maps to (e.g. ligatures or
+            // contextual forms), so keep all of them if the whole font is used.
+            #[cfg(feature = "shaping")]
+            {
+                if chars.is_none() {
+                    needed_glyphs.extend(0..face.raw().num_glyphs as u32);
+                }
+            }
+            // Color glyphs are drawn by their layers, which no char maps to.
+            color_glyphs.retain(|glyph, _| needed_glyphs.contains(glyph));
+            needed_glyphs.extend(color_glyphs.values().flat_map(|layers| layers.iter().map(|layer| layer.glyph)));
+
+            Self::set_size(&mut face, font_size)?;
+            // Line height and baseline are defined by the first (regular)
+            // face, glyphs of all faces sit on the same baseline.
+            if face_number == 0 {
+                font_height = face.size_metrics()
+                    .map_or(font_size as u16, |metrics| (metrics.height >> 6) as u16);
+                baseline = face.size_metrics().map_or(font_size as i32, |metrics| metrics.y_ppem as i32);
+                let em_size = face.em_size() as i32;
+                script_metrics = ft::tt_os2::TrueTypeOS2Table::from_face(&mut face)
+                    .filter(|_| em_size > 0)
+                    .map(|os2| {
+                        let to_px = |units: i16| units as i32 * font_size as i32 / em_size;
+                        ScriptMetrics {
+                            superscript_size: to_px(os2.y_superscript_y_size()),
+                            superscript_offset: to_px(os2.y_superscript_y_offset()),
+                            subscript_size: to_px(os2.y_subscript_y_size()),
+                            subscript_offset: to_px(os2.y_subscript_y_offset()),
+                        }
+                    });
+            }
+
+            // debug!("Start building the bitmap (glyphs: {})", needed_glyphs.len());
+
+            for index in needed_glyphs {
+                // Copy of the glyph outline to be stroked, taken before the
+                // glyph slot is rendered.
+                let mut contour = None;
+                if synthetic == SyntheticStyle::default() && distance_range == 0 && outlines.is_empty() {
+                    face.load_glyph(index, ft::face::LoadFlag::RENDER)?;
+                } else {
+                    // Transform the outline before rendering it. Embolding also
+                    // adjusts glyph metrics and advance.
+                    face.load_glyph(index, ft::face::LoadFlag::DEFAULT)?;
+                    unsafe {
+                        let slot = face.raw_mut().glyph;
+                        if synthetic.oblique {
+                            ft::ffi::FT_GlyphSlot_Oblique(slot);
+                        }
+                        if synthetic.bold {
+                            ft::ffi::FT_GlyphSlot_Embolden(slot);
+                        }
+                    }
+                    // Bitmap-only glyphs have nothing to stroke.
+                    if !outlines.is_empty() && face.glyph().outline().is_some() {
+                        contour = Some(face.glyph().get_glyph()?);
+                    }
+                    if synthetic.stroke == 0 && distance_range == 0 {
+                        face.glyph().render_glyph(ft::RenderMode::Normal)?;
+                    }
+                }
+                let glyph = face.glyph();
+                let ch_x_advance = (glyph.advance().x >> 6) as i32;
+                // FreeType synthesizes vertical metrics if the face has none.
+                let metrics = glyph.metrics();
+                let outline = glyph.outline().filter(|_| distance_range > 0);
+                let (ch_width, ch_height, ch_x_offset, ch_y_offset, ch_data) = if let Some(outline) = outline {
+                    // NOTE: Hollow glyphs are not stroked, as the field may
+                    // be stroked when drawn instead.
+                    let field = msdf::generate(&outline, distance_range);
+                    (field.width, field.height, field.left, baseline - field.top, field.data)
+                } else if distance_range > 0 {
+                    // Bitmap-only glyph, keep its coverage in all channels.
+                    glyph.render_glyph(ft::RenderMode::Normal)?;
+                    let bitmap = glyph.bitmap();
+                    (bitmap.width(), bitmap.rows(), glyph.bitmap_left(),
+                     baseline - glyph.bitmap_top(), expand_channels(&coverage(&bitmap), channels))
+                } else if synthetic.stroke == 0 || glyph.outline().is_none() {
+                    // Bitmap-only glyphs are kept filled, they have no
+                    // outline to stroke.
+                    let bitmap = glyph.bitmap();
+                    (bitmap.width(), bitmap.rows(), glyph.bitmap_left(),
+                     baseline - glyph.bitmap_top(), coverage(&bitmap))
+                } else {
+                    // Render the stroked outline alone, it's centered on the
+                    // glyph contour. Radius is in 26.6 fixed point.
+                    stroker.set(synthetic.stroke as ft::ffi::FT_Fixed * 32,
+                                ft::StrokerLineCap::Round, ft::StrokerLineJoin::Round, 0);
+                    let stroked = glyph.get_glyph()?.stroke(&stroker)?
+                        .to_bitmap(ft::RenderMode::Normal, None)?;
+                    let bitmap = stroked.bitmap();
+                    (bitmap.width(), bitmap.rows(), stroked.left(),
+                     baseline - stroked.top(), coverage(&bitmap))
+                };
 
-            sum_image_width += ch_width;
-            max_ch_width = max(max_ch_width, ch_width);
-            ch_box_height = max(ch_box_height, ch_height);
+                // Glyphs without an image (e.g. space) only advance the pen,
+                // they take no texture space and get no quads. Offsets may be
+                // negative, e.g. for glyphs reaching left of the pen.
+                let (ch_width, ch_height, ch_data) = if ch_width <= 0 || ch_height <= 0 {
+                    (0, 0, Vec::new())
+                } else {
+                    debug_assert_eq!(ch_data.len(), (ch_width * ch_height) as usize * channels);
+                    (ch_width, ch_height, ch_data)
+                };
+                // Rows are as wide as the texture and as high as the highest
+                // glyph, so a glyph larger than the texture can't be placed.
+                check_size(max(ch_width, ch_height) as usize, max_size)?;
+                if let Some(contour) = contour.filter(|_| ch_width > 0) {
+                    for (i, &width) in outlines.iter().enumerate() {
+                        // Hollow glyphs are outlined around their stroke.
+                        let radius = synthetic.stroke as ft::ffi::FT_Fixed * 32 + width as ft::ffi::FT_Fixed * 64;
+                        stroker.set(radius, ft::StrokerLineCap::Round, ft::StrokerLineJoin::Round, 0);
+                        let stroked = contour.stroke(&stroker)?.to_bitmap(ft::RenderMode::Normal, None)?;
+                        let bitmap = stroked.bitmap();
+                        // Aligned to the glyph extended by the width on every
+                        // side, like glow, rounding may cut off a pixel.
+                        let r = width as i32;
+                        let (outline_width, outline_height) = ((ch_width + 2 * r) as usize, (ch_height + 2 * r) as usize);
+                        check_size(max(outline_width, outline_height), max_size)?;
+                        let image = place_image(&coverage(&bitmap), bitmap.width() as usize, outline_width, outline_height,
+                                                [stroked.left() - ch_x_offset + r,
+                                                 baseline - stroked.top() - ch_y_offset + r]);
+                        outline_images.push(((face_number, index, i), outline_width, outline_height, image));
+                    }
+                }
+                chars_info.insert((face_number, index), BitmapChar {
+                    x_offset: ch_x_offset,
+                    y_offset: ch_y_offset,
+                    x_advance: ch_x_advance,
+                    v_x_offset: (metrics.vertBearingX >> 6) as i32,
+                    v_y_offset: (metrics.vertBearingY >> 6) as i32,
+                    y_advance: (metrics.vertAdvance >> 6) as i32,
+                    width: ch_width,
+                    height: ch_height,
+                    // We'll need to fix that fields later:
+                    tex: [0.0, 0.0],
+                    tex_width: 0.0,
+                    tex_height: 0.0,
+                    glow: None,
+                    outlines: Vec::new(),
+                    color_layers: color_glyphs.remove(&index).unwrap_or_default(),
+                    data: Some(ch_data),
+                });
+
+                sum_image_width += ch_width;
+                max_ch_width = max(max_ch_width, ch_width);
+                ch_box_height = max(ch_box_height, ch_height);
+            }
         }
 
+        // Reserve a white block for solid rectangles, placed like a glyph of
+        // a nonexistent face. It's 3 pixels wide, so linear filtering of its
+        // center isn't affected by the neighbours.
+        let white_key = (face_chars.len(), 0);
+        chars_info.insert(white_key, BitmapChar {
+            x_offset: 0,
+            y_offset: 0,
+            x_advance: 0,
+            v_x_offset: 0,
+            v_y_offset: 0,
+            y_advance: 0,
+            width: 3,
+            height: 3,
+            tex: [0.0, 0.0],
+            tex_width: 0.0,
+            tex_height: 0.0,
+            glow: None,
+            outlines: Vec::new(),
+            color_layers: Vec::new(),
+            data: Some(vec![255; 9 * channels]),
+        });
+        sum_image_width += 3;
+        max_ch_width = max(max_ch_width, 3);
+        ch_box_height = max(ch_box_height, 3);
+
         // In second pass we map character boxes with varying width onto the
         // fixed quad texture image and build the final texture image.
         //
@@ -202,8 +739,8 @@ impl BitmapFont {
 
         let ideal_image_size = sum_image_width * ch_box_height;
         let ideal_image_width = (ideal_image_size as f32).sqrt() as i32;
-        let image_width = max(max_ch_width, ideal_image_width);
-        let assumed_size = ideal_image_size as f32 * 1.5;
+        let image_width = max(max_ch_width, min(ideal_image_width, max_size as i32));
+        let assumed_size = ideal_image_size as f32 * 1.5 * channels as f32;
         let assumed_ch_in_row = image_width as f32 / max_ch_width as f32;
         let mut image = Vec::with_capacity(assumed_size as usize);
         let mut chars_row = Vec::with_capacity(assumed_ch_in_row as usize);
@@ -224,28 +761,35 @@ impl BitmapFont {
             // |       |  x      |   |
             // +-------+---------+---+
             //                     ^--- image_width - width_ch_i - width_ch_j
+            // Pixels take several bytes with distance fields.
+            let channels = channels as i32;
             for i in 0..ch_box_height {
                 let mut x = 0;
                 for &(width, height, ref data) in chars_row {
+                   let row_len = width * channels;
                    if i >= height {
-                       image.extend(repeat(0).take(width as usize));
+                       image.extend(repeat_n(0, row_len as usize));
                    } else {
-                       let skip = i * width;
-                       debug_assert!(data.len() >= (skip + width) as usize);
-                       let line = data.iter().skip(skip as usize).take(width as usize);
+                       let skip = i * row_len;
+                       debug_assert!(data.len() >= (skip + row_len) as usize);
+                       let line = data.iter().skip(skip as usize).take(row_len as usize);
                        image.extend(line.cloned());
                    };
                    x += width;
                 }
                 let cols_to_fill = image_width - x;
-                image.extend(repeat(0).take(cols_to_fill as usize));
+                image.extend(repeat_n(0, (cols_to_fill * channels) as usize));
             }
         };
 
         // debug!("Placing chars onto a plane");
 
-        // Hashmap doesn't preserve the order but we don't need it anyway.
-        for (_, ch_info) in chars_info.iter_mut() {
+        // Place glyphs ordered by face and index, so the atlas is the same on
+        // every run.
+        let mut keys: Vec<_> = chars_info.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let ch_info = chars_info.get_mut(&key).unwrap();
             if cursor_x + ch_info.width > image_width {
                 dump_row(&mut image, &chars_row);
                 chars_row.clear();
@@ -259,6 +803,7 @@ impl BitmapFont {
         }
         dump_row(&mut image, &chars_row);
         image_height += ch_box_height;
+        check_size(max(image_width, image_height) as usize, max_size)?;
 
         // Finally, we just precalculate some fields to make it easier to use
         // our font.
@@ -273,33 +818,864 @@ impl BitmapFont {
         // info!("Image width: {}, image height: {}, total size: {}",
         //     image_width, image_height, image.len());
 
-        Ok(BitmapFont {
+        let white = chars_info.remove(&white_key).unwrap();
+        let white_pixel = [
+            white.tex[0] + white.tex_width / 2.0,
+            white.tex[1] + white.tex_height / 2.0,
+        ];
+
+        let mut font = BitmapFont {
             width: image_width as u16,
             height: image_height as u16,
-            chars: chars_info,
+            chars: face_chars,
+            glyphs: chars_info,
             image: image,
-            font_height: (face.size_metrics().unwrap().height >> 6) as u16,
-        })
+            font_height: font_height,
+            baseline: baseline as u16,
+            white_pixel: white_pixel,
+            custom: HashMap::new(),
+            glow_radius: 0,
+            outline_widths: outline_widths,
+            distance_range: distance_range,
+            script_metrics: script_metrics,
+        };
+        if !outline_images.is_empty() {
+            let (_, placed) = font.append_images(outline_images);
+            for ((face, index, i), rect) in placed {
+                let outlines = &mut font.glyphs.get_mut(&(face, index)).unwrap().outlines;
+                if outlines.len() <= i {
+                    outlines.resize(i + 1, [0.0; 4]);
+                }
+                outlines[i] = rect;
+            }
+            check_size(max(font.width, font.height) as usize, max_size)?;
+        }
+        Ok(font)
+    }
+
+    /// Add a custom glyph for the char to the texture, replacing the font's
+    /// one in all faces. The image is given as rows of 8-bit coverage values
+    /// from the top. The texture grows to fit the image, so texture
+    /// coordinates of all glyphs change; the scale they were multiplied by
+    /// is returned. With distance fields, coverage is stored as the distance
+    /// in all channels, which keeps the shape but not the sharpness.
+    pub fn add_glyph(&mut self, ch: char, width: u16, height: u16, data: &[u8], metrics: GlyphMetrics) -> [f32; 2] {
+        assert_eq!(data.len(), width as usize * height as usize);
+        let data = expand_channels(data, self.channels());
+
+        // NOTE: The image is placed into a new row at the bottom, which is
+        // wasteful for many glyphs, but icons are usually few.
+        let old_height = self.height as usize;
+        let scale = self.extend_image(width as usize, &data);
+        let (new_width, new_height) = (self.width as usize, self.height as usize);
+        self.custom.insert(ch, BitmapChar {
+            x_offset: metrics.x_offset,
+            y_offset: metrics.y_offset,
+            x_advance: metrics.x_advance,
+            v_x_offset: -(width as i32) / 2,
+            v_y_offset: 0,
+            y_advance: height as i32,
+            width: width as i32,
+            height: height as i32,
+            tex: [0.0, old_height as f32 / new_height as f32],
+            tex_width: width as f32 / new_width as f32,
+            tex_height: height as f32 / new_height as f32,
+            glow: None,
+            outlines: Vec::new(),
+            color_layers: Vec::new(),
+            data: None,
+        });
+        scale
+    }
+
+    /// Add blurred copies of all glyphs extended by the radius on every
+    /// side to the texture, which are drawn behind the text to make it glow.
+    /// Returns the scale texture coordinates of the glyphs were multiplied
+    /// by, like `add_glyph`. Custom glyphs and fonts with distance fields
+    /// don't glow.
+    pub fn add_glow(&mut self, radius: u8) -> [f32; 2] {
+        if self.distance_range > 0 {
+            return [1.0, 1.0];
+        }
+        let r = radius as usize;
+        let (atlas_width, atlas_height) = (self.width as usize, self.height as usize);
+        // Blur every glyph separately and pack them into rows of the atlas
+        // width, the padding keeps neighbours from bleeding into each other.
+        let mut blurred = Vec::with_capacity(self.glyphs.len());
+        for (&key, ch_info) in &self.glyphs {
+            let (width, height) = (ch_info.width as usize, ch_info.height as usize);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let x = (ch_info.tex[0] * atlas_width as f32).round() as usize;
+            let y = (ch_info.tex[1] * atlas_height as f32).round() as usize;
+            let (glow_width, glow_height) = (width + 2 * r, height + 2 * r);
+            let mut glow = vec![0; glow_width * glow_height];
+            for row in 0..height {
+                let src = (y + row) * atlas_width + x;
+                let dst = (row + r) * glow_width + r;
+                glow[dst..dst + width].copy_from_slice(&self.image[src..src + width]);
+            }
+            blur(&mut glow, glow_width, glow_height, r);
+            blurred.push((key, glow_width, glow_height, glow));
+        }
+
+        let (scale, placed) = self.append_images(blurred);
+        for (key, rect) in placed {
+            if let Some(ch_info) = self.glyphs.get_mut(&key) {
+                ch_info.glow = Some(rect);
+            }
+        }
+        self.glow_radius = radius;
+        scale
+    }
+
+    // Pack the coverage images given by their key, width and height into
+    // rows of the texture width and append them to the bottom of the
+    // texture. Returns the scale of the old texture coordinates, like
+    // `extend_image`, and the texture rectangle of every image.
+    fn append_images<K: Ord>(&mut self, mut images: Vec<(K, usize, usize, Vec<u8>)>) -> ([f32; 2], Vec<(K, [f32; 4])>) {
+        let (atlas_width, atlas_height) = (self.width as usize, self.height as usize);
+        // Tall images first so rows are filled evenly, ties ordered by key
+        // so the atlas is reproducible.
+        images.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+
+        let block_width = images.iter().map(|image| image.1).max().map_or(atlas_width, |w| max(w, atlas_width));
+        let mut block = Vec::new();
+        let mut placed = Vec::with_capacity(images.len());
+        let (mut cursor_x, mut row_y, mut row_height) = (0, 0, 0);
+        for (key, width, height, image) in images {
+            if cursor_x + width > block_width {
+                cursor_x = 0;
+                row_y += row_height;
+                row_height = 0;
+            }
+            if row_height < height {
+                row_height = height;
+                block.resize((row_y + row_height) * block_width, 0);
+            }
+            for (row, data) in image.chunks(width).enumerate() {
+                let dst = (row_y + row) * block_width + cursor_x;
+                block[dst..dst + width].copy_from_slice(data);
+            }
+            placed.push((key, cursor_x, row_y, width, height));
+            cursor_x += width;
+        }
+
+        let scale = self.extend_image(block_width, &block);
+        let (new_width, new_height) = (self.width as f32, self.height as f32);
+        let placed = placed.into_iter().map(|(key, x, y, width, height)| (key, [
+            x as f32 / new_width,
+            (atlas_height + y) as f32 / new_height,
+            width as f32 / new_width,
+            height as f32 / new_height,
+        ])).collect();
+        (scale, placed)
+    }
+
+    /// Repack all glyph images (including glows and custom glyphs) into a
+    /// new texture without unused space, e.g. left by custom glyphs which
+    /// were replaced. Returns old and new texture rectangles (left, top,
+    /// width, height) of every image; solid rectangles drawn with the white
+    /// pixel are included with zero size.
+    pub fn compact(&mut self) -> Vec<([f32; 4], [f32; 4])> {
+        let channels = self.channels();
+        let (atlas_width, atlas_height) = (self.width as f32, self.height as f32);
+        let to_px = |rect: [f32; 4]| [
+            (rect[0] * atlas_width).round() as usize,
+            (rect[1] * atlas_height).round() as usize,
+            (rect[2] * atlas_width).round() as usize,
+            (rect[3] * atlas_height).round() as usize,
+        ];
+        let white = [self.white_pixel[0], self.white_pixel[1], 0.0, 0.0];
+        // The white block is 3 pixels wide with the white pixel in the center.
+        let white_px = [
+            (white[0] * atlas_width - 1.5).round() as usize,
+            (white[1] * atlas_height - 1.5).round() as usize,
+            3,
+            3,
+        ];
+        let mut rects = vec![(white, white_px)];
+        for ch_info in self.glyphs.values().chain(self.custom.values()) {
+            let rect = [ch_info.tex[0], ch_info.tex[1], ch_info.tex_width, ch_info.tex_height];
+            let pos = to_px(rect);
+            rects.push((rect, [pos[0], pos[1], ch_info.width as usize, ch_info.height as usize]));
+            for &rect in ch_info.glow.iter().chain(&ch_info.outlines) {
+                rects.push((rect, to_px(rect)));
+            }
+        }
+        // Glyphs without images may share the rectangle.
+        let mut seen = HashSet::new();
+        rects.retain(|rect| seen.insert(rect_bits(rect.0)));
+        // Tall images first so rows are filled evenly, like in `add_glow`,
+        // ties keep their order in the old atlas.
+        rects.sort_by(|a, b| (b.1[3], b.1[2], a.1[1], a.1[0]).cmp(&(a.1[3], a.1[2], b.1[1], b.1[0])));
+
+        let area: usize = rects.iter().map(|rect| rect.1[2] * rect.1[3]).sum();
+        let widest = rects.iter().map(|rect| rect.1[2]).max().unwrap_or(0);
+        let width = max(widest, (area as f32).sqrt().ceil() as usize);
+        let mut image = Vec::new();
+        let mut placed = Vec::with_capacity(rects.len());
+        let (mut cursor_x, mut row_y, mut row_height) = (0, 0, 0);
+        for (rect, px) in rects {
+            if cursor_x + px[2] > width {
+                cursor_x = 0;
+                row_y += row_height;
+                row_height = 0;
+            }
+            if row_height < px[3] {
+                row_height = px[3];
+                image.resize((row_y + row_height) * width * channels, 0);
+            }
+            for row in 0..px[3] {
+                let src = ((px[1] + row) * self.width as usize + px[0]) * channels;
+                let dst = ((row_y + row) * width + cursor_x) * channels;
+                let len = px[2] * channels;
+                image[dst..dst + len].copy_from_slice(&self.image[src..src + len]);
+            }
+            placed.push((rect, [cursor_x, row_y, px[2], px[3]]));
+            cursor_x += px[2];
+        }
+        let height = max(row_y + row_height, 1);
+        image.resize(width * height * channels, 0);
+
+        let (new_width, new_height) = (width as f32, height as f32);
+        let mut moved: Vec<([f32; 4], [f32; 4])> = placed.into_iter().map(|(rect, px)| {
+            (rect, [px[0] as f32 / new_width, px[1] as f32 / new_height,
+                    px[2] as f32 / new_width, px[3] as f32 / new_height])
+        }).collect();
+        let lookup: HashMap<[u32; 4], [f32; 4]> = moved.iter()
+            .map(|&(old, new)| (rect_bits(old), new))
+            .collect();
+        for ch_info in self.glyphs.values_mut().chain(self.custom.values_mut()) {
+            let rect = lookup[&rect_bits([ch_info.tex[0], ch_info.tex[1], ch_info.tex_width, ch_info.tex_height])];
+            ch_info.tex = [rect[0], rect[1]];
+            ch_info.tex_width = rect[2];
+            ch_info.tex_height = rect[3];
+            for rect in ch_info.glow.iter_mut().chain(ch_info.outlines.iter_mut()) {
+                *rect = lookup[&rect_bits(*rect)];
+            }
+        }
+        let block = lookup[&rect_bits(white)];
+        self.white_pixel = [block[0] + block[2] / 2.0, block[1] + block[3] / 2.0];
+        self.image = image;
+        self.width = width as u16;
+        self.height = height as u16;
+        // Solid quads refer to the white pixel rather than to the block.
+        for pair in &mut moved {
+            if pair.0 == white {
+                pair.1 = [self.white_pixel[0], self.white_pixel[1], 0.0, 0.0];
+            }
+        }
+        moved
     }
 
+    /// Return radius of the glow added with `add_glow`, zero without glow.
+    pub fn get_glow_radius(&self) -> u8 {
+        self.glow_radius
+    }
+
+    /// Return widths of the outlines stroked around the glyphs of the face,
+    /// in the order of `BitmapChar::outlines`.
+    pub fn get_outline_widths(&self, face: usize) -> &[u8] {
+        self.outline_widths.get(face).map_or(&[], |widths| &widths[..])
+    }
+
+    // Append the image given as rows of the width to the bottom of the
+    // texture, widening it if needed. Texture coordinates of all glyphs are
+    // adjusted, the scale they were multiplied by is returned.
+    fn extend_image(&mut self, width: usize, data: &[u8]) -> [f32; 2] {
+        let channels = self.channels();
+        let old_size = [self.width as usize, self.height as usize];
+        let new_width = max(old_size[0], width);
+        let new_height = old_size[1] + if width == 0 { 0 } else { data.len() / (width * channels) };
+        let mut image = Vec::with_capacity(new_width * new_height * channels);
+        for row in self.image.chunks(old_size[0] * channels) {
+            image.extend_from_slice(row);
+            image.extend(repeat_n(0, (new_width - old_size[0]) * channels));
+        }
+        if width > 0 {
+            for row in data.chunks(width * channels) {
+                image.extend_from_slice(row);
+                image.extend(repeat_n(0, (new_width - width) * channels));
+            }
+        }
+
+        let scale = [old_size[0] as f32 / new_width as f32, old_size[1] as f32 / new_height as f32];
+        for ch_info in self.glyphs.values_mut().chain(self.custom.values_mut()) {
+            ch_info.tex = [ch_info.tex[0] * scale[0], ch_info.tex[1] * scale[1]];
+            ch_info.tex_width *= scale[0];
+            ch_info.tex_height *= scale[1];
+            let rects = ch_info.glow.iter_mut().chain(ch_info.outlines.iter_mut());
+            for rect in rects {
+                *rect = [rect[0] * scale[0], rect[1] * scale[1], rect[2] * scale[0], rect[3] * scale[1]];
+            }
+        }
+        self.white_pixel = [self.white_pixel[0] * scale[0], self.white_pixel[1] * scale[1]];
+        self.image = image;
+        self.width = new_width as u16;
+        self.height = new_height as u16;
+        scale
+    }
+
+    /// Find custom glyph of the char, see `add_glyph`.
+    pub fn find_custom(&self, ch: char) -> Option<&BitmapChar> {
+        self.custom.get(&ch)
+    }
+
+    /// Return superscript and subscript metrics of the regular face, if the
+    /// font has them.
+    pub fn get_script_metrics(&self) -> Option<ScriptMetrics> {
+        self.script_metrics
+    }
+
+    /// Return width of the texture.
     pub fn get_width(&self) -> u16 {
         self.width
     }
 
+    /// Return height of the texture.
     pub fn get_height(&self) -> u16 {
         self.height
     }
 
-    /// Return 8-bit texture raw data (grayscale).
+    /// Return 8-bit texture raw data, grayscale or RGBA with distance fields.
     pub fn get_image(&self) -> &[u8] {
         &self.image
     }
 
+    /// Return range of the distance fields in pixels, zero if the texture
+    /// holds glyph coverage.
+    pub fn get_distance_range(&self) -> u8 {
+        self.distance_range
+    }
+
+    fn channels(&self) -> usize {
+        if self.distance_range > 0 { 4 } else { 1 }
+    }
+
+    /// Return fraction of the texture area covered by glyph bitmaps.
+    pub fn get_occupancy(&self) -> f32 {
+        let used: i32 = self.glyphs.values().map(|ch| ch.width * ch.height).sum();
+        used as f32 / (self.width as f32 * self.height as f32)
+    }
+
+    /// Return distance between the lines of text.
     pub fn get_font_height(&self) -> u16 {
         self.font_height
     }
 
-    pub fn find_char(&self, ch: char) -> Option<&BitmapChar> {
-        self.chars.get(&ch)
+    /// Return distance from the top of the line to the baseline, which is
+    /// the pixel size of the regular face.
+    pub fn get_baseline(&self) -> u16 {
+        self.baseline
+    }
+
+    /// Return texture coordinates of a white pixel, e.g. to draw solid
+    /// rectangles with the same texture as glyphs.
+    pub fn get_white_pixel(&self) -> [f32; 2] {
+        self.white_pixel
+    }
+
+    /// Return number of the font faces.
+    pub fn get_face_count(&self) -> usize {
+        self.chars.len()
+    }
+
+    /// Find glyph of the char in the given face.
+    pub fn find_char(&self, face: usize, ch: char) -> Option<&BitmapChar> {
+        if let Some(ch_info) = self.custom.get(&ch) {
+            return Some(ch_info);
+        }
+        self.chars[face].get(&ch).and_then(|&index| self.glyphs.get(&(face, index)))
+    }
+
+    /// Find glyph by its index in the given face. Only the glyphs of the
+    /// chars placed into the texture are available, and all glyphs of the
+    /// font if it was built with all chars and the `shaping` feature.
+    pub fn find_glyph(&self, face: usize, index: u32) -> Option<&BitmapChar> {
+        self.glyphs.get(&(face, index))
+    }
+
+    /// Serialize the texture and glyph metrics, see `read_from`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(SERIALIZED_MAGIC)?;
+        write_u32(w, self.width as u32)?;
+        write_u32(w, self.height as u32)?;
+        write_u32(w, self.font_height as u32)?;
+        write_u32(w, self.baseline as u32)?;
+        write_u32(w, self.white_pixel[0].to_bits())?;
+        write_u32(w, self.white_pixel[1].to_bits())?;
+        write_u32(w, self.glow_radius as u32)?;
+        write_u32(w, self.distance_range as u32)?;
+        write_u32(w, self.script_metrics.is_some() as u32)?;
+        if let Some(metrics) = self.script_metrics {
+            for &value in &[metrics.superscript_size, metrics.superscript_offset,
+                            metrics.subscript_size, metrics.subscript_offset] {
+                write_u32(w, value as u32)?;
+            }
+        }
+        write_u32(w, self.chars.len() as u32)?;
+        for face in 0..self.chars.len() {
+            let widths = self.get_outline_widths(face);
+            write_u32(w, widths.len() as u32)?;
+            for &width in widths {
+                write_u32(w, width as u32)?;
+            }
+        }
+        // Entries are sorted, so the same font is always written the same.
+        for chars in &self.chars {
+            write_u32(w, chars.len() as u32)?;
+            let mut chars: Vec<_> = chars.iter().collect();
+            chars.sort();
+            for (&ch, &index) in chars {
+                write_u32(w, ch as u32)?;
+                write_u32(w, index)?;
+            }
+        }
+        write_u32(w, self.glyphs.len() as u32)?;
+        let mut glyphs: Vec<_> = self.glyphs.iter().collect();
+        glyphs.sort_by_key(|&(&key, _)| key);
+        for (&(face, index), ch) in glyphs {
+            write_u32(w, face as u32)?;
+            write_u32(w, index)?;
+            for &value in &[ch.x_offset, ch.y_offset, ch.x_advance,
+                            ch.v_x_offset, ch.v_y_offset, ch.y_advance,
+                            ch.width, ch.height] {
+                write_u32(w, value as u32)?;
+            }
+            for &value in &[ch.tex[0], ch.tex[1], ch.tex_width, ch.tex_height] {
+                write_u32(w, value.to_bits())?;
+            }
+            write_u32(w, ch.glow.is_some() as u32)?;
+            for &value in ch.glow.iter().flat_map(|glow| glow.iter()) {
+                write_u32(w, value.to_bits())?;
+            }
+            write_u32(w, ch.outlines.len() as u32)?;
+            for &value in ch.outlines.iter().flat_map(|outline| outline.iter()) {
+                write_u32(w, value.to_bits())?;
+            }
+            write_u32(w, ch.color_layers.len() as u32)?;
+            for layer in &ch.color_layers {
+                write_u32(w, layer.glyph)?;
+                write_u32(w, layer.color.is_some() as u32)?;
+                for &value in layer.color.iter().flat_map(|color| color.iter()) {
+                    write_u32(w, value.to_bits())?;
+                }
+            }
+        }
+        write_u32(w, self.image.len() as u32)?;
+        w.write_all(&self.image)
+    }
+
+    /// Deserialize the font written by `write_to`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<BitmapFont> {
+        let mut magic = [0; 8];
+        read_bytes(r, &mut magic)?;
+        if &magic != SERIALIZED_MAGIC {
+            return Err(invalid_data());
+        }
+        let width = read_u32(r)? as u16;
+        let height = read_u32(r)? as u16;
+        let font_height = read_u32(r)? as u16;
+        let baseline = read_u32(r)? as u16;
+        let white_pixel = [f32::from_bits(read_u32(r)?), f32::from_bits(read_u32(r)?)];
+        let glow_radius = read_u32(r)? as u8;
+        let distance_range = read_u32(r)? as u8;
+        let script_metrics = match read_u32(r)? {
+            0 => None,
+            _ => Some(ScriptMetrics {
+                superscript_size: read_u32(r)? as i32,
+                superscript_offset: read_u32(r)? as i32,
+                subscript_size: read_u32(r)? as i32,
+                subscript_offset: read_u32(r)? as i32,
+            }),
+        };
+        let num_faces = read_u32(r)?;
+        let mut outline_widths = Vec::new();
+        for _ in 0..num_faces {
+            let mut widths = Vec::new();
+            for _ in 0..read_u32(r)? {
+                widths.push(read_u32(r)? as u8);
+            }
+            outline_widths.push(widths);
+        }
+        let mut face_chars = Vec::new();
+        for _ in 0..num_faces {
+            let mut chars = HashMap::new();
+            for _ in 0..read_u32(r)? {
+                let ch = from_u32(read_u32(r)?).ok_or_else(invalid_data)?;
+                chars.insert(ch, read_u32(r)?);
+            }
+            face_chars.push(chars);
+        }
+        let mut glyphs = HashMap::new();
+        for _ in 0..read_u32(r)? {
+            let key = (read_u32(r)? as usize, read_u32(r)?);
+            let mut ints = [0; 8];
+            for value in &mut ints {
+                *value = read_u32(r)? as i32;
+            }
+            let mut floats = [0.0; 4];
+            for value in &mut floats {
+                *value = f32::from_bits(read_u32(r)?);
+            }
+            let glow = match read_u32(r)? {
+                0 => None,
+                _ => {
+                    let mut rect = [0.0; 4];
+                    for value in &mut rect {
+                        *value = f32::from_bits(read_u32(r)?);
+                    }
+                    Some(rect)
+                },
+            };
+            let mut outlines = Vec::new();
+            for _ in 0..read_u32(r)? {
+                let mut rect = [0.0; 4];
+                for value in &mut rect {
+                    *value = f32::from_bits(read_u32(r)?);
+                }
+                outlines.push(rect);
+            }
+            let mut color_layers = Vec::new();
+            for _ in 0..read_u32(r)? {
+                let glyph = read_u32(r)?;
+                let color = match read_u32(r)? {
+                    0 => None,
+                    _ => {
+                        let mut color = [0.0; 4];
+                        for value in &mut color {
+                            *value = f32::from_bits(read_u32(r)?);
+                        }
+                        Some(color)
+                    },
+                };
+                color_layers.push(ColorLayer { glyph: glyph, color: color });
+            }
+            glyphs.insert(key, BitmapChar {
+                x_offset: ints[0],
+                y_offset: ints[1],
+                x_advance: ints[2],
+                v_x_offset: ints[3],
+                v_y_offset: ints[4],
+                y_advance: ints[5],
+                width: ints[6],
+                height: ints[7],
+                tex: [floats[0], floats[1]],
+                tex_width: floats[2],
+                tex_height: floats[3],
+                glow: glow,
+                outlines: outlines,
+                color_layers: color_layers,
+                data: None,
+            });
+        }
+        let image_len = read_u32(r)? as usize;
+        let channels = if distance_range > 0 { 4 } else { 1 };
+        if image_len != width as usize * height as usize * channels {
+            return Err(invalid_data());
+        }
+        let mut image = vec![0; image_len];
+        read_bytes(r, &mut image)?;
+        Ok(BitmapFont {
+            width: width,
+            height: height,
+            chars: face_chars,
+            glyphs: glyphs,
+            image: image,
+            font_height: font_height,
+            baseline: baseline,
+            white_pixel: white_pixel,
+            custom: HashMap::new(),
+            glow_radius: glow_radius,
+            outline_widths: outline_widths,
+            distance_range: distance_range,
+            script_metrics: script_metrics,
+        })
+    }
+}
+
+// Bits of the rectangle coordinates, to look it up by exact value.
+pub(crate) fn rect_bits(rect: [f32; 4]) -> [u32; 4] {
+    [rect[0].to_bits(), rect[1].to_bits(), rect[2].to_bits(), rect[3].to_bits()]
+}
+
+// Bump the version on every change of the serialized format.
+const SERIALIZED_MAGIC: &'static [u8; 8] = b"GFXTXT08";
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    read_bytes(r, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Fill the buffer, truncated data is malformed like any other.
+fn read_bytes<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    r.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data(),
+        _ => e,
+    })
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed font cache")
+}
+
+// Copy the coverage image of the width into a blank image of the given size
+// at the offset, cutting off what lies outside of it.
+fn place_image(data: &[u8], width: usize, dst_width: usize, dst_height: usize, offset: [i32; 2]) -> Vec<u8> {
+    let mut image = vec![0; dst_width * dst_height];
+    if width == 0 {
+        return image;
+    }
+    for (row, line) in data.chunks(width).enumerate() {
+        let y = row as i32 + offset[1];
+        if y < 0 || y >= dst_height as i32 {
+            continue;
+        }
+        for (col, &value) in line.iter().enumerate() {
+            let x = col as i32 + offset[0];
+            if x >= 0 && x < dst_width as i32 {
+                image[y as usize * dst_width + x as usize] = value;
+            }
+        }
+    }
+    image
+}
+
+// Coverage values of the bitmap row by row without padding, glyphs of bitmap
+// fonts are usually given with one bit per pixel.
+fn coverage(bitmap: &ft::Bitmap) -> Vec<u8> {
+    let (width, rows) = (bitmap.width() as usize, bitmap.rows() as usize);
+    // Bitmaps of glyphs without an image may have no buffer at all.
+    if width == 0 || rows == 0 {
+        return Vec::new();
+    }
+    let pitch = bitmap.pitch().unsigned_abs() as usize;
+    let buffer = bitmap.buffer();
+    match bitmap.pixel_mode() {
+        Ok(ft::bitmap::PixelMode::Mono) => (0..rows).flat_map(|y| (0..width).map(move |x| {
+            if buffer[y * pitch + x / 8] & (0x80 >> (x % 8)) != 0 { 255 } else { 0 }
+        })).collect(),
+        _ => (0..rows).flat_map(|y| buffer[y * pitch..y * pitch + width].iter().cloned()).collect(),
+    }
+}
+
+// Repeat every coverage value in all channels of the pixel.
+fn expand_channels(data: &[u8], channels: usize) -> Vec<u8> {
+    data.iter().flat_map(|&value| repeat_n(value, channels)).collect()
+}
+
+// Blur the image in place with two passes of box blur in both directions,
+// together they spread every pixel by about the radius. The result is
+// brightened, as blurring thin strokes makes them faint.
+fn blur(image: &mut [u8], width: usize, height: usize, radius: usize) {
+    let box_radius = max(radius / 2, 1);
+    let mut values: Vec<u32> = image.iter().map(|&value| value as u32).collect();
+    let mut line = Vec::new();
+    for _ in 0..2 {
+        for y in 0..height {
+            line.clear();
+            line.extend((0..width).map(|x| values[y * width + x]));
+            for x in 0..width {
+                values[y * width + x] = box_average(&line, x, box_radius);
+            }
+        }
+        for x in 0..width {
+            line.clear();
+            line.extend((0..height).map(|y| values[y * width + x]));
+            for y in 0..height {
+                values[y * width + x] = box_average(&line, y, box_radius);
+            }
+        }
+    }
+    for (pixel, value) in image.iter_mut().zip(values) {
+        *pixel = min(value * 2, 255) as u8;
+    }
+}
+
+// Average of the values within the radius around the position, the values
+// outside of the line are zero.
+fn box_average(line: &[u32], pos: usize, radius: usize) -> u32 {
+    let start = pos.saturating_sub(radius);
+    let end = min(pos + radius + 1, line.len());
+    line[start..end].iter().sum::<u32>() / (2 * radius + 1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &'static [u8] = include_bytes!("../assets/NotoSans-Regular.ttf");
+    // Bitmap font with a single 8 pixel strike.
+    const BDF: &'static [u8] = b"STARTFONT 2.1\n\
+FONT -test-tiny-medium-r-normal--8-80-75-75-c-60-iso10646-1\n\
+SIZE 8 75 75\n\
+FONTBOUNDINGBOX 6 8 0 -1\n\
+STARTPROPERTIES 3\n\
+FONT_ASCENT 7\n\
+FONT_DESCENT 1\n\
+DEFAULT_CHAR 65\n\
+ENDPROPERTIES\n\
+CHARS 2\n\
+STARTCHAR A\n\
+ENCODING 65\n\
+SWIDTH 480 0\n\
+DWIDTH 6 0\n\
+BBX 5 7 0 0\n\
+BITMAP\n\
+20\n\
+50\n\
+88\n\
+88\n\
+F8\n\
+88\n\
+88\n\
+ENDCHAR\n\
+STARTCHAR space\n\
+ENCODING 32\n\
+SWIDTH 480 0\n\
+DWIDTH 6 0\n\
+BBX 1 1 0 0\n\
+BITMAP\n\
+00\n\
+ENDCHAR\n\
+ENDFONT\n";
+
+    fn build(font_size: u8, chars: &[char], max_size: u16) -> FontResult {
+        build_outlined(font_size, chars, max_size, &[])
+    }
+
+    fn build_outlined(font_size: u8, chars: &[char], max_size: u16, outlines: &[u8]) -> FontResult {
+        BitmapFont::from_faces(&[FaceInfo {
+            source: FontSource::Data(FONT),
+            index: 0,
+            synthetic: SyntheticStyle::default(),
+            charmap: Charmap::default(),
+            glyphs: &[],
+            outlines: outlines,
+        }], font_size, Some(chars), &[], 0, max_size)
+    }
+
+    #[test]
+    fn space_has_advance_but_no_image() {
+        let font = build(16, &[' ', 'a'], DEFAULT_MAX_SIZE).unwrap();
+        let space = font.find_char(0, ' ').unwrap();
+        assert_eq!((space.width, space.height), (0, 0));
+        assert_eq!((space.tex_width, space.tex_height), (0.0, 0.0));
+        assert!(space.x_advance > 0);
+    }
+
+    #[test]
+    fn negative_bitmap_left_is_kept() {
+        let font = build(16, &['j', 'J', '_'], DEFAULT_MAX_SIZE).unwrap();
+        let j = font.find_char(0, 'j').unwrap();
+        assert!(j.x_offset < 0);
+        // The whole bitmap is placed, not only the part right of the pen.
+        assert!(j.width > 0);
+        assert_eq!((j.tex_width * font.get_width() as f32).round() as i32, j.width);
+        assert!(j.tex[0] >= 0.0 && j.tex[0] + j.tex_width <= 1.0);
+    }
+
+    #[test]
+    fn glyph_larger_than_texture_fails() {
+        match build(64, &['W'], 32) {
+            Err(FontError::AtlasTooLarge { needed, max }) => {
+                assert!(needed > 32);
+                assert_eq!(max, 32);
+            },
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("glyph placed into a too small texture"),
+        }
+    }
+
+    #[test]
+    fn outlines_extend_glyphs_by_their_width() {
+        let font = build_outlined(16, &[' ', 'o'], DEFAULT_MAX_SIZE, &[1, 3]).unwrap();
+        assert_eq!(font.get_outline_widths(0), [1, 3]);
+        assert!(font.find_char(0, ' ').unwrap().outlines.is_empty());
+        let o = font.find_char(0, 'o').unwrap();
+        assert_eq!(o.outlines.len(), 2);
+        for (&rect, &width) in o.outlines.iter().zip(&[1, 3]) {
+            let size = [(rect[2] * font.get_width() as f32).round() as i32,
+                        (rect[3] * font.get_height() as f32).round() as i32];
+            assert_eq!(size, [o.width + 2 * width, o.height + 2 * width]);
+        }
+        // The stroke covers the glyph's edge, so the rim is not empty.
+        let rect = o.outlines[1];
+        let (x, y) = ((rect[0] * font.get_width() as f32).round() as usize,
+                      (rect[1] * font.get_height() as f32).round() as usize);
+        let middle_row = (y + (o.height as usize + 6) / 2) * font.get_width() as usize;
+        assert!(font.get_image()[middle_row + x + 1] > 0);
+    }
+
+    fn build_strike(font_size: u8, stroke: u8) -> FontResult {
+        BitmapFont::from_faces(&[FaceInfo {
+            source: FontSource::Data(BDF),
+            index: 0,
+            synthetic: SyntheticStyle { stroke: stroke, ..SyntheticStyle::default() },
+            charmap: Charmap::default(),
+            glyphs: &[],
+            outlines: &[1],
+        }], font_size, Some(&['A']), &[], 0, DEFAULT_MAX_SIZE)
+    }
+
+    #[test]
+    fn strike_glyphs_sit_on_its_baseline() {
+        let font = build_strike(16, 0).unwrap();
+        assert_eq!(font.get_baseline(), 8);
+        let a = font.find_char(0, 'A').unwrap();
+        // 7 pixels tall and resting on the baseline.
+        assert_eq!((a.height, a.y_offset), (7, 1));
+        // Nothing to stroke around bitmaps.
+        assert!(a.outlines.is_empty());
+    }
+
+    #[test]
+    fn hollow_strike_glyphs_stay_filled() {
+        let font = build_strike(8, 1).unwrap();
+        let a = font.find_char(0, 'A').unwrap();
+        assert_eq!((a.width, a.height), (5, 7));
+    }
+
+    #[test]
+    fn serialized_font_reads_back_the_same() {
+        let chars = ['a', 'g', 'W', ' '];
+        let font = build_outlined(16, &chars, DEFAULT_MAX_SIZE, &[2]).unwrap();
+        let mut data = Vec::new();
+        font.write_to(&mut data).unwrap();
+        let read = BitmapFont::read_from(&mut &data[..]).unwrap();
+        assert_eq!((read.get_width(), read.get_height()), (font.get_width(), font.get_height()));
+        assert_eq!(read.get_font_height(), font.get_font_height());
+        assert_eq!(read.get_baseline(), font.get_baseline());
+        assert_eq!(read.get_outline_widths(0), [2]);
+        assert_eq!(read.get_script_metrics(), font.get_script_metrics());
+        assert!(read.get_image() == font.get_image());
+        for &ch in &chars {
+            let (a, b) = (font.find_char(0, ch).unwrap(), read.find_char(0, ch).unwrap());
+            assert_eq!((a.x_offset, a.y_offset, a.x_advance), (b.x_offset, b.y_offset, b.x_advance), "{:?}", ch);
+            assert_eq!((a.v_x_offset, a.v_y_offset, a.y_advance), (b.v_x_offset, b.v_y_offset, b.y_advance), "{:?}", ch);
+            assert_eq!((a.width, a.height), (b.width, b.height), "{:?}", ch);
+            assert_eq!((a.tex, a.tex_width, a.tex_height), (b.tex, b.tex_width, b.tex_height), "{:?}", ch);
+            assert_eq!(a.outlines, b.outlines, "{:?}", ch);
+        }
+    }
+
+    #[test]
+    fn malformed_serialized_font_fails() {
+        let font = build(16, &['a'], DEFAULT_MAX_SIZE).unwrap();
+        let mut data = Vec::new();
+        font.write_to(&mut data).unwrap();
+        for &len in &[4, 20, data.len() - 1] {
+            let e = BitmapFont::read_from(&mut &data[..len]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "truncated to {} bytes", len);
+        }
+        data[0] ^= 0xFF;
+        assert_eq!(BitmapFont::read_from(&mut &data[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 }
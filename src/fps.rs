@@ -0,0 +1,122 @@
+//! Frame rate counter drawn with a renderer.
+
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::time::Instant;
+use gfx::{Factory, Resources};
+use super::{Color, HorizontalAnchor, Origin, Renderer, VerticalAnchor};
+
+const DEFAULT_FRAME_WINDOW: usize = 120;
+
+/// Frame time statistics over the last frames.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut fps = gfx_text::FpsCounter::new();
+///
+/// // In render loop:
+/// fps.tick();
+/// fps.add_to(&mut text, HorizontalAnchor::Right, VerticalAnchor::Top, [1.0, 1.0, 0.0, 1.0]);
+/// text.draw(&mut encoder, &color_output).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct FpsCounter {
+    last_tick: Option<Instant>,
+    // Durations of the last frames in milliseconds, oldest first.
+    frame_times: VecDeque<f32>,
+    window: usize,
+}
+
+impl FpsCounter {
+    /// Create a counter averaging over the last 120 frames.
+    pub fn new() -> FpsCounter {
+        FpsCounter::with_window(DEFAULT_FRAME_WINDOW)
+    }
+
+    /// Create a counter averaging over the given number of last frames.
+    pub fn with_window(window: usize) -> FpsCounter {
+        FpsCounter {
+            last_tick: None,
+            frame_times: VecDeque::with_capacity(window),
+            window: max(window, 1),
+        }
+    }
+
+    /// Mark the start of a new frame, should be called once per frame.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last_tick) = self.last_tick {
+            let elapsed = now.duration_since(last_tick);
+            let ms = elapsed.as_secs() as f32 * 1000.0 + elapsed.subsec_nanos() as f32 / 1_000_000.0;
+            if self.frame_times.len() == self.window {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(ms);
+        }
+        self.last_tick = Some(now);
+    }
+
+    /// Return minimal, average and maximal frame time in milliseconds, or
+    /// `None` before the second tick.
+    pub fn frame_times(&self) -> Option<(f32, f32, f32)> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let min = self.frame_times.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = self.frame_times.iter().cloned().fold(0.0, f32::max);
+        let avg = self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32;
+        Some((min, avg, max))
+    }
+
+    /// Return average frames per second, zero before the second tick.
+    pub fn fps(&self) -> f32 {
+        match self.frame_times() {
+            Some((_, avg, _)) if avg > 0.0 => 1000.0 / avg,
+            _ => 0.0,
+        }
+    }
+
+    /// Return statistics formatted as "FPS / ms (min/avg/max)".
+    pub fn text(&self) -> String {
+        match self.frame_times() {
+            Some((min, avg, max)) => format!("{:.0} FPS / {:.1} ms ({:.1}/{:.1}/{:.1})", self.fps(), avg, min, avg, max),
+            None => "-- FPS".to_owned(),
+        }
+    }
+
+    /// Add statistics text to the renderer at the screen edge or corner
+    /// selected by the anchors, e.g. the top right corner with
+    /// `HorizontalAnchor::Right` and `VerticalAnchor::Top`.
+    pub fn add_to<R: Resources, F: Factory<R>, C: Into<Color>>(
+        &self,
+        renderer: &mut Renderer<R, F>,
+        horizontal: HorizontalAnchor,
+        vertical: VerticalAnchor,
+        color: C,
+    ) {
+        let text = self.text();
+        let horizontal = horizontal.resolve(renderer.layout.is_rtl(&text));
+        let x = match horizontal {
+            HorizontalAnchor::Left | HorizontalAnchor::Start | HorizontalAnchor::End => 0.0,
+            HorizontalAnchor::Center => 0.5,
+            HorizontalAnchor::Right => 1.0,
+        };
+        let y = match vertical {
+            VerticalAnchor::Top => 0.0,
+            VerticalAnchor::Center | VerticalAnchor::CapHeight | VerticalAnchor::XHeight => 0.5,
+            VerticalAnchor::Bottom => 1.0,
+        };
+        let y = match renderer.origin {
+            Origin::TopLeft => y,
+            Origin::BottomLeft => 1.0 - y,
+        };
+        renderer.add_normalized(&text, [x, y], horizontal, vertical, color);
+    }
+}
+
+impl Default for FpsCounter {
+    fn default() -> FpsCounter {
+        FpsCounter::new()
+    }
+}
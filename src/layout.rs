@@ -0,0 +1,1422 @@
+//! GPU-independent text layout. Measures and wraps text and positions glyph
+//! quads using metrics of the built font atlas, so it may be used without a
+//! graphics device, e.g. on a simulation thread.
+
+use std::borrow::Cow;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::iter::repeat_n;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use font::{BitmapChar, GlyphMetrics};
+use super::{Color, ControlChars, FeatureTag, FontAtlas, HorizontalAnchor, Style, TextAlign, TextDirection, TextSpan, TextTransform, VerticalAnchor, opentype_tag};
+
+/// Glyph quad positioned in pixels relative to the top left corner of the
+/// screen or of the world anchor of the text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphQuad {
+    /// Top left corner of the quad.
+    pub pos: [i32; 2],
+    /// Width and height of the quad.
+    pub size: [i32; 2],
+    /// Top left corner, width and height of the glyph in the atlas texture,
+    /// in texture coordinates.
+    pub tex: [f32; 4],
+    /// Byte offset of the char (or of the first char of the cluster) the
+    /// glyph was made from in the laid out text.
+    pub source: usize,
+    /// Texture rectangle of the blurred copy of the glyph, if the font has
+    /// glow, see `BitmapChar::glow`.
+    pub glow: Option<[f32; 4]>,
+    /// Texture rectangle of the stroked outline of the glyph of the layout's
+    /// outline width, see `BitmapChar::outlines`.
+    pub outline: Option<[f32; 4]>,
+    /// Palette color of the color glyph layer, drawn instead of the text
+    /// color, see `BitmapChar::color_layers`.
+    pub color: Option<[f32; 4]>,
+}
+
+impl GlyphQuad {
+    // Whether the quad covers no pixels, e.g. of a space.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.size[0] <= 0 || self.size[1] <= 0
+    }
+}
+
+/// Line of text wrapped by `TextLayout::wrap_lines`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WrappedLine<'a> {
+    /// Slice of the wrapped text.
+    pub text: &'a str,
+    /// Whether the line ends a paragraph.
+    pub paragraph_end: bool,
+    /// Whether the line was broken inside a word and is followed by a
+    /// hyphen, which is not a part of the text.
+    pub hyphenated: bool,
+}
+
+/// Function returning byte offsets inside the word at which it may be
+/// hyphenated, see `TextLayout::set_hyphenator`.
+pub type Hyphenator = Box<dyn Fn(&str) -> Vec<usize> + Send + Sync>;
+
+/// Pixel format of the images rendered by `TextLayout::render_to_image`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// One byte of glyph coverage per pixel.
+    Gray,
+    /// Four bytes per pixel, the text color with alpha multiplied by the
+    /// glyph coverage.
+    Rgba,
+}
+
+// Single line of text laid out by `TextLayout::layout_line`.
+struct LineLayout {
+    // Glyph quads relative to the text origin with byte offsets of their
+    // chars in the text.
+    glyphs: Vec<GlyphQuad>,
+    // Pen position after the whole text.
+    advance: i32,
+    // Distance from the origin to the right edge of the last glyph with an
+    // image, or to the last tab stop if it follows all glyphs.
+    width: i32,
+    // Byte offset of every char or cluster moving the pen and how far it
+    // moved it, in visual order.
+    advances: Vec<(usize, i32)>,
+    // Whether the byte of the laid out text belongs to a synthesized small
+    // capital, empty if there are none.
+    small_caps: Vec<bool>,
+    // Scale of the whole line, e.g. of a superscript.
+    scale: f32,
+}
+
+impl LineLayout {
+    // Append the glyph following all previous ones, glyphs without an image
+    // only advance the pen.
+    fn push_glyph(&mut self, quad: GlyphQuad) {
+        if !quad.is_empty() {
+            self.width = quad.pos[0] + quad.size[0];
+            self.glyphs.push(quad);
+        }
+    }
+
+    // Move the pen past the char at the byte offset.
+    fn advance_pen(&mut self, source: usize, advance: i32) {
+        self.advances.push((source, advance));
+        self.advance += advance;
+    }
+
+    // Scale of the glyph of the char at the byte offset.
+    fn scale(&self, source: usize) -> f32 {
+        if self.small_caps.get(source).cloned().unwrap_or(false) { self.scale * SMALL_CAPS_SCALE } else { self.scale }
+    }
+}
+
+// Text with its case transformed for the layout.
+struct TransformedText {
+    text: String,
+    // Byte offset of the original char of every byte, and the text length
+    // at the end.
+    sources: Vec<usize>,
+    // Whether the byte belongs to a synthesized small capital.
+    small_caps: Vec<bool>,
+}
+
+// Size of synthesized small capitals relative to capitals, close to the
+// x-height of most fonts.
+const SMALL_CAPS_SCALE: f32 = 0.75;
+
+// How a char is drawn.
+enum CharGlyph<'a> {
+    // With the glyph the font (or the shaper) has for it.
+    Font,
+    // With another glyph, e.g. a custom one.
+    Replaced(&'a BitmapChar),
+    // Not drawn and takes no space.
+    Hidden,
+}
+
+// How many distinct lines of every face are kept laid out. Lines are kept
+// for two generations, so up to twice as many are actually stored.
+const LINE_CACHE_SIZE: usize = 256;
+
+// Line laid out at the origin.
+struct CachedLine {
+    quads: Vec<GlyphQuad>,
+    width: i32,
+}
+
+// Lines laid out recently, so labels repeated every frame are laid out only
+// once. When the current generation is full it replaces the previous one,
+// lines used since then survive by moving to the current generation.
+#[derive(Default)]
+struct LineCache {
+    current: HashMap<String, Arc<CachedLine>>,
+    previous: HashMap<String, Arc<CachedLine>>,
+}
+
+/// Text layout engine. Holds the font atlas and the layout options and turns
+/// text into glyph quads.
+///
+/// # Examples
+///
+/// ```ignore
+/// let atlas = gfx_text::FontBuilder::new().with_size(20).build().unwrap();
+/// let layout = gfx_text::TextLayout::new(atlas);
+/// assert_eq!(layout.measure("").0, 0);
+/// ```
+pub struct TextLayout {
+    atlas: FontAtlas,
+    direction: TextDirection,
+    style: Style,
+    features: Vec<FeatureTag>,
+    control_chars: ControlChars,
+    transform: TextTransform,
+    hyphenator: Option<Hyphenator>,
+    // Width of the glyph outlines the quads refer to, zero for none.
+    outline_width: u8,
+    // By face and outline width.
+    line_caches: Mutex<HashMap<(usize, u8), LineCache>>,
+    // Time spent laying out lines since the last `take_layout_time`, in
+    // nanoseconds.
+    layout_nanos: AtomicU64,
+}
+
+impl TextLayout {
+    /// Create a new layout with default options using the given font atlas.
+    pub fn new(atlas: FontAtlas) -> TextLayout {
+        TextLayout {
+            atlas: atlas,
+            direction: TextDirection::Auto,
+            style: Style::Regular,
+            features: Vec::new(),
+            control_chars: ControlChars::Strip,
+            transform: TextTransform::None,
+            hyphenator: None,
+            outline_width: 0,
+            line_caches: Mutex::new(HashMap::new()),
+            layout_nanos: AtomicU64::new(0),
+        }
+    }
+
+    // Used to pass the features set in the renderer builder.
+    pub(crate) fn with_feature_tags(mut self, features: Vec<FeatureTag>) -> TextLayout {
+        self.features = features;
+        self
+    }
+
+    /// Return font atlas used for the layout.
+    pub fn atlas(&self) -> &FontAtlas {
+        &self.atlas
+    }
+
+    /// Return time spent laying out text since the previous call, lines
+    /// taken from the cache are not counted. Used for `Renderer::stats`.
+    pub fn take_layout_time(&self) -> Duration {
+        Duration::from_nanos(self.layout_nanos.swap(0, Ordering::Relaxed))
+    }
+
+    /// Return current base direction of the text.
+    pub fn direction(&self) -> TextDirection {
+        self.direction
+    }
+
+    /// Return whether the text goes right-to-left, either as set with
+    /// `set_direction` or detected from its first strong character.
+    pub fn is_rtl(&self, text: &str) -> bool {
+        use unicode_bidi::BidiInfo;
+
+        match self.direction {
+            TextDirection::LeftToRight => false,
+            TextDirection::RightToLeft => true,
+            TextDirection::Auto => BidiInfo::new(text, None).paragraphs.first()
+                .is_some_and(|paragraph| paragraph.level.is_rtl()),
+        }
+    }
+
+    /// Shorten a single line of text with an ellipsis at its end to fit into
+    /// the given width, e.g. for table cells. The ellipsis follows the base
+    /// direction of the text, so it's on the left for right-to-left text.
+    pub fn elide<'a>(&self, text: &'a str, width: i32) -> Cow<'a, str> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        if self.measure(text).0 <= width {
+            return Cow::Borrowed(text);
+        }
+        let ellipsis = if self.char_advance('\u{2026}').is_some() { "\u{2026}" } else { "..." };
+        // Find the longest prefix fitting with the ellipsis, prefixes are not
+        // cached as they are rarely reused.
+        let mut bounds: Vec<usize> = text.grapheme_indices(true).map(|(i, _)| i).collect();
+        bounds.push(text.len());
+        let (mut low, mut high) = (0, bounds.len() - 1);
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            let candidate = format!("{}{}", &text[..bounds[mid]], ellipsis);
+            if self.layout_line(&candidate).width <= width {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        Cow::Owned(format!("{}{}", text[..bounds[low]].trim_end(), ellipsis))
+    }
+
+    /// Change base direction of the text, see `Renderer::set_direction`.
+    pub fn set_direction(&mut self, direction: TextDirection) {
+        if self.direction != direction {
+            self.clear_line_caches();
+        }
+        self.direction = direction;
+    }
+
+    /// Return current font style.
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    /// Change font style of the text, see `Renderer::set_style`.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// Return width of the glyph outlines the quads refer to.
+    pub fn outline_width(&self) -> u8 {
+        self.outline_width
+    }
+
+    /// Change width of the glyph outlines the quads refer to, zero for none,
+    /// see `Renderer::set_outline`. Glyphs get no outline if the atlas has
+    /// no outlines of the width.
+    pub fn set_outline_width(&mut self, width: u8) {
+        self.outline_width = width;
+    }
+
+    /// Change OpenType features applied to the text, see
+    /// `Renderer::set_features`.
+    pub fn set_features(&mut self, features: &[(&str, u32)]) {
+        self.features = features.iter().map(|&(tag, value)| (opentype_tag(tag), value)).collect();
+        self.clear_line_caches();
+    }
+
+    /// Change how control characters are drawn, see
+    /// `Renderer::set_control_chars`.
+    pub fn set_control_chars(&mut self, control_chars: ControlChars) {
+        if self.control_chars != control_chars {
+            self.clear_line_caches();
+        }
+        self.control_chars = control_chars;
+    }
+
+    /// Change case of the text, see `Renderer::set_text_transform`.
+    pub fn set_text_transform(&mut self, transform: TextTransform) {
+        if self.transform != transform {
+            self.clear_line_caches();
+        }
+        self.transform = transform;
+    }
+
+    /// Set function used to hyphenate words which don't fit into wrapped
+    /// lines, e.g. using patterns of the text language, or `None` to break
+    /// words only at soft hyphens (U+00AD). Without hyphenation words wider
+    /// than the line stick out of it.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // With the hyphenation crate.
+    /// let dictionary = hyphenation::Standard::from_embedded(hyphenation::Language::German1996).unwrap();
+    /// layout.set_hyphenator(Some(Box::new(move |word: &str| dictionary.hyphenate(word).breaks)));
+    /// ```
+    pub fn set_hyphenator(&mut self, hyphenator: Option<Hyphenator>) {
+        self.hyphenator = hyphenator;
+    }
+
+    /// Add a custom glyph to the atlas, see `Renderer::register_glyph`.
+    /// Returns the scale applied to texture coordinates of existing glyphs.
+    pub fn register_glyph(&mut self, ch: char, width: u16, height: u16, data: &[u8], metrics: GlyphMetrics) -> [f32; 2] {
+        self.clear_line_caches();
+        self.atlas.bitmap.add_glyph(ch, width, height, data, metrics)
+    }
+
+    /// Replace the font atlas, keeping the layout options.
+    pub fn set_atlas(&mut self, atlas: FontAtlas) {
+        self.clear_line_caches();
+        self.atlas = atlas;
+    }
+
+    /// Repack the atlas without unused space, see `Renderer::compact_atlas`.
+    /// Returns old and new texture rectangles of all glyph images.
+    pub fn compact_atlas(&mut self) -> Vec<([f32; 4], [f32; 4])> {
+        self.clear_line_caches();
+        self.atlas.bitmap.compact()
+    }
+
+    /// Get the bounding box size of a string as rendered by this font.
+    pub fn measure(&self, text: &str) -> (i32, i32) {
+        (self.cached_line(text).width, self.atlas.line_height())
+    }
+
+    /// Return the largest scale, up to 1.0, at which the single line text
+    /// fits into the given size.
+    pub fn fit_scale(&self, text: &str, width: i32, height: i32) -> f32 {
+        let (text_width, text_height) = self.measure(text);
+        let mut scale = 1.0f32;
+        if text_width > width {
+            scale = scale.min(width as f32 / text_width as f32);
+        }
+        if text_height > height {
+            scale = scale.min(height as f32 / text_height as f32);
+        }
+        scale.max(0.0)
+    }
+
+    /// Lay out a single line of text centered in the rectangle (x, y,
+    /// width, height), scaled down with `fit_scale` when it doesn't fit.
+    /// Scaled glyphs are resampled from the atlas, so they are blurrier than
+    /// the ones of a font built at the smaller size.
+    pub fn fitted_quads(&self, text: &str, rect: [i32; 4]) -> Vec<GlyphQuad> {
+        let scale = self.fit_scale(text, rect[2], rect[3]);
+        let (width, height) = self.measure(text);
+        let offset = [
+            rect[0] as f32 + (rect[2] as f32 - width as f32 * scale) / 2.0,
+            rect[1] as f32 + (rect[3] as f32 - height as f32 * scale) / 2.0,
+        ];
+        let mut quads = self.line_quads(text, [0, 0]);
+        for quad in &mut quads {
+            quad.pos = [
+                (offset[0] + quad.pos[0] as f32 * scale).round() as i32,
+                (offset[1] + quad.pos[1] as f32 * scale).round() as i32,
+            ];
+            quad.size = [
+                (quad.size[0] as f32 * scale).round() as i32,
+                (quad.size[1] as f32 * scale).round() as i32,
+            ];
+        }
+        quads
+    }
+
+    /// Get the bounding box size of a text block as laid out by
+    /// `block_quads` with the given width.
+    pub fn measure_block(&self, text: &str, width: i32) -> (i32, i32) {
+        let lines = self.wrap_lines(text, width);
+        let block_width = lines.iter().map(|line| self.wrapped_width(line)).max().unwrap_or(0);
+        (block_width, lines.len() as i32 * self.atlas.line_height())
+    }
+
+    /// Split text into lines at newlines and wrap them to fit into the given
+    /// width. Lines are broken at spaces, after hyphens and between CJK
+    /// ideographs following the Unicode line breaking rules, but never at
+    /// non-breaking spaces. Words which don't fit are hyphenated, see
+    /// `set_hyphenator`.
+    pub fn wrap_lines<'a>(&self, text: &'a str, width: i32) -> Vec<WrappedLine<'a>> {
+        let hyphen_width = self.char_advance('-').unwrap_or(0);
+        let mut lines = Vec::new();
+        for paragraph in split_lines(text) {
+            // Leading spaces of the paragraph are kept for indentation.
+            let mut line_start = 0;
+            // End of the last word on the line, `None` while it's empty.
+            let mut line_end = None;
+            for (start, end) in break_ranges(paragraph) {
+                let mut start = start;
+                loop {
+                    // Candidate lines are not cached, they are rarely reused.
+                    if self.layout_line(&paragraph[line_start..end]).width <= width {
+                        line_end = Some(end);
+                        break;
+                    }
+                    let hyphenation = self.hyphenation_point(paragraph, line_start, (start, end), width - hyphen_width);
+                    if let Some((hyphen_at, resume_at)) = hyphenation {
+                        lines.push(WrappedLine {
+                            text: &paragraph[line_start..hyphen_at],
+                            paragraph_end: false,
+                            hyphenated: true,
+                        });
+                        line_start = resume_at;
+                        line_end = None;
+                        start = resume_at;
+                        continue;
+                    }
+                    match line_end {
+                        // Move the word to the next line.
+                        Some(line_end) => lines.push(WrappedLine {
+                            text: &paragraph[line_start..line_end],
+                            paragraph_end: false,
+                            hyphenated: false,
+                        }),
+                        // The word is wider than the line.
+                        None => {
+                            line_end = Some(end);
+                            break;
+                        },
+                    }
+                    line_start = start;
+                    line_end = None;
+                }
+            }
+            lines.push(WrappedLine {
+                text: &paragraph[line_start..line_end.unwrap_or(line_start)],
+                paragraph_end: true,
+                hyphenated: false,
+            });
+        }
+        lines
+    }
+
+    // Return where to end the line starting at the given offset of the text
+    // with a hyphen inside the word and where to continue on the next line.
+    // The line must fit into the given width without the hyphen.
+    fn hyphenation_point(&self, text: &str, line_start: usize, word: (usize, usize), width: i32) -> Option<(usize, usize)> {
+        let (start, end) = word;
+        let mut points: Vec<(usize, usize)> = text[start..end].char_indices()
+            .filter(|&(_, ch)| ch == '\u{AD}')
+            .map(|(i, ch)| (start + i, start + i + ch.len_utf8()))
+            .collect();
+        if let Some(ref hyphenator) = self.hyphenator {
+            points.extend(hyphenator(&text[start..end]).into_iter()
+                .filter(|&i| text.is_char_boundary(start + i))
+                .map(|i| (start + i, start + i)));
+        }
+        points.retain(|&(hyphen_at, resume_at)| hyphen_at > start && resume_at < end);
+        points.sort();
+        points.into_iter().rev()
+            .find(|&(hyphen_at, _)| self.layout_line(&text[line_start..hyphen_at]).width <= width)
+    }
+
+    // Width of the wrapped line including the hyphen.
+    fn wrapped_width(&self, line: &WrappedLine) -> i32 {
+        let hyphen_width = if line.hyphenated { self.char_advance('-').unwrap_or(0) } else { 0 };
+        self.measure(line.text).0 + hyphen_width
+    }
+
+    /// Return horizontal pen advance of the char, see
+    /// `Renderer::char_advance`.
+    pub fn char_advance(&self, ch: char) -> Option<i32> {
+        self.atlas.bitmap.find_char(self.face(), ch).map(|ch_info| ch_info.x_advance)
+    }
+
+    /// Return adjustment of the advance between two chars, see
+    /// `Renderer::kerning`.
+    #[cfg(feature = "shaping")]
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        // Fonts made from user bitmaps and bitmap fonts have no shapers.
+        match self.atlas.shapers.get(self.face()) {
+            Some(Some(shaper)) => shaper.kerning(left, right, &self.features),
+            _ => 0,
+        }
+    }
+
+    /// Return adjustment of the advance between two chars, see
+    /// `Renderer::kerning`.
+    #[cfg(not(feature = "shaping"))]
+    pub fn kerning(&self, _left: char, _right: char) -> i32 {
+        // Only the shaper applies kerning.
+        0
+    }
+
+    /// Return byte offset and horizontal pen position of every caret stop
+    /// in the text, see `Renderer::caret_positions`.
+    pub fn caret_positions(&self, text: &str) -> Vec<(usize, i32)> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        // Advances of the chars are summed up in logical order.
+        let mut advances = vec![0; text.len()];
+        for (source, advance) in self.layout_line(text).advances {
+            advances[source] += advance;
+        }
+        let mut positions = vec![(0, 0)];
+        let mut x = 0;
+        positions.extend(text.grapheme_indices(true).map(|(i, cluster)| {
+            let end = i + cluster.len();
+            x += advances[i..end].iter().sum::<i32>();
+            (end, x)
+        }));
+        positions
+    }
+
+    /// Lay out a single line of text with its top left corner at the given
+    /// position.
+    pub fn line_quads(&self, text: &str, pos: [i32; 2]) -> Vec<GlyphQuad> {
+        place_quads(&self.cached_line(text), pos)
+    }
+
+    /// Lay out a single line of glyphs given by their indices in the font of
+    /// the current style, e.g. icons of an icon font which no char maps to,
+    /// with its top left corner at the given position. Glyphs missing in the
+    /// atlas are skipped; only glyphs of the font chars are placed into it,
+    /// or all of them with the `shaping` feature.
+    pub fn glyph_quads(&self, indices: &[u32], pos: [i32; 2]) -> Vec<GlyphQuad> {
+        let face = self.face();
+        let mut advance = 0;
+        let mut quads = Vec::with_capacity(indices.len());
+        for (source, &index) in indices.iter().enumerate() {
+            if let Some(ch_info) = self.atlas.bitmap.find_glyph(face, index) {
+                let pen = [pos[0] + advance, pos[1]];
+                quads.extend(self.pen_quads(face, ch_info, pen, source, 1.0).filter(|quad| !quad.is_empty()));
+                advance += ch_info.x_advance;
+            }
+        }
+        quads
+    }
+
+    /// Lay out a single line of text with the edge or mid-point selected by
+    /// the anchors at the given position, see `Renderer::add_anchored`. The
+    /// text is laid out once, its size is taken from the same layout.
+    pub fn anchored_quads(&self, text: &str, pos: [i32; 2], horizontal: HorizontalAnchor, vertical: VerticalAnchor) -> Vec<GlyphQuad> {
+        self.anchored_line(text, pos, horizontal, vertical).0
+    }
+
+    // Same as `anchored_quads`, also returning the line box rectangle (x, y,
+    // width, height).
+    pub(crate) fn anchored_line(&self, text: &str, pos: [i32; 2], horizontal: HorizontalAnchor,
+                                vertical: VerticalAnchor) -> (Vec<GlyphQuad>, [i32; 4]) {
+        let line = self.cached_line(text);
+        // Detecting the direction takes another pass, so only if needed.
+        let horizontal = match horizontal {
+            HorizontalAnchor::Start | HorizontalAnchor::End => horizontal.resolve(self.is_rtl(text)),
+            _ => horizontal,
+        };
+        let height = self.atlas.line_height();
+        let x = match horizontal {
+            HorizontalAnchor::Left | HorizontalAnchor::Start | HorizontalAnchor::End => pos[0],
+            HorizontalAnchor::Center => pos[0] - line.width / 2,
+            HorizontalAnchor::Right => pos[0] - line.width,
+        };
+        let y = match vertical {
+            VerticalAnchor::Top => pos[1],
+            VerticalAnchor::Center => pos[1] - height / 2,
+            VerticalAnchor::Bottom => pos[1] - height,
+            VerticalAnchor::CapHeight => pos[1] - self.atlas.baseline + self.atlas.cap_height() / 2,
+            VerticalAnchor::XHeight => pos[1] - self.atlas.baseline + self.atlas.x_height() / 2,
+        };
+        (place_quads(&line, [x, y]), [x, y, line.width, height])
+    }
+
+    // Return the line laid out at the origin, from the cache if possible.
+    fn cached_line(&self, text: &str) -> Arc<CachedLine> {
+        let face = self.face();
+        let mut caches = self.line_caches.lock().unwrap();
+        let cache = caches.entry((face, self.outline_width)).or_default();
+        if let Some(line) = cache.current.get(text) {
+            return line.clone();
+        }
+        let line = match cache.previous.remove(text) {
+            Some(line) => line,
+            None => {
+                let layout = self.layout_line(text);
+                Arc::new(CachedLine { quads: layout.glyphs, width: layout.width })
+            },
+        };
+        if cache.current.len() >= LINE_CACHE_SIZE {
+            cache.previous = ::std::mem::take(&mut cache.current);
+        }
+        cache.current.insert(text.to_owned(), line.clone());
+        line
+    }
+
+    fn clear_line_caches(&self) {
+        self.line_caches.lock().unwrap().clear();
+    }
+
+    // Append quads of the line starting at the given byte offset of the
+    // whole laid out text.
+    pub(crate) fn push_line_quads(&self, text: &str, offset: usize, pos: [i32; 2], quads: &mut Vec<GlyphQuad>) {
+        quads.extend(self.layout_line(text).glyphs.into_iter().map(|quad| GlyphQuad {
+            pos: [pos[0] + quad.pos[0], pos[1] + quad.pos[1]],
+            source: offset + quad.source,
+            ..quad
+        }));
+    }
+
+    /// Lay out a single line made of spans with their own style and baseline
+    /// shift, see `Renderer::add_spans`. Sources of the quads are byte
+    /// offsets in the concatenated text of all spans.
+    pub fn span_quads(&mut self, spans: &[TextSpan], pos: [i32; 2]) -> Vec<GlyphQuad> {
+        self.layout_spans(spans, pos).0
+    }
+
+    /// Return rectangle (x, y, width, height) covering every span of the line
+    /// laid out by `span_quads`, e.g. to detect clicks on links. The line box
+    /// is scaled and shifted together with the span.
+    pub fn span_rects(&mut self, spans: &[TextSpan], pos: [i32; 2]) -> Vec<[i32; 4]> {
+        self.layout_spans(spans, pos).1
+    }
+
+    // Lay out the spans, returning their glyph quads and rectangles.
+    pub(crate) fn layout_spans(&mut self, spans: &[TextSpan], pos: [i32; 2]) -> (Vec<GlyphQuad>, Vec<[i32; 4]>) {
+        let prev_style = self.style;
+        let baseline = self.atlas.baseline;
+        let mut quads = Vec::new();
+        let mut rects = Vec::with_capacity(spans.len());
+        let (mut pen, mut offset) = (pos[0], 0);
+        for span in spans {
+            self.style = span.style;
+            let (raise, scale) = self.atlas.baseline_shift(span.baseline);
+            let layout = self.layout_scaled(span.text, scale);
+            quads.extend(layout.glyphs.into_iter().map(|quad| GlyphQuad {
+                pos: [pen + quad.pos[0], pos[1] - raise + quad.pos[1]],
+                source: offset + quad.source,
+                ..quad
+            }));
+            let top = pos[1] - raise + baseline - scale_px(baseline, scale);
+            rects.push([pen, top, layout.advance, scale_px(self.atlas.line_height(), scale)]);
+            pen += layout.advance;
+            offset += span.text.len();
+        }
+        self.style = prev_style;
+        (quads, rects)
+    }
+
+    /// Return rectangle (x, y, width, height) covering the byte range of the
+    /// line laid out at the given position, e.g. to highlight selected text.
+    /// The range is measured in logical order like `caret_positions`, and
+    /// must lie on char boundaries.
+    pub fn range_rect(&self, text: &str, pos: [i32; 2], range: Range<usize>) -> [i32; 4] {
+        let start = self.layout_line(&text[..range.start]).advance;
+        let end = self.layout_line(&text[..range.end]).advance;
+        [pos[0] + start, pos[1], end - start, self.atlas.line_height()]
+    }
+
+    /// Return quad filling the rectangle (x, y, width, height) with the
+    /// white pixel of the atlas, so it may be drawn together with glyphs.
+    pub fn solid_quad(&self, rect: [i32; 4]) -> GlyphQuad {
+        let white = self.atlas.bitmap.get_white_pixel();
+        GlyphQuad {
+            pos: [rect[0], rect[1]],
+            size: [rect[2], rect[3]],
+            tex: [white[0], white[1], 0.0, 0.0],
+            source: 0,
+            glow: None,
+            outline: None,
+            color: None,
+        }
+    }
+
+    /// Lay out multi-line text, see `Renderer::add_block`.
+    pub fn block_quads(&self, text: &str, pos: [i32; 2], width: i32, align: TextAlign) -> Vec<GlyphQuad> {
+        let line_height = self.atlas.line_height();
+        let rtl = self.is_rtl(text);
+        let mut quads = Vec::new();
+        let mut y = pos[1];
+        for wrapped in self.wrap_lines(text, width) {
+            let (line, last) = (wrapped.text, wrapped.paragraph_end);
+            // Lines are slices of the text.
+            let offset = line.as_ptr() as usize - text.as_ptr() as usize;
+            let extra = width - self.wrapped_width(&wrapped);
+            let words = word_ranges(line);
+            let justified = align == TextAlign::Justify && !last && words.len() > 1 && extra > 0;
+            let start_align = TextAlign::Start.resolve(rtl);
+            let x = match align.resolve(rtl) {
+                TextAlign::Justify if justified || start_align == TextAlign::Left => pos[0],
+                TextAlign::Left => pos[0],
+                TextAlign::Center => pos[0] + extra / 2,
+                _ => pos[0] + extra,
+            };
+            if justified {
+                let gaps = words.len() as i32 - 1;
+                for (i, &(start, end)) in words.iter().enumerate() {
+                    let x = x + self.layout_line(&line[..start]).advance + extra * i as i32 / gaps;
+                    self.push_line_quads(&line[start..end], offset + start, [x, y], &mut quads);
+                }
+            } else {
+                self.push_line_quads(line, offset, [x, y], &mut quads);
+            }
+            // NOTE: The hyphen is placed after the end of the line, which is
+            // wrong for right-to-left text.
+            if let (true, Some(ch_info)) = (wrapped.hyphenated, self.find_char(self.face(), '-')) {
+                let end_x = x + self.layout_line(line).advance + if justified { extra } else { 0 };
+                let glyph_pos = [end_x + ch_info.x_offset, y + ch_info.y_offset];
+                let quad = glyph_quad(ch_info, glyph_pos, offset + line.len(), self.outline_slot(self.face()));
+                if !quad.is_empty() {
+                    quads.push(quad);
+                }
+            }
+            y += line_height;
+        }
+        quads
+    }
+
+    /// Render a single line of text into an image buffer on the CPU, without
+    /// any GPU resources. The image has the size returned by `measure` and is
+    /// returned together with its width and height, rows go from the top.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let (data, width, height) = layout.render_to_image("Hello", ImageFormat::Gray, [1.0; 4]);
+    /// assert_eq!(data.len(), (width * height) as usize);
+    /// ```
+    pub fn render_to_image<C: Into<Color>>(&self, text: &str, format: ImageFormat, color: C) -> (Vec<u8>, u32, u32) {
+        let color = color.into().0;
+        let (width, height) = self.measure(text);
+        let (width, height) = (max(width, 0) as usize, max(height, 0) as usize);
+        let atlas_width = self.atlas.width() as usize;
+        let atlas_height = self.atlas.height() as usize;
+        let atlas_image = self.atlas.image();
+        let distance_range = self.atlas.bitmap.get_distance_range();
+        let mut coverage = vec![0u8; width * height];
+        for quad in self.line_quads(text, [0, 0]) {
+            let src_x = (quad.tex[0] * atlas_width as f32).round() as usize;
+            let src_y = (quad.tex[1] * atlas_height as f32).round() as usize;
+            // Quads of small capitals are smaller than their glyphs.
+            let src_width = (quad.tex[2] * atlas_width as f32).round() as i32;
+            let src_height = (quad.tex[3] * atlas_height as f32).round() as i32;
+            for y in 0..quad.size[1] {
+                let dst_y = quad.pos[1] + y;
+                if dst_y < 0 || dst_y >= height as i32 {
+                    continue;
+                }
+                for x in 0..quad.size[0] {
+                    let dst_x = quad.pos[0] + x;
+                    if dst_x < 0 || dst_x >= width as i32 {
+                        continue;
+                    }
+                    let (glyph_x, glyph_y) = (x * src_width / quad.size[0], y * src_height / quad.size[1]);
+                    let src_index = (src_y + glyph_y as usize) * atlas_width + src_x + glyph_x as usize;
+                    let src = if distance_range > 0 {
+                        distance_coverage(&atlas_image[src_index * 4..src_index * 4 + 3], distance_range)
+                    } else {
+                        atlas_image[src_index] as u32
+                    };
+                    let dst = &mut coverage[dst_y as usize * width + dst_x as usize];
+                    // Overlapping glyphs are composited the same way as with
+                    // alpha blending on the GPU.
+                    *dst = (src + *dst as u32 * (255 - src) / 255) as u8;
+                }
+            }
+        }
+        let data = match format {
+            ImageFormat::Gray => coverage,
+            ImageFormat::Rgba => {
+                let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let rgb = [to_byte(color[0]), to_byte(color[1]), to_byte(color[2])];
+                coverage.iter().flat_map(|&alpha| {
+                    vec![rgb[0], rgb[1], rgb[2], to_byte(alpha as f32 / 255.0 * color[3])]
+                }).collect()
+            },
+        };
+        (data, width as u32, height as u32)
+    }
+
+    /// Lay out vertical text, see `Renderer::add_vertical`.
+    pub fn vertical_quads(&self, text: &str, pos: [i32; 2]) -> Vec<GlyphQuad> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        // NOTE: Vertical alternates of glyphs (e.g. rotated brackets) are not
+        // used, the same glyphs as in horizontal layout are drawn. Color
+        // glyphs are drawn by their monochrome base glyphs.
+        let start_time = Instant::now();
+        let column_width = self.atlas.line_height();
+        let face = self.face();
+        let mut quads = Vec::new();
+        // Pen is placed on the center line of the column.
+        let mut x = pos[0] - column_width / 2;
+        for column in split_lines(text) {
+            // Columns are slices of the text.
+            let offset = column.as_ptr() as usize - text.as_ptr() as usize;
+            let transformed = self.transform_case(column);
+            let (chars, sources, small_caps) = match transformed {
+                Some(ref transformed) => (&transformed.text[..], Some(&transformed.sources[..]), &transformed.small_caps[..]),
+                None => (column, None, &[][..]),
+            };
+            let mut y = pos[1];
+            for (start, cluster) in chars.grapheme_indices(true) {
+                // Combining marks share the pen with the base char.
+                let mut advance = 0;
+                for (i, ch) in cluster.char_indices() {
+                    if let Some(ch_info) = self.find_char(face, ch) {
+                        let index = start + i;
+                        let source = sources.map_or(index, |sources| sources[index]);
+                        // Small capitals are scaled around the pen.
+                        let scale = if small_caps.get(index) == Some(&true) { SMALL_CAPS_SCALE } else { 1.0 };
+                        let glyph_pos = [x + scale_px(ch_info.v_x_offset, scale), y + scale_px(ch_info.v_y_offset, scale)];
+                        let mut quad = glyph_quad(ch_info, glyph_pos, offset + source, self.outline_slot(face));
+                        quad.size = [scale_px(quad.size[0], scale), scale_px(quad.size[1], scale)];
+                        if !quad.is_empty() {
+                            quads.push(quad);
+                        }
+                        advance = max(advance, scale_px(ch_info.y_advance, scale));
+                    }
+                }
+                y += advance;
+            }
+            x -= column_width;
+        }
+        self.add_layout_time(start_time);
+        quads
+    }
+
+    // Find glyph drawn for the char, see `char_glyph`.
+    fn find_char(&self, face: usize, ch: char) -> Option<&BitmapChar> {
+        match self.char_glyph(ch) {
+            CharGlyph::Font => self.atlas.bitmap.find_char(face, ch),
+            CharGlyph::Replaced(ch_info) => Some(ch_info),
+            CharGlyph::Hidden => None,
+        }
+    }
+
+    // Return how the char is drawn apart from the usual font lookup.
+    fn char_glyph<'a>(&'a self, ch: char) -> CharGlyph<'a> {
+        if let Some(ch_info) = self.atlas.bitmap.find_custom(ch) {
+            return CharGlyph::Replaced(ch_info);
+        }
+        match ch {
+            // Soft hyphens are only drawn by `block_quads` at line breaks.
+            '\u{AD}' | '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}' => CharGlyph::Hidden,
+            _ if ch.is_control() => {
+                let picture = match ch as u32 {
+                    code @ 0x00..=0x1F => ::std::char::from_u32(0x2400 + code),
+                    0x7F => Some('\u{2421}'),
+                    _ => None,
+                };
+                let ch_info = picture.and_then(|picture| self.atlas.bitmap.find_char(self.face(), picture));
+                match (self.control_chars, ch_info) {
+                    (ControlChars::Visualize, Some(ch_info)) => CharGlyph::Replaced(ch_info),
+                    _ => CharGlyph::Hidden,
+                }
+            },
+            _ => CharGlyph::Font,
+        }
+    }
+
+    // Number of the font face of the current style.
+    fn face(&self) -> usize {
+        self.atlas.style_faces[self.style.slot()]
+    }
+
+    // Return the text with its case transformed, or `None` if it's laid out
+    // as is.
+    fn transform_case(&self, text: &str) -> Option<TransformedText> {
+        let synthesize_small_caps = match self.transform {
+            TextTransform::None => return None,
+            // The shaper substitutes small capitals of the font.
+            TextTransform::SmallCaps if self.has_small_caps() => return None,
+            TextTransform::SmallCaps => true,
+            TextTransform::Uppercase | TextTransform::Lowercase => false,
+        };
+        let mut transformed = TransformedText {
+            text: String::with_capacity(text.len()),
+            sources: Vec::with_capacity(text.len() + 1),
+            small_caps: Vec::with_capacity(text.len()),
+        };
+        for (i, ch) in text.char_indices() {
+            let start = transformed.text.len();
+            let small_caps = synthesize_small_caps && ch.is_lowercase();
+            if self.transform == TextTransform::Lowercase {
+                transformed.text.extend(ch.to_lowercase());
+            } else {
+                transformed.text.extend(ch.to_uppercase());
+            }
+            let len = transformed.text.len() - start;
+            transformed.sources.extend(repeat_n(i, len));
+            transformed.small_caps.extend(repeat_n(small_caps, len));
+        }
+        transformed.sources.push(text.len());
+        if transformed.text == text && !transformed.small_caps.contains(&true) {
+            return None;
+        }
+        Some(transformed)
+    }
+
+    // Whether the font of the current style has small capitals.
+    #[cfg(feature = "shaping")]
+    fn has_small_caps(&self) -> bool {
+        self.atlas.small_caps.get(self.face()).cloned().unwrap_or(false)
+    }
+
+    #[cfg(not(feature = "shaping"))]
+    fn has_small_caps(&self) -> bool {
+        false
+    }
+
+    /// Lay out a single line of text.
+    fn layout_line(&self, text: &str) -> LineLayout {
+        self.layout_scaled(text, 1.0)
+    }
+
+    /// Lay out a single line of text scaled around the baseline.
+    fn layout_scaled(&self, text: &str, scale: f32) -> LineLayout {
+        let start = Instant::now();
+        let layout = match self.transform_case(text) {
+            Some(transformed) => {
+                let mut layout = self.layout_transformed(&transformed.text, transformed.small_caps, scale);
+                // Quads refer to the original text.
+                for quad in &mut layout.glyphs {
+                    quad.source = transformed.sources[quad.source];
+                }
+                for advance in &mut layout.advances {
+                    advance.0 = transformed.sources[advance.0];
+                }
+                layout
+            },
+            None => self.layout_transformed(text, Vec::new(), scale),
+        };
+        self.add_layout_time(start);
+        layout
+    }
+
+    // Add time elapsed since the start to the layout time.
+    fn add_layout_time(&self, start: Instant) {
+        let elapsed = start.elapsed();
+        let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+        self.layout_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Lay out a single line of text with its case already transformed.
+    fn layout_transformed(&self, text: &str, small_caps: Vec<bool>, scale: f32) -> LineLayout {
+        let mut layout = LineLayout {
+            glyphs: Vec::new(),
+            advance: 0,
+            width: 0,
+            advances: Vec::new(),
+            small_caps: small_caps,
+            scale: scale,
+        };
+        let mut offset = 0;
+        for (i, run) in text.split('\t').enumerate() {
+            if i > 0 {
+                let tab_stop = self.next_tab_stop(layout.advance);
+                layout.advance_pen(offset - 1, tab_stop - layout.advance);
+                layout.width = layout.advance;
+            }
+            self.layout_bidi(run, offset, &mut layout);
+            offset += run.len() + 1;
+        }
+        layout
+    }
+
+    /// Append glyphs of a text segment without tabs, starting at the given
+    /// byte offset of the line, to the layout in visual order.
+    fn layout_bidi(&self, text: &str, offset: usize, layout: &mut LineLayout) {
+        use unicode_bidi::{BidiInfo, Level};
+
+        let level = match self.direction {
+            TextDirection::Auto => None,
+            TextDirection::LeftToRight => Some(Level::ltr()),
+            TextDirection::RightToLeft => Some(Level::rtl()),
+        };
+        let info = BidiInfo::new(text, level);
+        if !info.has_rtl() {
+            self.layout_run(text, offset, false, layout);
+            return
+        }
+        for paragraph in &info.paragraphs {
+            let (levels, runs) = info.visual_runs(paragraph, paragraph.range.clone());
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+                self.layout_run(&text[run.clone()], offset + run.start, rtl, layout);
+            }
+        }
+    }
+
+    /// Append glyphs of a single direction text run to the layout.
+    #[cfg(not(feature = "shaping"))]
+    fn layout_run(&self, run: &str, offset: usize, rtl: bool, layout: &mut LineLayout) {
+        self.layout_clusters(run, offset, rtl, layout);
+    }
+
+    /// Append glyphs of the text run to the layout placing chars of every
+    /// grapheme cluster over its first char.
+    fn layout_clusters(&self, run: &str, offset: usize, rtl: bool, layout: &mut LineLayout) {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        // NOTE: Mirrored forms of brackets are not substituted, it requires
+        // the shaping support.
+        let face = self.face();
+        let mut clusters: Vec<(usize, &str)> = run.grapheme_indices(true).collect();
+        if rtl {
+            clusters.reverse();
+        }
+        for (cluster_start, cluster) in clusters {
+            let start = layout.advance;
+            // The first char of a grapheme cluster advances the pen, the rest
+            // (combining marks) are placed over it.
+            let mut base_advance = None;
+            for (i, ch) in cluster.char_indices() {
+                let source = offset + cluster_start + i;
+                let ch_info = match self.find_char(face, ch) {
+                    Some(info) => info,
+                    // Skip unknown chars from text string. Probably it would
+                    // be better to place some "?" mark instead but it may
+                    // not exist in the font too.
+                    None => continue,
+                };
+                let scale = layout.scale(source);
+                let x_advance = scale_px(ch_info.x_advance, scale);
+                match base_advance {
+                    None => {
+                        for quad in self.pen_quads(face, ch_info, [start, 0], source, scale) {
+                            layout.push_glyph(quad);
+                        }
+                        layout.advance_pen(source, x_advance);
+                        base_advance = Some(x_advance);
+                    },
+                    Some(base_advance) => {
+                        // Zero-advance marks are already positioned by the
+                        // font relative to the end of the base glyph, spacing
+                        // ones are centered over it.
+                        let x = if x_advance == 0 {
+                            layout.advance
+                        } else {
+                            start + (base_advance - x_advance) / 2
+                        };
+                        for quad in self.pen_quads(face, ch_info, [x, 0], source, scale) {
+                            if !quad.is_empty() {
+                                layout.width = max(layout.width, quad.pos[0] + quad.size[0]);
+                                layout.glyphs.push(quad);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Append glyphs of a single direction text run to the layout.
+    #[cfg(feature = "shaping")]
+    fn layout_run(&self, run: &str, offset: usize, rtl: bool, layout: &mut LineLayout) {
+        let face = self.face();
+        // Fonts made from user bitmaps and bitmap fonts have no shapers.
+        let shaper = match self.atlas.shapers.get(face) {
+            Some(Some(shaper)) => shaper,
+            _ => return self.layout_clusters(run, offset, rtl, layout),
+        };
+        let small_caps_features;
+        let features = if self.transform == TextTransform::SmallCaps {
+            small_caps_features = self.features.iter().cloned()
+                .chain(Some((opentype_tag("smcp"), 1)))
+                .collect::<Vec<_>>();
+            &small_caps_features
+        } else {
+            &self.features
+        };
+        let glyphs = shaper.shape(run, rtl, features);
+        // Byte offsets of the cluster starts, in logical order.
+        let mut cluster_starts: Vec<usize> = glyphs.iter().map(|glyph| glyph.cluster).collect();
+        cluster_starts.sort();
+        cluster_starts.dedup();
+        // Glyphs of a cluster follow each other.
+        let mut rest = &glyphs[..];
+        while let Some(first) = rest.first() {
+            let cluster = first.cluster;
+            let count = rest.iter().take_while(|glyph| glyph.cluster == cluster).count();
+            let (cluster_glyphs, next) = rest.split_at(count);
+            rest = next;
+
+            let source = offset + cluster;
+            let scale = layout.scale(source);
+            // Custom glyphs and control pictures replace whatever the font
+            // has shaped their chars into.
+            let ch = run[cluster..].chars().next().unwrap_or(' ');
+            match self.char_glyph(ch) {
+                CharGlyph::Font => (),
+                CharGlyph::Replaced(ch_info) => {
+                    let quad = self.pen_quad(face, ch_info, [layout.advance, 0], source, scale);
+                    layout.push_glyph(quad);
+                    layout.advance_pen(source, scale_px(ch_info.x_advance, scale));
+                    continue;
+                },
+                CharGlyph::Hidden => continue,
+            }
+            // Glyphs may be missing in the atlas built with a limited char
+            // set, e.g. ligatures or contextual forms. Chars of the cluster
+            // are placed one by one then, as without shaping.
+            let missing = cluster_glyphs.iter()
+                .any(|glyph| self.atlas.bitmap.find_glyph(face, glyph.index).is_none());
+            if missing {
+                let end = cluster_starts.iter().cloned().find(|&start| start > cluster).unwrap_or(run.len());
+                self.layout_clusters(&run[cluster..end], source, rtl, layout);
+                continue;
+            }
+            for glyph in cluster_glyphs {
+                if let Some(ch_info) = self.atlas.bitmap.find_glyph(face, glyph.index) {
+                    let pen = [layout.advance + scale_px(glyph.x_offset, scale), -scale_px(glyph.y_offset, scale)];
+                    for quad in self.pen_quads(face, ch_info, pen, source, scale) {
+                        layout.push_glyph(quad);
+                    }
+                }
+                layout.advance_pen(source, scale_px(glyph.x_advance, scale));
+            }
+        }
+    }
+
+    // Return quad of the glyph with the pen at the given position, scaled
+    // around the pen on the baseline.
+    fn pen_quad(&self, face: usize, ch_info: &BitmapChar, pen: [i32; 2], source: usize, scale: f32) -> GlyphQuad {
+        let pos = [pen[0] + ch_info.x_offset, pen[1] + ch_info.y_offset];
+        let mut quad = glyph_quad(ch_info, pos, source, self.outline_slot(face));
+        if scale != 1.0 {
+            let baseline = self.atlas.baseline;
+            quad.pos = [
+                pen[0] + scale_px(ch_info.x_offset, scale),
+                pen[1] + baseline - scale_px(baseline - ch_info.y_offset, scale),
+            ];
+            quad.size = [scale_px(ch_info.width, scale), scale_px(ch_info.height, scale)];
+        }
+        quad
+    }
+
+    // Return quad of the glyph with the pen at the given position like
+    // `pen_quad`, or quads of its color layers from the bottom.
+    fn pen_quads<'a>(&'a self, face: usize, ch_info: &'a BitmapChar, pen: [i32; 2], source: usize, scale: f32)
+                     -> impl Iterator<Item = GlyphQuad> + 'a {
+        let glyph = if ch_info.color_layers.is_empty() {
+            Some(self.pen_quad(face, ch_info, pen, source, scale))
+        } else {
+            None
+        };
+        glyph.into_iter().chain(ch_info.color_layers.iter().filter_map(move |layer| {
+            self.atlas.bitmap.find_glyph(face, layer.glyph).map(|layer_info| GlyphQuad {
+                color: layer.color,
+                ..self.pen_quad(face, layer_info, pen, source, scale)
+            })
+        }))
+    }
+
+    // Index of the outlines of the layout's width among the outlines of the
+    // face's glyphs, see `BitmapChar::outlines`.
+    fn outline_slot(&self, face: usize) -> Option<usize> {
+        if self.outline_width == 0 {
+            return None;
+        }
+        self.atlas.bitmap.get_outline_widths(face).iter().position(|&width| width == self.outline_width)
+    }
+
+    /// Return position of the tab stop following the given one, relative to
+    /// the text origin.
+    fn next_tab_stop(&self, advance: i32) -> i32 {
+        (advance / self.atlas.tab_width + 1) * self.atlas.tab_width
+    }
+}
+
+// Return quads of the line laid out at the origin moved to the position.
+fn place_quads(line: &CachedLine, pos: [i32; 2]) -> Vec<GlyphQuad> {
+    line.quads.iter().map(|quad| GlyphQuad {
+        pos: [pos[0] + quad.pos[0], pos[1] + quad.pos[1]],
+        ..*quad
+    }).collect()
+}
+
+fn scale_px(value: i32, scale: f32) -> i32 {
+    (value as f32 * scale).round() as i32
+}
+
+// Coverage of the pixel with the given distance field channels, drawn at
+// the font size.
+fn distance_coverage(field: &[u8], range: u8) -> u32 {
+    let median = max(min(field[0], field[1]), min(max(field[0], field[1]), field[2]));
+    // Distance in pixels, positive inside.
+    let distance = (median as f32 / 255.0 - 0.5) * 2.0 * range as f32;
+    ((distance + 0.5).clamp(0.0, 1.0) * 255.0).round() as u32
+}
+
+fn glyph_quad(ch_info: &BitmapChar, pos: [i32; 2], source: usize, outline: Option<usize>) -> GlyphQuad {
+    GlyphQuad {
+        pos: pos,
+        size: [ch_info.width, ch_info.height],
+        tex: [ch_info.tex[0], ch_info.tex[1], ch_info.tex_width, ch_info.tex_height],
+        source: source,
+        glow: ch_info.glow,
+        outline: outline.and_then(|i| ch_info.outlines.get(i).cloned()),
+        color: None,
+    }
+}
+
+// Return byte ranges of space separated words.
+fn word_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, ch) in text.char_indices() {
+        match (ch == ' ', start) {
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            },
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+// Return byte ranges of the unbreakable parts of the text, without trailing
+// spaces. Lines may be broken between any of them, at the break
+// opportunities of UAX #14.
+fn break_ranges(text: &str) -> Vec<(usize, usize)> {
+    // NOTE: Thai, Lao and other complex context (SA) scripts are broken only
+    // at spaces, breaking between their words requires a dictionary.
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (end, _) in unicode_linebreak::linebreaks(text) {
+        let part = &text[start..end];
+        let word = part.trim_matches(' ');
+        if !word.is_empty() {
+            let word_start = start + part.len() - part.trim_start_matches(' ').len();
+            ranges.push((word_start, word_start + word.len()));
+        }
+        start = end;
+    }
+    ranges
+}
+
+// Split text into lines at newlines, treating "\r\n" as a single newline.
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split('\n').map(|line| line.trim_end_matches('\r')).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::FontBuilder;
+
+    fn layout() -> TextLayout {
+        TextLayout::new(FontBuilder::new().build().unwrap())
+    }
+
+    #[test]
+    fn measure_grows_with_text() {
+        let layout = layout();
+        let line_height = layout.atlas().line_height();
+        assert_eq!(layout.measure(""), (0, line_height));
+        let (short, height) = layout.measure("Hello");
+        assert_eq!(height, line_height);
+        assert!(short > 0);
+        assert!(layout.measure("Hello, world").0 > short);
+    }
+
+    #[test]
+    fn wrapped_lines_fit_and_break_at_spaces() {
+        let layout = layout();
+        let text = "The quick brown fox jumps over the lazy dog";
+        let width = layout.measure("The quick brown").0;
+        let lines = layout.wrap_lines(text, width);
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(layout.measure(line.text).0 <= width, "{:?} is too wide", line.text);
+            assert!(!line.text.starts_with(' ') && !line.text.ends_with(' '));
+            assert!(!line.hyphenated);
+        }
+        assert_eq!(lines[0].text, "The quick brown");
+        assert!(lines.last().unwrap().paragraph_end);
+        let words: Vec<&str> = lines.iter().flat_map(|line| line.text.split(' ')).collect();
+        assert_eq!(words, text.split(' ').collect::<Vec<_>>());
+
+        let paragraphs = layout.wrap_lines("one\r\ntwo", 1000);
+        let texts: Vec<&str> = paragraphs.iter().map(|line| line.text).collect();
+        assert_eq!(texts, ["one", "two"]);
+        assert!(paragraphs.iter().all(|line| line.paragraph_end));
+    }
+
+    #[test]
+    fn caret_positions_follow_grapheme_clusters() {
+        let layout = layout();
+        let text = "Ae\u{301}b c";
+        let positions = layout.caret_positions(text);
+        let offsets: Vec<usize> = positions.iter().map(|&(offset, _)| offset).collect();
+        // The combining accent doesn't get a stop of its own.
+        assert_eq!(offsets, [0, 1, 4, 5, 6, 7]);
+        assert_eq!(positions[0], (0, 0));
+        assert!(positions.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(positions.last().unwrap().1, layout.range_rect(text, [0, 0], 0..text.len())[2]);
+
+        // The caret after a tab is at the tab stop.
+        let positions = layout.caret_positions("a\tb");
+        assert_eq!(positions[2], (2, layout.next_tab_stop(0)));
+    }
+
+    #[test]
+    fn bidi_direction_orders_glyphs() {
+        let mut layout = layout();
+        assert!(!layout.is_rtl("abc"));
+        assert!(layout.is_rtl("\u{5E9}\u{5DC}\u{5D5}\u{5DD} abc"));
+        assert!(!layout.is_rtl("abc \u{5E9}\u{5DC}\u{5D5}\u{5DD}"));
+
+        // Neutral chars take the base direction.
+        let sources = |layout: &TextLayout| -> Vec<usize> {
+            let mut quads = layout.line_quads("(.,", [0, 0]);
+            quads.sort_by_key(|quad| quad.pos[0]);
+            quads.into_iter().map(|quad| quad.source).collect()
+        };
+        assert_eq!(sources(&layout), [0, 1, 2]);
+        layout.set_direction(TextDirection::RightToLeft);
+        assert!(layout.is_rtl("abc"));
+        assert_eq!(sources(&layout), [2, 1, 0]);
+        // Latin text keeps its order in a right-to-left paragraph.
+        let mut quads = layout.line_quads("ab", [0, 0]);
+        quads.sort_by_key(|quad| quad.pos[0]);
+        assert_eq!(quads.iter().map(|quad| quad.source).collect::<Vec<_>>(), [0, 1]);
+    }
+
+    // Text split into its unbreakable parts.
+    fn break_parts(text: &str) -> Vec<&str> {
+        break_ranges(text).into_iter().map(|(start, end)| &text[start..end]).collect()
+    }
+
+    #[test]
+    fn line_break_opportunities() {
+        let cases: &[(&str, &[&str])] = &[
+            ("one two  three", &["one", "two", "three"]),
+            ("  indented", &["indented"]),
+            // No-break space and word joiner glue the words.
+            ("10\u{A0}km a\u{2060}b", &["10\u{A0}km", "a\u{2060}b"]),
+            // Breaks after hyphens, but not in front of a number.
+            ("well-known -5", &["well-", "known", "-5"]),
+            // Breaks around em dashes.
+            ("a\u{2014}b", &["a", "\u{2014}", "b"]),
+            // Closing punctuation stays with the preceding word.
+            ("(word) end.", &["(word)", "end."]),
+            ("\"quoted\" text!", &["\"quoted\"", "text!"]),
+            // Breaks between ideographs, but not before closing marks and
+            // small kana.
+            ("\u{65E5}\u{672C}\u{8A9E}", &["\u{65E5}", "\u{672C}", "\u{8A9E}"]),
+            ("\u{3042}\u{3002}\u{3044}", &["\u{3042}\u{3002}", "\u{3044}"]),
+            ("\u{300C}\u{5B57}\u{300D}\u{5B57}", &["\u{300C}\u{5B57}\u{300D}", "\u{5B57}"]),
+            ("\u{30C1}\u{30E3}", &["\u{30C1}\u{30E3}"]),
+            ("\u{D55C}\u{AD6D} \u{C5B4}", &["\u{D55C}", "\u{AD6D}", "\u{C5B4}"]),
+            // Thai words are broken only at spaces.
+            ("\u{E20}\u{E32}\u{E29}\u{E32}\u{E44}\u{E17}\u{E22} \u{E14}\u{E35}", &[
+                "\u{E20}\u{E32}\u{E29}\u{E32}\u{E44}\u{E17}\u{E22}", "\u{E14}\u{E35}",
+            ]),
+            ("a\u{200B}b", &["a\u{200B}", "b"]),
+        ];
+        for &(text, parts) in cases {
+            assert_eq!(break_parts(text), parts, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn vertical_columns_keep_source_offsets() {
+        let layout = layout();
+        let sources = |text: &str| -> Vec<usize> {
+            layout.vertical_quads(text, [0, 0]).iter().map(|quad| quad.source).collect()
+        };
+        assert_eq!(sources("ab\ncd"), [0, 1, 3, 4]);
+        // The stripped carriage return still counts.
+        assert_eq!(sources("ab\r\ncd"), [0, 1, 4, 5]);
+        let quads = layout.vertical_quads("a\r\nb", [0, 0]);
+        assert!(quads[1].pos[0] < quads[0].pos[0]);
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn shaped_pairs_are_kerned() {
+        let layout = layout();
+        assert!(layout.kerning('A', 'V') < 0);
+        assert_eq!(layout.kerning('H', 'H'), 0);
+        let advance = |text: &str| layout.caret_positions(text).last().unwrap().1;
+        assert_eq!(advance("AV"), advance("A") + advance("V") + layout.kerning('A', 'V'));
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn clusters_missing_in_atlas_are_laid_out_per_char() {
+        let atlas = FontBuilder::new().with_chars(&['f', 'i']).build().unwrap();
+        let layout = TextLayout::new(atlas);
+        let quads = layout.line_quads("fi", [0, 0]);
+        let sources: Vec<usize> = quads.iter().map(|quad| quad.source).collect();
+        assert_eq!(sources, [0, 1]);
+    }
+}
@@ -8,7 +8,7 @@
 //!
 //! ```ignore
 //! // Initialize text renderer.
-//! let mut text = gfx_text::new(factory).build().unwrap();
+//! let mut text = gfx_text::new().build(&mut factory).unwrap();
 //!
 //! // In render loop:
 //!
@@ -29,23 +29,69 @@
 // extern crate log;
 #[macro_use]
 extern crate gfx;
+#[cfg(not(feature = "freetype-face"))]
 extern crate freetype;
+/// FreeType bindings of the faces returned by `Renderer::font_face`.
+#[cfg(feature = "freetype-face")]
+pub extern crate freetype;
+extern crate unicode_bidi;
+extern crate unicode_segmentation;
+extern crate unicode_linebreak;
+#[cfg(feature = "shaping")]
+extern crate rustybuzz;
+#[cfg(feature = "serde")]
+extern crate serde;
 
+use std::borrow::Cow;
+use std::cmp::{max, min};
 use std::collections::hash_map::{Entry, HashMap};
-use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use gfx::{CombinedError, CommandBuffer, Encoder, Factory, PipelineStateError, Resources, UpdateError};
 use gfx::shade::ProgramError;
-use gfx::handle::{Buffer, RenderTargetView};
+use gfx::handle::{Buffer, DepthStencilView, RawRenderTargetView, RenderTargetView, ShaderResourceView};
 use gfx::pso::PipelineState;
 use gfx::texture;
 use gfx::traits::FactoryExt;
+mod cache;
+mod colr;
+mod config;
+#[cfg(feature = "console")]
+mod console;
+mod cursor;
 mod font;
-use font::BitmapFont;
-pub use font::FontError;
+mod fps;
+mod layout;
+mod marquee;
+mod msdf;
+mod scene;
+mod shaders;
+#[cfg(test)]
+mod test_factory;
+mod text_edit;
+#[cfg(feature = "shaping")]
+mod shaping;
+use font::{FaceInfo, FontSource, SyntheticStyle};
+pub use config::RendererConfig;
+#[cfg(feature = "console")]
+pub use console::{Console, LogLevel};
+pub use cursor::TextCursor;
+pub use font::{BitmapChar, BitmapFont, Charmap, ColorLayer, FontError, GlyphMetrics, ScriptMetrics};
+pub use fps::FpsCounter;
+pub use layout::{GlyphQuad, Hyphenator, ImageFormat, TextLayout, WrappedLine};
+pub use marquee::Marquee;
+pub use scene::TextScene;
+pub use shaders::ShaderVersion;
+pub use text_edit::TextEditVisual;
 
 const DEFAULT_FONT_SIZE: u8 = 16;
 const DEFAULT_BUFFER_SIZE: usize = 128;
-const DEFAULT_OUTLINE_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+// Maximum amount of glyph instances per draw call. Same number of glyphs as
+// the 65536 vertices of four per glyph before instancing, about 1 MiB of
+// 72 byte instances, so a full buffer stays cheap to stream every frame.
+const DEFAULT_MAX_BUFFER_SIZE: usize = 16384;
+const DEFAULT_BUFFER_COUNT: usize = 3;
+const DEFAULT_TAB_WIDTH: u8 = 8;
 const DEFAULT_PROJECTION: [[f32; 4]; 4] = [
     [1.0, 0.0, 0.0, 0.0],
     [0.0, 1.0, 0.0, 0.0],
@@ -73,10 +119,12 @@ pub enum Error {
     CombinedError(CombinedError),
     /// An error occuring in buffer/texture updates
     UpdateError(UpdateError<usize>),
+    /// An error occuring during creation of vertex or instance buffer
+    BufferCreation(gfx::buffer::CreationError),
 }
 
 /// An anchor aligns text horizontally to its given x position.
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum HorizontalAnchor {
     /// Anchor the left edge of the text
     Left,
@@ -84,10 +132,27 @@ pub enum HorizontalAnchor {
     Center,
     /// Anchor the right edge of the text
     Right,
+    /// Anchor the edge where the text starts, the right one for
+    /// right-to-left text
+    Start,
+    /// Anchor the edge where the text ends, the left one for right-to-left
+    /// text
+    End,
+}
+
+impl HorizontalAnchor {
+    // Replace the direction dependent anchors with the fixed ones.
+    fn resolve(self, rtl: bool) -> HorizontalAnchor {
+        match (self, rtl) {
+            (HorizontalAnchor::Start, false) | (HorizontalAnchor::End, true) => HorizontalAnchor::Left,
+            (HorizontalAnchor::Start, true) | (HorizontalAnchor::End, false) => HorizontalAnchor::Right,
+            (anchor, _) => anchor,
+        }
+    }
 }
 
 /// An anchor aligns text vertically to its given y position.
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum VerticalAnchor {
     /// Anchor the top edge of the text
     Top,
@@ -95,6 +160,469 @@ pub enum VerticalAnchor {
     Center,
     /// Anchor the bottom edge of the text
     Bottom,
+    /// Anchor the mid-point of capital letters, between the baseline and
+    /// the cap height, to center all-caps text optically
+    CapHeight,
+    /// Anchor the mid-point of lowercase letters, between the baseline and
+    /// the x-height
+    XHeight,
+}
+
+/// Origin of the screen coordinates (see `RendererBuilder::with_origin`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum Origin {
+    /// Top left corner of the screen, Y axis goes down
+    TopLeft,
+    /// Bottom left corner of the screen, Y axis goes up
+    BottomLeft,
+}
+
+/// Alignment of lines within a text block (see `Renderer::add_block`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    /// Align lines to the left edge of the block
+    Left,
+    /// Center lines within the block
+    Center,
+    /// Align lines to the right edge of the block
+    Right,
+    /// Stretch spaces so lines fill the whole block width, except the last
+    /// line of every paragraph which is aligned to the start
+    Justify,
+    /// Align lines to the edge where the text starts, the right one for
+    /// right-to-left text
+    Start,
+    /// Align lines to the edge where the text ends, the left one for
+    /// right-to-left text
+    End,
+}
+
+impl TextAlign {
+    // Replace the direction dependent alignments with the fixed ones.
+    fn resolve(self, rtl: bool) -> TextAlign {
+        match (self, rtl) {
+            (TextAlign::Start, false) | (TextAlign::End, true) => TextAlign::Left,
+            (TextAlign::Start, true) | (TextAlign::End, false) => TextAlign::Right,
+            (align, _) => align,
+        }
+    }
+}
+
+/// Base direction of the added text, used to order bidirectional text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextDirection {
+    /// Detect direction from the first strong character of the text
+    Auto,
+    /// Left-to-right text
+    LeftToRight,
+    /// Right-to-left text
+    RightToLeft,
+}
+
+/// How control characters (other than newlines and tabs) in the added text
+/// are drawn, see `Renderer::set_control_chars`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlChars {
+    /// Skip them
+    Strip,
+    /// Draw symbols from the Unicode Control Pictures block, e.g. "␍", if
+    /// the font has them
+    Visualize,
+}
+
+/// Case transformation applied to the added text at layout time, see
+/// `Renderer::set_text_transform`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextTransform {
+    /// Draw the text as is
+    None,
+    /// Draw all letters in upper case
+    Uppercase,
+    /// Draw all letters in lower case
+    Lowercase,
+    /// Draw lower case letters as small capitals, either from the font if it
+    /// has them (requires the `shaping` feature) or synthesized by scaling
+    /// down capitals
+    SmallCaps,
+}
+
+/// Font style of the added text, see `RendererBuilder::with_style_font`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    /// Regular font
+    Regular,
+    /// Bold font
+    Bold,
+    /// Italic font
+    Italic,
+    /// Bold italic font
+    BoldItalic,
+}
+
+impl Style {
+    // Slot of the style in `FontAtlas::style_faces`.
+    fn slot(self) -> usize {
+        match self {
+            Style::Regular => 0,
+            Style::Bold => 1,
+            Style::Italic => 2,
+            Style::BoldItalic => 3,
+        }
+    }
+
+    fn synthetic(self) -> SyntheticStyle {
+        SyntheticStyle {
+            bold: self == Style::Bold || self == Style::BoldItalic,
+            oblique: self == Style::Italic || self == Style::BoldItalic,
+            stroke: 0,
+        }
+    }
+}
+
+/// Vertical position and size of a text span relative to the surrounding
+/// text, see `TextSpan`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BaselineShift {
+    /// On the baseline at the font size
+    None,
+    /// Raised and scaled down as recommended by the font, e.g. for exponents
+    /// and footnote markers
+    Superscript,
+    /// Lowered and scaled down as recommended by the font, e.g. for chemical
+    /// formulas
+    Subscript,
+    /// Raised by the given number of pixels (lowered if negative) and scaled
+    /// by the given factor around the baseline
+    Custom(i32, f32),
+}
+
+/// Background panel drawn behind a text block, see
+/// `Renderer::add_block_with_panel`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Panel {
+    /// Filled rectangle
+    Solid,
+    /// Filled rectangle with corners rounded by the radius in pixels, made
+    /// of one pixel high strips, so the corners are not antialiased
+    Rounded(i32),
+    /// Image of the custom glyph of the char (see `Renderer::register_glyph`)
+    /// cut into 9 slices: corners of the border size in pixels are drawn as
+    /// is, edges are stretched along the panel sides and the center fills
+    /// the rest
+    NineSlice {
+        /// Char the image is registered for
+        ch: char,
+        /// Size of the corners in pixels
+        border: i32,
+    },
+}
+
+/// Alignment of the cells within a table column, see `Renderer::add_table`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnAlign {
+    /// Align cells to the left edge of the column
+    Left,
+    /// Align cells to the right edge of the column
+    Right,
+    /// Align decimal points of the cells above each other, cells without
+    /// one are aligned as if it followed them
+    Decimal,
+}
+
+/// Width and alignment of a table column, see `Renderer::add_table`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableColumn {
+    /// Width of the column in pixels, or `None` to fit the widest cell
+    pub width: Option<i32>,
+    /// Alignment of the cells
+    pub align: ColumnAlign,
+}
+
+impl TableColumn {
+    /// Create a column fitting its widest cell.
+    pub fn new(align: ColumnAlign) -> TableColumn {
+        TableColumn {
+            width: None,
+            align: align,
+        }
+    }
+
+    /// Set fixed width of the column in pixels.
+    pub fn with_width(mut self, width: i32) -> TableColumn {
+        self.width = Some(width);
+        self
+    }
+}
+
+/// Conversion state of a clause of the text being composed by an input
+/// method, marked by its underline, see `Renderer::add_composition`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClauseStyle {
+    /// Text typed but not converted yet, underlined with dots
+    Unconverted,
+    /// Converted text, underlined with a thin line
+    Converted,
+    /// Clause being converted now, underlined with a thick line
+    Target,
+}
+
+/// Byte range of an input method composition string with its conversion
+/// state, as given by the platform, see `Renderer::add_composition`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompositionClause {
+    /// Byte range of the clause in the composition string
+    pub range: ::std::ops::Range<usize>,
+    /// Conversion state of the clause
+    pub style: ClauseStyle,
+}
+
+/// Part of a line of rich text with its own color, style and baseline, see
+/// `Renderer::add_spans`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextSpan<'a> {
+    /// Text of the span
+    pub text: &'a str,
+    /// Color of the text
+    pub color: [f32; 4],
+    /// Font style of the text
+    pub style: Style,
+    /// Vertical position and size of the text
+    pub baseline: BaselineShift,
+}
+
+impl<'a> TextSpan<'a> {
+    /// Create a span of regular text on the baseline.
+    pub fn new<C: Into<Color>>(text: &'a str, color: C) -> TextSpan<'a> {
+        let color = color.into().0;
+        TextSpan {
+            text: text,
+            color: color,
+            style: Style::Regular,
+            baseline: BaselineShift::None,
+        }
+    }
+
+    /// Set font style of the span.
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Set vertical position and size of the span.
+    pub fn with_baseline(mut self, baseline: BaselineShift) -> Self {
+        self.baseline = baseline;
+        self
+    }
+}
+
+/// Visual style of text kept in the renderer under a name, see
+/// `Renderer::define_style`. Glow radius is a setting of the font, see
+/// `RendererBuilder::with_glow`, only its color may differ per style.
+/// Outline widths must be added to the font, see
+/// `RendererBuilder::with_outline`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    /// Color of the text
+    pub color: [f32; 4],
+    /// Font style of the text
+    pub style: Style,
+    /// Offset in pixels and color of the shadow drawn below the text
+    pub shadow: Option<([i32; 2], [f32; 4])>,
+    /// Color of the glow, the one set with `Renderer::set_glow` if `None`
+    pub glow: Option<[f32; 4]>,
+    /// Width and color of the outline, the one set with
+    /// `Renderer::set_outline` if `None`
+    pub outline: Option<(u8, [f32; 4])>,
+}
+
+impl TextStyle {
+    /// Create a style of regular text without shadow.
+    pub fn new<C: Into<Color>>(color: C) -> TextStyle {
+        TextStyle {
+            color: color.into().0,
+            style: Style::Regular,
+            shadow: None,
+            glow: None,
+            outline: None,
+        }
+    }
+
+    /// Set font style of the text.
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Draw a shadow below the text moved by the offset in pixels.
+    pub fn with_shadow<C: Into<Color>>(mut self, offset: [i32; 2], color: C) -> Self {
+        self.shadow = Some((offset, color.into().0));
+        self
+    }
+
+    /// Set color of the glow drawn behind glyphs of fonts built with glow,
+    /// e.g. dark for damage numbers and blue for HUD text of one renderer.
+    pub fn with_glow<C: Into<Color>>(mut self, color: C) -> Self {
+        self.glow = Some(color.into().0);
+        self
+    }
+
+    /// Draw an outline of the given width and color under the text, e.g. a
+    /// thick one for titles and a thin one for labels.
+    pub fn with_outline<C: Into<Color>>(mut self, width: u8, color: C) -> Self {
+        self.outline = Some((width, color.into().0));
+        self
+    }
+}
+
+/// Text color as normalized RGBA floats. Color parameters of the API take
+/// anything convertible into it: `[f32; 4]`, `[u8; 4]` or a `0xRRGGBBAA`
+/// number. Colors are sRGB values and look the same on render targets with
+/// linear and sRGB formats, e.g. `Rgba8` and `Srgba8`.
+///
+/// # Examples
+///
+/// ```ignore
+/// text.add("Hello", [10, 10], Color::WHITE);
+/// text.add("Hello", [10, 30], [255, 128, 0, 255]);
+/// text.add("Hello", [10, 50], 0xff8000ff);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub [f32; 4]);
+
+impl Color {
+    /// Opaque white.
+    pub const WHITE: Color = Color([1.0, 1.0, 1.0, 1.0]);
+    /// Opaque black.
+    pub const BLACK: Color = Color([0.0, 0.0, 0.0, 1.0]);
+    /// Opaque red.
+    pub const RED: Color = Color([1.0, 0.0, 0.0, 1.0]);
+    /// Opaque green.
+    pub const GREEN: Color = Color([0.0, 1.0, 0.0, 1.0]);
+    /// Opaque blue.
+    pub const BLUE: Color = Color([0.0, 0.0, 1.0, 1.0]);
+    /// Opaque yellow.
+    pub const YELLOW: Color = Color([1.0, 1.0, 0.0, 1.0]);
+    /// Fully transparent black.
+    pub const TRANSPARENT: Color = Color([0.0, 0.0, 0.0, 0.0]);
+
+    /// Create a color from 8-bit channels.
+    pub fn rgba8(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0])
+    }
+
+    /// Return the same color with another alpha.
+    pub fn with_alpha(self, alpha: f32) -> Color {
+        let Color([r, g, b, _]) = self;
+        Color([r, g, b, alpha])
+    }
+}
+
+impl From<[f32; 4]> for Color {
+    fn from(color: [f32; 4]) -> Color {
+        Color(color)
+    }
+}
+
+impl From<[u8; 4]> for Color {
+    fn from(color: [u8; 4]) -> Color {
+        Color::rgba8(color[0], color[1], color[2], color[3])
+    }
+}
+
+/// Reads `0xRRGGBBAA`.
+impl From<u32> for Color {
+    fn from(color: u32) -> Color {
+        Color::rgba8((color >> 24) as u8, (color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(color: Color) -> [f32; 4] {
+        color.0
+    }
+}
+
+/// Describes how text is combined with the render target contents.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BlendMode {
+    /// Usual transparency, target is expected to have straight alpha
+    Alpha,
+    /// Transparency for targets with premultiplied alpha
+    Premultiplied,
+    /// Add text color scaled by its alpha to the target, useful for glowing
+    /// text
+    Additive,
+    /// Write text color and coverage without blending, e.g. into an offscreen
+    /// mask
+    Replace,
+}
+
+impl BlendMode {
+    fn to_blend(self) -> Option<gfx::state::Blend> {
+        use gfx::state::{Blend, BlendChannel, BlendValue, Equation, Factor};
+
+        let channel = |source, destination| BlendChannel {
+            equation: Equation::Add,
+            source: source,
+            destination: destination,
+        };
+        match self {
+            BlendMode::Alpha => Some(gfx::preset::blend::ALPHA),
+            BlendMode::Premultiplied => Some(Blend {
+                color: channel(Factor::ZeroPlus(BlendValue::SourceAlpha),
+                               Factor::OneMinus(BlendValue::SourceAlpha)),
+                alpha: channel(Factor::One, Factor::OneMinus(BlendValue::SourceAlpha)),
+            }),
+            BlendMode::Additive => Some(Blend {
+                color: channel(Factor::ZeroPlus(BlendValue::SourceAlpha), Factor::One),
+                alpha: channel(Factor::One, Factor::One),
+            }),
+            BlendMode::Replace => None,
+        }
+    }
+}
+
+/// Renderer statistics, see `Renderer::stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// Current capacity of the largest glyph buffer (in glyphs)
+    pub buffer_capacity: usize,
+    /// Width of the font texture atlas in pixels
+    pub atlas_width: u16,
+    /// Height of the font texture atlas in pixels
+    pub atlas_height: u16,
+    /// Fraction of the atlas area covered by glyphs, from 0.0 to 1.0
+    pub atlas_occupancy: f32,
+    /// Number of glyphs queued for the next draw
+    pub glyphs_queued: usize,
+    /// Number of draw calls issued by the last draw
+    pub draw_calls: usize,
+    /// Number of glyphs (including backgrounds and glows) drawn by the last
+    /// draw
+    pub glyphs_drawn: usize,
+    /// Number of bytes of glyph data uploaded to the GPU by the last draw
+    pub bytes_uploaded: usize,
+    /// Number of times the last draw bound a pipeline different from the
+    /// previous one, e.g. after a change of the blend mode or the target
+    /// format
+    pub pipeline_switches: usize,
+    /// Time spent laying out text added for the last draw, not counting
+    /// lines laid out earlier and taken from the cache
+    pub layout_time: ::std::time::Duration,
+}
+
+/// Strategy used to resolve screen-space overlaps between world-anchored
+/// labels (see `RendererBuilder::with_declutter`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Declutter {
+    /// Draw all labels, overlapping or not
+    Off,
+    /// Hide labels which overlap a label with higher priority
+    Hide,
+    /// Move overlapping labels down below the higher priority ones, hiding
+    /// them only if there is still no free space
+    Offset,
 }
 
 impl From<FontError> for Error {
@@ -117,419 +645,3603 @@ impl From<UpdateError<usize>> for Error {
     fn from(e: UpdateError<usize>) -> Error { Error::UpdateError(e) }
 }
 
-type IndexT = u32;
+impl From<gfx::buffer::CreationError> for Error {
+    fn from(e: gfx::buffer::CreationError) -> Error { Error::BufferCreation(e) }
+}
+
+static NEXT_RENDERER_ID: AtomicUsize = AtomicUsize::new(0);
+
+// OpenType feature tag and value.
+type FeatureTag = ([u8; 4], u32);
+
+// Target format, blend mode and whether the target is multisampled.
+type PsoKey = (gfx::format::Format, BlendMode, bool);
+
+// How many times `Declutter::Offset` tries to shift a label before giving up
+// and hiding it.
+const DECLUTTER_OFFSET_ATTEMPTS: usize = 4;
+
+/// Change of a single glyph applied by `Renderer::add_animated`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphOffset {
+    /// Offset of the glyph in pixels
+    pub pos: [f32; 2],
+    /// Multiplier of the glyph alpha
+    pub alpha: f32,
+}
+
+impl Default for GlyphOffset {
+    fn default() -> GlyphOffset {
+        GlyphOffset { pos: [0.0, 0.0], alpha: 1.0 }
+    }
+}
+
+/// Text just added to the draw scene, returned by `Renderer::add` and
+/// similar methods.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddedText {
+    /// Id of the text, see `Renderer::hit_test`.
+    pub id: TextId,
+    /// Rectangle (x, y, width, height) of the line box in the coordinates
+    /// of the text position, e.g. to draw a background behind the text.
+    /// For world labels it's relative to the projected label position,
+    /// with the Y axis going down.
+    pub rect: [i32; 4],
+}
+
+/// Handle of a text laid out in advance, see `Renderer::cache_text`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CachedText(usize);
+
+/// Number of a text added to the current draw scene, see
+/// `Renderer::hit_test`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextId(usize);
+
+// Glyphs of a cached text laid out at the origin.
+struct CachedLayout {
+    quads: Vec<GlyphQuad>,
+    // Source of the quads, to lay them out again with another font: the
+    // text and the block width and alignment, if it's a block.
+    text: String,
+    block: Option<(i32, TextAlign)>,
+    // Byte offsets of the ends of grapheme clusters.
+    cluster_ends: Vec<usize>,
+}
+
+// Geometry of a single world-anchored label queued for drawing.
+#[derive(Clone)]
+struct WorldLabel {
+    // Index of the span of the label's glyphs.
+    span: usize,
+    world_pos: [f32; 3],
+    // Bounds of glyph quads relative to the label origin: min x, min y,
+    // max x, max y.
+    bounds: [f32; 4],
+    priority: i32,
+}
 
 /// Text renderer.
 pub struct Renderer<R: Resources, F: Factory<R>> {
+    // Unique ID to distinguish renderers in `TextScene`.
+    id: usize,
     factory: F,
-    pso_map: HashMap<gfx::format::Format, PipelineState<R, pipe::Meta>>,
+    pso_map: HashMap<PsoKey, PipelineState<R, pipe::Meta>>,
+    depth_stencil_pso_map: HashMap<PsoKey, PipelineState<R, pipe_depth_stencil::Meta>>,
+    depth_stencil: (gfx::state::Depth, gfx::state::Stencil),
     shaders: gfx::ShaderSet<R>,
-    vertex_data: Vec<Vertex>,
-    vertex_buffer: Buffer<R, Vertex>,
-    index_data: Vec<IndexT>,
-    index_buffer: Buffer<R, IndexT>,
-    font_bitmap: BitmapFont,
+    shader_version: ShaderVersion,
+    blend: BlendMode,
+    // Static unit quad which is instanced for every glyph, or repeated for
+    // every glyph of the largest chunk if instancing isn't supported.
+    quad_buffer: Buffer<R, Vertex>,
+    quad_slice: gfx::Slice<R>,
+    // Whether the device supports per-instance attributes. Otherwise every
+    // instance is uploaded once per corner of its quad and drawn as plain
+    // vertices, e.g. on OpenGL 2.1 and OpenGL ES 2.0.
+    instanced: bool,
+    instance_data: Vec<Instance>,
+    // Ring of instance buffers used by draw calls in turn, so a buffer isn't
+    // updated while the GPU may still read it for a previous draw.
+    instance_buffers: Vec<InstanceBuffer<R>>,
+    next_buffer: usize,
+    // Whether instances are uploaded as `PackedInstance`.
+    packed: bool,
+    locals: Buffer<R, Locals>,
+    // Starting glyph and layer of every text added to the scene.
+    spans: Vec<usize>,
+    layers: Vec<i32>,
+    // Fraction of the viewport size added to the position of every text, see
+    // `Renderer::add_normalized`.
+    fractions: Vec<[f32; 2]>,
+    layer: i32,
+    min_buffer_size: usize,
+    max_buffer_size: usize,
+    // Shrink the instance buffer after that many frames of low usage, zero
+    // disables shrinking.
+    shrink_after: usize,
+    low_usage_frames: usize,
+    low_usage_peak: usize,
+    draw_calls: usize,
+    glyphs_drawn: usize,
+    bytes_uploaded: usize,
+    pipeline_switches: usize,
+    layout_time: ::std::time::Duration,
+    // Pipeline used by the last draw call and whether it has depth-stencil.
+    bound_pso: Option<(PsoKey, bool)>,
+    layout: TextLayout,
     color: (gfx::handle::ShaderResourceView<R, f32>, gfx::handle::Sampler<R>),
+    declutter: Declutter,
+    labels: Vec<WorldLabel>,
+    cached: Vec<Option<CachedLayout>>,
+    virtual_size: Option<(u16, u16)>,
+    origin: Origin,
+    // Offset of all glyphs in target pixels, see
+    // `RendererBuilder::with_pixel_offset`.
+    pixel_offset: [f32; 2],
+    // Color of the glow drawn behind glyphs, see `RendererBuilder::with_glow`.
+    glow: Option<[f32; 4]>,
+    // Color of the glyph outlines of the layout's outline width, see
+    // `RendererBuilder::with_outline`.
+    outline: Option<[f32; 4]>,
+    // Color all text is multiplied by when drawn.
+    tint: [f32; 4],
+    // Color and anchors of the text added by `add_default`.
+    default_color: [f32; 4],
+    default_anchor: (HorizontalAnchor, VerticalAnchor),
+    // Offset of all screen positions, see `Renderer::set_origin_offset`.
+    origin_offset: [i32; 2],
+    // Reused for the text formatted by `add_fmt` and `add_chars`.
+    scratch: String,
+    // Settings the font was built with, unless it was built in advance. User
+    // font data is kept shared, so the font may be built again at another
+    // size without copying it.
+    font_settings: Option<FontBuilder<'static>>,
+    // Named styles, see `Renderer::define_style`.
+    styles: HashMap<String, TextStyle>,
 }
 
-/// Text renderer builder. Allows to set rendering options using builder
-/// pattern.
+// Instance buffer of the ring.
+struct InstanceBuffer<R: Resources> {
+    // Buffer of `Instance` or `PackedInstance` elements.
+    buffer: gfx::handle::RawBuffer<R>,
+    // Number of instances the buffer holds.
+    len: usize,
+    // Copy of the glyph data currently stored in the buffer, used to upload
+    // only changed spans.
+    uploaded: Vec<Instance>,
+}
+
+/// Rasterized font glyphs and their metrics. Used for the text layout and
+/// uploaded to the GPU by `RendererBuilder::with_built_font`.
+#[derive(Clone)]
+pub struct FontAtlas {
+    bitmap: BitmapFont,
+    // Number of the font face used for every style, see `Style::slot`.
+    style_faces: [usize; 4],
+    #[cfg(feature = "shaping")]
+    // Shaper of every face, `None` for faces which are not OpenType fonts.
+    shapers: Vec<Option<shaping::Shaper>>,
+    // Whether the atlas has small capitals of every face.
+    #[cfg(feature = "shaping")]
+    small_caps: Vec<bool>,
+    // Distance between tab stops in pixels.
+    tab_width: i32,
+    // Distance from the top of the line to the baseline, in pixels.
+    baseline: i32,
+    // Indices of the glyphs placed into the atlas by their names.
+    glyph_names: HashMap<String, u32>,
+}
+
+impl FontAtlas {
+    /// Use the font texture built or loaded by user, e.g. to construct a
+    /// renderer with `Renderer::from_font`. All styles use the regular face
+    /// and text is not shaped even if the `shaping` feature is enabled.
+    pub fn from_bitmap(bitmap: BitmapFont) -> FontAtlas {
+        // The baseline is at the pixel size of the regular face.
+        let font_size = bitmap.get_baseline() as i32;
+        let tab_width = tab_stop_width(&bitmap, font_size, DEFAULT_TAB_WIDTH);
+        FontAtlas {
+            bitmap: bitmap,
+            style_faces: [0; 4],
+            #[cfg(feature = "shaping")]
+            shapers: Vec::new(),
+            #[cfg(feature = "shaping")]
+            small_caps: Vec::new(),
+            tab_width: tab_width,
+            baseline: font_size,
+            glyph_names: HashMap::new(),
+        }
+    }
+
+    /// Return the font texture and glyph metrics.
+    pub fn bitmap(&self) -> &BitmapFont {
+        &self.bitmap
+    }
+
+    /// Return width of the atlas texture.
+    pub fn width(&self) -> u16 {
+        self.bitmap.get_width()
+    }
+
+    /// Return height of the atlas texture.
+    pub fn height(&self) -> u16 {
+        self.bitmap.get_height()
+    }
+
+    /// Return 8-bit texture data row by row from the top, grayscale or RGBA
+    /// with distance fields.
+    pub fn image(&self) -> &[u8] {
+        self.bitmap.get_image()
+    }
+
+    /// Return index of the regular glyph with the name given to
+    /// `RendererBuilder::with_glyph_names`, `None` if the font doesn't have
+    /// it.
+    pub fn glyph_index_by_name(&self, name: &str) -> Option<u32> {
+        self.glyph_names.get(name).cloned()
+    }
+
+    /// Return distance between the lines of text.
+    pub fn line_height(&self) -> i32 {
+        self.bitmap.get_font_height() as i32
+    }
+
+    /// Return height of capital letters above the baseline, measured from
+    /// the "H" glyph, or estimated if the atlas doesn't have it.
+    pub fn cap_height(&self) -> i32 {
+        self.glyph_height('H', 70)
+    }
+
+    /// Return height of lowercase letters above the baseline, measured from
+    /// the "x" glyph like `cap_height`.
+    pub fn x_height(&self) -> i32 {
+        self.glyph_height('x', 50)
+    }
+
+    // Height of the regular glyph of the char above the baseline, or the
+    // given percentage of the font size if the glyph is missing.
+    fn glyph_height(&self, ch: char, percent: i32) -> i32 {
+        // Distance fields pad glyphs by their range.
+        let padding = self.bitmap.get_distance_range() as i32;
+        self.bitmap.find_char(0, ch)
+            .filter(|ch_info| ch_info.height > 0)
+            .map_or(self.baseline * percent / 100, |ch_info| self.baseline - ch_info.y_offset - padding)
+    }
+
+    // Return raise in pixels and scale of the text with the baseline shift.
+    fn baseline_shift(&self, baseline: BaselineShift) -> (i32, f32) {
+        // NOTE: Typical values of the OS/2 table for fonts without one.
+        let font_size = self.baseline;
+        let metrics = self.bitmap.get_script_metrics().unwrap_or(ScriptMetrics {
+            superscript_size: font_size * 65 / 100,
+            superscript_offset: font_size * 35 / 100,
+            subscript_size: font_size * 65 / 100,
+            subscript_offset: font_size * 15 / 100,
+        });
+        let scale = |size: i32| if font_size > 0 { size as f32 / font_size as f32 } else { 1.0 };
+        match baseline {
+            BaselineShift::None => (0, 1.0),
+            BaselineShift::Superscript => (metrics.superscript_offset, scale(metrics.superscript_size)),
+            BaselineShift::Subscript => (-metrics.subscript_offset, scale(metrics.subscript_size)),
+            BaselineShift::Custom(raise, scale) => (raise, scale),
+        }
+    }
+}
+
+// Font file given to the font builder.
+#[derive(Clone)]
+enum FontFile<'r> {
+    Path(Cow<'r, str>),
+    Data(Cow<'r, [u8]>),
+    // Data shared with the user and between copies of the settings.
+    Shared(Arc<[u8]>),
+}
+
+impl<'r> FontFile<'r> {
+    fn source<'a>(&'a self) -> FontSource<'a> {
+        match *self {
+            FontFile::Path(ref path) => FontSource::Path(path),
+            FontFile::Data(ref data) => FontSource::Data(data),
+            FontFile::Shared(ref data) => FontSource::Data(data),
+        }
+    }
+
+    // Borrowed data is copied once into the shared one, unless it's the
+    // bundled font.
+    fn to_static(&self) -> FontFile<'static> {
+        match *self {
+            FontFile::Path(ref path) => FontFile::Path(Cow::Owned(path.clone().into_owned())),
+            FontFile::Data(ref data) => match DEFAULT_FONT_DATA {
+                Some(default) if ::std::ptr::eq(default, &data[..]) => FontFile::Data(Cow::Borrowed(default)),
+                _ => FontFile::Shared(Arc::from(&data[..])),
+            },
+            FontFile::Shared(ref data) => FontFile::Shared(data.clone()),
+        }
+    }
+}
+
+/// Font builder. Rasterizing the glyphs may take a while for big char sets,
+/// so unlike `RendererBuilder` it doesn't need the factory and may be used
+/// on a worker thread. Font settings are the same as of `RendererBuilder`.
 ///
 /// # Examples
 ///
 /// ```ignore
-/// let mut text = gfx_text::RendererBuilder::new(factory)
-///     .with_size(25)
-///     .with_font("/path/to/font.ttf")
-///     .with_chars(&['a', 'b', 'c'])
-///     .build()
-///     .unwrap();
+/// let worker = std::thread::spawn(|| {
+///     gfx_text::FontBuilder::new().with_size(25).with_font("/path/to/font.ttf").build()
+/// });
+/// // ... show the loading screen ...
+/// let font = worker.join().unwrap().unwrap();
+/// let mut text = gfx_text::new().with_built_font(font).build(&mut factory).unwrap();
 /// ```
-pub struct RendererBuilder<'r, R: Resources, F: Factory<R>> {
-    factory: F,
+#[derive(Clone)]
+pub struct FontBuilder<'r> {
     font_size: u8,
     // NOTE(Kagami): Better to use `P: AsRef<OsStr>` but since we store path in
     // the intermediate builder structure, Rust will unable to infer type
     // without manual annotation which is much worse. Anyway, it's possible to
     // just pass raw bytes.
-    font_path: Option<&'r str>,
-    font_data: Option<&'r [u8]>,
-    outline_width: Option<u8>,
-    outline_color: [f32; 4],
-    buffer_size: usize,
-    chars: Option<&'r [char]>,
-    // XXX(Kagami): Shut up the Rust complains about unused R. We can't use
-    // just `factory: &mut Factory<R>` because it doesn't work with lifetimes
-    // (complains about the Marker associated type). Is there any better way?
-    _r: PhantomData<R>,
+    // Regular font and index of its face in a font collection.
+    font: Option<(FontFile<'r>, u32)>,
+    // Widths of the outlines stroked around the glyphs.
+    outline_widths: Vec<u8>,
+    chars: Option<Cow<'r, [char]>>,
+    // Names of the glyphs placed into the texture besides the chars.
+    glyph_names: Vec<String>,
+    tab_width: u8,
+    variations: Vec<([u8; 4], f32)>,
+    synthetic: SyntheticStyle,
+    charmap: Charmap,
+    // Fonts of the additional styles, synthesized from the regular font if
+    // not set.
+    styles: Vec<(Style, Option<(FontFile<'r>, u32)>)>,
+    cache_dir: Option<Cow<'r, str>>,
+    glow_radius: u8,
+    // Stroke width of the hollow glyphs, zero for filled ones.
+    hollow: u8,
+    // Range of the multi-channel distance fields, zero for coverage.
+    distance_range: u8,
+    max_texture_size: u16,
 }
 
-/// Create a new text renderer builder. Alias for `RendererBuilder::new`.
-pub fn new<'r, R: Resources, F: Factory<R>>(factory: F) -> RendererBuilder<'r, R, F> {
-    RendererBuilder::new(factory)
+impl<'r> Default for FontBuilder<'r> {
+    fn default() -> Self {
+        FontBuilder::new()
+    }
 }
 
-impl<'r, R: Resources, F: Factory<R>> RendererBuilder<'r, R, F> {
-    /// Create a new text renderer builder.
-    pub fn new(factory: F) -> Self {
-        // Default renderer settings.
-        RendererBuilder {
-            factory: factory,
+impl<'r> FontBuilder<'r> {
+    /// Create a new font builder.
+    pub fn new() -> Self {
+        // Default font settings.
+        FontBuilder {
             font_size: DEFAULT_FONT_SIZE,
-            font_path: None,  // Default font will be used
-            font_data: DEFAULT_FONT_DATA,
-            outline_width: None,  // No outline by default
-            outline_color: DEFAULT_OUTLINE_COLOR,
-            buffer_size: DEFAULT_BUFFER_SIZE,
+            font: DEFAULT_FONT_DATA.map(|data| (FontFile::Data(Cow::Borrowed(data)), 0)),
+            outline_widths: Vec::new(),  // No outline by default
             chars: None,  // Place all available font chars into texture
-            _r: PhantomData,
+            glyph_names: Vec::new(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            variations: Vec::new(),
+            synthetic: SyntheticStyle::default(),
+            charmap: Charmap::Unicode,
+            styles: Vec::new(),
+            cache_dir: None,  // Always build the texture
+            glow_radius: 0,
+            hollow: 0,
+            distance_range: 0,
+            max_texture_size: font::DEFAULT_MAX_SIZE,
         }
     }
 
-    /// Specify custom size.
+    /// See `RendererBuilder::with_size`.
     pub fn with_size(mut self, size: u8) -> Self {
         self.font_size = size;
         self
     }
 
-    /// Specify custom font by path.
-    pub fn with_font(mut self, path: &'r str) -> Self {
-        self.font_path = Some(path);
+    /// See `RendererBuilder::with_font`.
+    pub fn with_font(self, path: &'r str) -> Self {
+        self.with_font_index(path, 0)
+    }
+
+    /// See `RendererBuilder::with_font_index`.
+    pub fn with_font_index(mut self, path: &'r str, index: u32) -> Self {
+        self.font = Some((FontFile::Path(Cow::Borrowed(path)), index));
         self
     }
 
-    /// Pass raw font data.
-    pub fn with_font_data(mut self, data: &'r [u8]) -> Self {
-        self.font_data = Some(data);
+    /// See `RendererBuilder::with_font_data`.
+    pub fn with_font_data(self, data: &'r [u8]) -> Self {
+        self.with_font_data_index(data, 0)
+    }
+
+    /// See `RendererBuilder::with_font_data_index`.
+    pub fn with_font_data_index(mut self, data: &'r [u8], index: u32) -> Self {
+        self.font = Some((FontFile::Data(Cow::Borrowed(data)), index));
         self
     }
 
-    /// Specify outline width and color.
-    /// **Not implemented yet.**
-    pub fn with_outline(mut self, width: u8, color: [f32; 4]) -> Self {
-        self.outline_width = Some(width);
-        self.outline_color = color;
+    /// See `RendererBuilder::with_shared_font_data`.
+    pub fn with_shared_font_data(mut self, data: Arc<[u8]>, index: u32) -> Self {
+        self.font = Some((FontFile::Shared(data), index));
         self
     }
 
-    /// Specify custom initial buffer size.
-    pub fn with_buffer_size(mut self, size: usize) -> Self {
-        self.buffer_size = size;
+    /// See `RendererBuilder::with_charmap`.
+    pub fn with_charmap(mut self, charmap: Charmap) -> Self {
+        self.charmap = charmap;
         self
     }
 
-    /// Make available only provided characters in font texture instead of
-    /// loading all existing from the font face.
+    /// See `RendererBuilder::with_glyph_names`.
+    pub fn with_glyph_names(mut self, names: &[&str]) -> Self {
+        self.glyph_names = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Add outlines of the given width stroked around the glyphs to the
+    /// atlas, see `RendererBuilder::with_outline`. Called once for every
+    /// width used, e.g. by text styles.
+    pub fn with_outline(mut self, width: u8) -> Self {
+        if width > 0 && !self.outline_widths.contains(&width) {
+            self.outline_widths.push(width);
+        }
+        self
+    }
+
+    /// See `RendererBuilder::with_chars`.
     pub fn with_chars(mut self, chars: &'r [char]) -> Self {
-        self.chars = Some(chars);
+        self.chars = Some(Cow::Borrowed(chars));
         self
     }
 
-    /// Build a new text renderer instance using current settings.
-    pub fn build(mut self) -> Result<Renderer<R, F>, Error> {
-        use gfx::buffer;
-        use gfx::memory;
+    /// See `RendererBuilder::with_tab_width`.
+    pub fn with_tab_width(mut self, columns: u8) -> Self {
+        self.tab_width = columns;
+        self
+    }
 
-        let vertex_buffer = self.factory.create_buffer(
-            self.buffer_size,
-            buffer::Role::Vertex,
-            memory::Usage::Dynamic,
-            memory::Bind::empty()
-        ).expect("Could not create vertex buffer");
-        let index_buffer = self.factory.create_buffer(
-            self.buffer_size,
-            buffer::Role::Index,
-            memory::Usage::Dynamic,
-            memory::Bind::empty()
-        ).expect("Count not create index buffer");
+    /// See `RendererBuilder::with_variation`.
+    pub fn with_variation(mut self, axes: &[(&str, f32)]) -> Self {
+        self.variations = axes.iter().map(|&(tag, value)| (opentype_tag(tag), value)).collect();
+        self
+    }
 
-        // Initialize bitmap font.
-        // TODO(Kagami): Outline!
-        // TODO(Kagami): More granulated font settings, e.g. antialiasing,
-        // hinting, kerning, etc.
-        let font_bitmap = match self.font_path {
-            Some(path) =>
-                BitmapFont::from_path(path, self.font_size, self.chars),
-            None => match self.font_data {
-                Some(data) => BitmapFont::from_bytes(data, self.font_size, self.chars),
-                None => Err(FontError::NoFont),
-            },
-        }?;
-        let font_texture = create_texture_r8_static(
-            &mut self.factory,
-            font_bitmap.get_width(),
-            font_bitmap.get_height(),
-            font_bitmap.get_image(),
-        )?;
-        let sampler = self.factory.create_sampler(
-            texture::SamplerInfo::new(texture::FilterMethod::Bilinear,
-                                  texture::WrapMode::Clamp)
-        );
+    /// See `RendererBuilder::with_synthetic_bold`.
+    pub fn with_synthetic_bold(mut self, bold: bool) -> Self {
+        self.synthetic.bold = bold;
+        self
+    }
 
-        let shaders = self.factory.create_shader_set(VERTEX_SRC, FRAGMENT_SRC)?;
+    /// See `RendererBuilder::with_synthetic_oblique`.
+    pub fn with_synthetic_oblique(mut self, oblique: bool) -> Self {
+        self.synthetic.oblique = oblique;
+        self
+    }
 
-        Ok(Renderer {
-            factory: self.factory,
-            pso_map: HashMap::new(),
-            shaders: shaders,
-            vertex_data: Vec::new(),
-            vertex_buffer: vertex_buffer,
-            index_data: Vec::new(),
-            index_buffer: index_buffer,
-            font_bitmap: font_bitmap,
-            color: (font_texture, sampler),
-        })
+    /// See `RendererBuilder::with_style_font`.
+    pub fn with_style_font(self, style: Style, path: &'r str) -> Self {
+        self.with_style_font_index(style, path, 0)
     }
 
-    /// Just an alias for `builder.build().unwrap()`.
-    pub fn unwrap(self) -> Renderer<R, F> {
-        self.build().unwrap()
+    /// See `RendererBuilder::with_style_font_index`.
+    pub fn with_style_font_index(self, style: Style, path: &'r str, index: u32) -> Self {
+        self.with_style(style, Some((FontFile::Path(Cow::Borrowed(path)), index)))
     }
-}
 
-impl<R: Resources, F: Factory<R>> Renderer<R, F> {
-    fn prepare_pso(&mut self, format: gfx::format::Format) -> Result<(), Error> {
-        Ok(if let Entry::Vacant(e) = self.pso_map.entry(format) {
-            let init = pipe::Init {
-                vbuf: (),
-                screen_size: "u_Screen_Size",
-                proj: "u_Proj",
-                color: "t_Color",
-                out_color: ("o_Color", format, gfx::state::ColorMask::all(), Some(gfx::preset::blend::ALPHA)),
-            };
-            let pso = self.factory.create_pipeline_state(
-                &self.shaders,
-                gfx::Primitive::TriangleList,
-                gfx::state::Rasterizer::new_fill().with_cull_back(),
-                init
-            )?;
-            e.insert(pso);
-        })
+    /// See `RendererBuilder::with_style_font_data`.
+    pub fn with_style_font_data(self, style: Style, data: &'r [u8]) -> Self {
+        self.with_style_font_data_index(style, data, 0)
     }
 
-    /// Add some text to the current draw scene relative to the top left corner
-    /// of the screen using pixel coordinates.
-    pub fn add(&mut self, text: &str, pos: [i32; 2], color: [f32; 4]) {
-        self.add_generic(text, Ok(pos), color)
+    /// See `RendererBuilder::with_style_font_data_index`.
+    pub fn with_style_font_data_index(self, style: Style, data: &'r [u8], index: u32) -> Self {
+        self.with_style(style, Some((FontFile::Data(Cow::Borrowed(data)), index)))
     }
 
-    /// Add text to the draw scene by anchoring an edge or mid-point to a
-    /// position defined in screen pixel coordinates.
-    pub fn add_anchored(&mut self, text: &str, pos: [i32; 2], horizontal: HorizontalAnchor, vertical: VerticalAnchor, color: [f32; 4]) {
-        if horizontal == HorizontalAnchor::Left && vertical == VerticalAnchor::Top {
-            self.add_generic(text, Ok(pos), color);
-            return
-        }
+    /// See `RendererBuilder::with_synthetic_style`.
+    pub fn with_synthetic_style(self, style: Style) -> Self {
+        self.with_style(style, None)
+    }
 
-        let (width, height) = self.measure(text);
-        let x = match horizontal {
-            HorizontalAnchor::Left => pos[0],
-            HorizontalAnchor::Center => pos[0] - width / 2,
-            HorizontalAnchor::Right => pos[0] - width,
-        };
-        let y = match vertical {
-            VerticalAnchor::Top => pos[1],
-            VerticalAnchor::Center => pos[1] - height / 2,
-            VerticalAnchor::Bottom => pos[1] - height,
-        };
+    /// See `RendererBuilder::with_cache_dir`.
+    pub fn with_cache_dir(mut self, dir: &'r str) -> Self {
+        self.cache_dir = Some(Cow::Borrowed(dir));
+        self
+    }
 
-        self.add_generic(text, Ok([x, y]), color)
+    /// Add blurred copies of the glyphs with the given radius to the atlas,
+    /// see `RendererBuilder::with_glow`.
+    pub fn with_glow(mut self, radius: u8) -> Self {
+        self.glow_radius = radius;
+        self
     }
 
-    /// Add some text to the draw scene using absolute world coordinates.
-    pub fn add_at(&mut self, text: &str, pos: [f32; 3], color: [f32; 4]) {
-        self.add_generic(text, Err(pos), color)
+    /// See `RendererBuilder::with_hollow`.
+    pub fn with_hollow(mut self, stroke_width: u8) -> Self {
+        self.hollow = stroke_width;
+        self
     }
 
-    fn add_generic(&mut self, text: &str, pos: Result<[i32; 2], [f32; 3]>, color: [f32; 4]) {
-        // `Result` is used here as an `Either` analogue.
-        let (screen_pos, world_pos, screen_rel) = match pos {
-            Ok(screen_pos) => (screen_pos, [0.0, 0.0, 0.0], 1),
-            Err(world_pos) => ([0, 0], world_pos, 0),
+    /// See `RendererBuilder::with_msdf`.
+    pub fn with_msdf(mut self, range: u8) -> Self {
+        self.distance_range = range;
+        self
+    }
+
+    /// See `RendererBuilder::with_max_texture_size`.
+    pub fn with_max_texture_size(mut self, size: u16) -> Self {
+        self.max_texture_size = size;
+        self
+    }
+
+    // Return copy of the settings which doesn't borrow anything, so the
+    // renderer may keep it to build the font again.
+    fn to_static(&self) -> FontBuilder<'static> {
+        let to_owned = |s: &Cow<str>| Cow::Owned(s.clone().into_owned());
+        FontBuilder {
+            font: self.font.as_ref().map(|&(ref file, index)| (file.to_static(), index)),
+            chars: self.chars.as_ref().map(|chars| Cow::Owned(chars.to_vec())),
+            glyph_names: self.glyph_names.clone(),
+            outline_widths: self.outline_widths.clone(),
+            styles: self.styles.iter()
+                .map(|&(style, ref font)| (style, font.as_ref().map(|&(ref file, index)| (file.to_static(), index))))
+                .collect(),
+            cache_dir: self.cache_dir.as_ref().map(to_owned),
+            variations: self.variations.clone(),
+            ..*self
+        }
+    }
+
+    fn with_style(mut self, style: Style, font: Option<(FontFile<'r>, u32)>) -> Self {
+        self.styles.retain(|&(s, _)| s != style);
+        if style != Style::Regular {
+            self.styles.push((style, font));
+        }
+        self
+    }
+
+    /// Open the FreeType face of the regular font at the font size, e.g. to
+    /// query OS/2 metrics or glyph names. Needs the `freetype-face` feature.
+    #[cfg(feature = "freetype-face")]
+    pub fn open_face(&self) -> Result<freetype::Face, FontError> {
+        let (ref file, index) = *self.font.as_ref().ok_or(FontError::NoFont)?;
+        font::open_face(FaceInfo {
+            source: file.source(),
+            index: index,
+            synthetic: self.synthetic,
+            charmap: self.charmap,
+            glyphs: &[],
+            outlines: &[],
+        }, self.font_size, &self.variations)
+    }
+
+    /// Rasterize the font using current settings.
+    pub fn build(&self) -> Result<FontAtlas, FontError> {
+        // TODO(Kagami): More granulated font settings, e.g. antialiasing,
+        // hinting, kerning, etc.
+        let (ref regular_file, regular_index) = *self.font.as_ref().ok_or(FontError::NoFont)?;
+        let regular = regular_file.source();
+        let mut regular_face = FaceInfo {
+            source: regular,
+            index: regular_index,
+            synthetic: self.synthetic,
+            charmap: self.charmap,
+            glyphs: &[],
+            outlines: &[],
+        };
+        let glyph_names: HashMap<String, u32> = if self.glyph_names.is_empty() {
+            HashMap::new()
+        } else {
+            self.glyph_names.iter().cloned()
+                .zip(font::glyph_indices(regular_face, &self.glyph_names)?)
+                .filter_map(|(name, index)| index.map(|index| (name, index)))
+                .collect()
+        };
+        let mut named_glyphs: Vec<u32> = glyph_names.values().cloned().collect();
+        // Keep the cache key stable.
+        named_glyphs.sort();
+        regular_face.glyphs = &named_glyphs;
+        // Regular face goes first, then the faces of additional styles.
+        let mut faces = vec![regular_face];
+        let mut style_faces = [0; 4];
+        for &(style, ref font) in &self.styles {
+            style_faces[style.slot()] = faces.len();
+            faces.push(match *font {
+                Some((ref file, index)) => FaceInfo {
+                    source: file.source(),
+                    index: index,
+                    synthetic: SyntheticStyle::default(),
+                    charmap: self.charmap,
+                    glyphs: &[],
+                    outlines: &[],
+                },
+                None => FaceInfo {
+                    source: regular,
+                    index: regular_index,
+                    synthetic: style.synthetic(),
+                    charmap: self.charmap,
+                    glyphs: &[],
+                    outlines: &[],
+                },
+            });
+        }
+        for face in &mut faces {
+            face.synthetic.stroke = self.hollow;
+            face.outlines = &self.outline_widths;
+        }
+        let chars = self.chars.as_ref().map(|chars| &chars[..]);
+        let mut bitmap = match self.cache_dir {
+            Some(ref dir) => cache::build(dir, &faces, self.font_size, chars, &self.variations,
+                                          self.distance_range, self.max_texture_size)?,
+            None => BitmapFont::from_faces(&faces, self.font_size, chars, &self.variations,
+                                           self.distance_range, self.max_texture_size)?,
+        };
+        if self.glow_radius > 0 {
+            bitmap.add_glow(self.glow_radius);
+            font::check_size(max(bitmap.get_width(), bitmap.get_height()) as usize, self.max_texture_size)?;
+        }
+        #[cfg(feature = "shaping")]
+        let shapers = faces.iter()
+            .map(|face| shaping::Shaper::from_source(face.source, face.index, self.font_size, face.synthetic.bold,
+                                                   &self.variations))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Small capitals may be missing in the atlas built with limited
+        // chars, even if the font has them.
+        #[cfg(feature = "shaping")]
+        let small_caps = shapers.iter().enumerate().map(|(face, shaper)| {
+            shaper.as_ref()
+                .and_then(|shaper| shaper.substitute('a', opentype_tag("smcp")))
+                .is_some_and(|index| bitmap.find_glyph(face, index).is_some())
+        }).collect();
+
+        let tab_width = tab_stop_width(&bitmap, self.font_size as i32, self.tab_width);
+        // Bitmap strikes may differ from the requested size.
+        let baseline = bitmap.get_baseline() as i32;
+
+        Ok(FontAtlas {
+            bitmap: bitmap,
+            style_faces: style_faces,
+            #[cfg(feature = "shaping")]
+            shapers: shapers,
+            #[cfg(feature = "shaping")]
+            small_caps: small_caps,
+            tab_width: tab_width,
+            baseline: baseline,
+            glyph_names: glyph_names,
+        })
+    }
+}
+
+/// Text renderer builder. Allows to set rendering options using builder
+/// pattern.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut text = gfx_text::RendererBuilder::new()
+///     .with_size(25)
+///     .with_font("/path/to/font.ttf")
+///     .with_chars(&['a', 'b', 'c'])
+///     .build(&mut factory)
+///     .unwrap();
+/// ```
+pub struct RendererBuilder<'r> {
+    font: FontBuilder<'r>,
+    // Font built in advance, overrides the font settings.
+    built_font: Option<FontAtlas>,
+    buffer_size: usize,
+    max_buffer_size: usize,
+    buffer_count: usize,
+    packed: bool,
+    shrink_after: usize,
+    features: Vec<FeatureTag>,
+    declutter: Declutter,
+    shader_version: Option<ShaderVersion>,
+    blend: BlendMode,
+    depth_stencil: (gfx::state::Depth, gfx::state::Stencil),
+    virtual_size: Option<(u16, u16)>,
+    origin: Origin,
+    pixel_offset: [f32; 2],
+    glow: Option<[f32; 4]>,
+    outline: Option<(u8, [f32; 4])>,
+}
+
+/// Create a new text renderer builder. Alias for `RendererBuilder::new`.
+pub fn new<'r>() -> RendererBuilder<'r> {
+    RendererBuilder::new()
+}
+
+impl<'r> Default for RendererBuilder<'r> {
+    fn default() -> Self {
+        RendererBuilder::new()
+    }
+}
+
+impl<'r> RendererBuilder<'r> {
+    /// Create a new text renderer builder.
+    pub fn new() -> Self {
+        // Default renderer settings.
+        RendererBuilder {
+            font: FontBuilder::new(),
+            built_font: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            packed: false,
+            shrink_after: 0,  // Never shrink buffer by default
+            features: Vec::new(),
+            declutter: Declutter::Off,
+            shader_version: None,  // Pick the first one which compiles
+            blend: BlendMode::Alpha,
+            // Don't test or write anything by default.
+            depth_stencil: (gfx::state::Depth::default(), gfx::state::Stencil {
+                front: Default::default(),
+                back: Default::default(),
+            }),
+            virtual_size: None,  // Lay out in target pixels
+            origin: Origin::TopLeft,
+            pixel_offset: [0.0, 0.0],
+            glow: None,
+            outline: None,
+        }
+    }
+
+    /// Specify custom size. Bitmap fonts without outlines (e.g. BDF or PCF)
+    /// use their embedded size nearest to it, which is then used for the
+    /// baseline.
+    pub fn with_size(mut self, size: u8) -> Self {
+        self.font = self.font.with_size(size);
+        self
+    }
+
+    /// Specify custom font by path.
+    pub fn with_font(self, path: &'r str) -> Self {
+        self.with_font_index(path, 0)
+    }
+
+    /// Specify custom font by path and index of the face in a font
+    /// collection (e.g. `.ttc` file).
+    pub fn with_font_index(mut self, path: &'r str, index: u32) -> Self {
+        self.font = self.font.with_font_index(path, index);
+        self
+    }
+
+    /// Pass raw font data.
+    pub fn with_font_data(self, data: &'r [u8]) -> Self {
+        self.with_font_data_index(data, 0)
+    }
+
+    /// Pass raw font data and index of the face in a font collection.
+    pub fn with_font_data_index(mut self, data: &'r [u8], index: u32) -> Self {
+        self.font = self.font.with_font_data_index(data, index);
+        self
+    }
+
+    /// Pass raw font data shared with the renderer and index of the face in
+    /// a font collection. The renderer keeps the font settings to build the
+    /// font again, e.g. with `Renderer::set_font_size`, and copies the data
+    /// given by reference once for that, shared data is not copied.
+    pub fn with_shared_font_data(mut self, data: Arc<[u8]>, index: u32) -> Self {
+        self.font = self.font.with_shared_font_data(data, index);
+        self
+    }
+
+    /// Select the charmap mapping chars to glyphs of all fonts. Default is
+    /// `Charmap::Unicode`, symbol fonts without one use their symbol
+    /// charmap anyway.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut text = gfx_text::new().with_font("wingding.ttf").with_charmap(Charmap::MsSymbol).build(&mut factory).unwrap();
+    /// // The envelope symbol of Wingdings.
+    /// text.add("*", [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn with_charmap(mut self, charmap: Charmap) -> Self {
+        self.font = self.font.with_charmap(charmap);
+        self
+    }
+
+    /// Place the glyphs with given names into the font texture besides the
+    /// chars, so glyphs of icon fonts without mapped chars may be drawn by
+    /// `Renderer::add_glyphs`. Names are looked up in the regular font,
+    /// unknown ones are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut text = gfx_text::new().with_font("icons.ttf").with_glyph_names(&["chevron-right"]).build(&mut factory).unwrap();
+    /// if let Some(index) = text.glyph_index_by_name("chevron-right") {
+    ///     text.add_glyphs(&[index], [10, 10], Color::WHITE);
+    /// }
+    /// ```
+    pub fn with_glyph_names(mut self, names: &[&str]) -> Self {
+        self.font = self.font.with_glyph_names(names);
+        self
+    }
+
+    /// Draw an outline of the given width in pixels and color under the
+    /// glyphs, e.g. to keep subtitles readable on any background. Glyph
+    /// outlines stroked by FreeType are added to the font texture, so glyphs
+    /// of bitmap-only fonts and fonts with distance fields are not outlined.
+    /// The outline may be changed later with `Renderer::set_outline` or per
+    /// style, to widths added to the font. Calling this again adds another
+    /// width and makes it the default. A font set with `with_built_font`
+    /// must be built with `FontBuilder::with_outline` for every width.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Thin outline by default, thick one for titles.
+    /// let mut text = gfx_text::new().with_size(24)
+    ///     .with_outline(4, Color::BLACK)
+    ///     .with_outline(1, Color::BLACK)
+    ///     .build(&mut factory).unwrap();
+    /// text.define_style("title", TextStyle::new(Color::WHITE).with_outline(4, [0.8, 0.1, 0.1, 1.0]));
+    /// ```
+    pub fn with_outline<C: Into<Color>>(mut self, width: u8, color: C) -> Self {
+        let color = color.into().0;
+        self.font = self.font.with_outline(width);
+        self.outline = Some((width, color));
+        self
+    }
+
+    /// Specify custom initial buffer size (in glyphs).
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Specify maximum buffer size (in glyphs), 16384 by default. Text which
+    /// doesn't fit into the buffer of that size is drawn using several draw
+    /// calls instead of reallocating bigger buffer, so this also caps glyphs
+    /// per draw call, e.g. for long log dumps. Glyphs outside of the target
+    /// are culled before that and take no space. Devices without instancing
+    /// draw at most 16384 glyphs per call.
+    pub fn with_max_buffer_size(mut self, size: usize) -> Self {
+        self.max_buffer_size = size;
+        self
+    }
+
+    /// Specify number of glyph buffers used in turn by draw calls. Updating
+    /// a buffer the GPU still reads for a previous frame stalls until it's
+    /// done, more buffers make it less likely at the cost of memory. One
+    /// buffer is enough if text is uploaded mostly once, e.g. with a single
+    /// static scene. Default is 3.
+    pub fn with_buffer_count(mut self, count: usize) -> Self {
+        self.buffer_count = count;
+        self
+    }
+
+    /// Upload glyphs in a packed format taking half the space, with 8-bit
+    /// colors, 16-bit texture coordinates and half float positions, which
+    /// helps with a lot of text changing every frame. Positions are exact
+    /// up to 2048 pixels from the origin (or the world anchor) and are
+    /// rounded to even pixels beyond that, so large targets may show uneven
+    /// spacing. Half float vertex attributes need OpenGL 3.0 or OpenGL ES
+    /// 3.0. Default is `false`.
+    pub fn with_packed_instances(mut self, packed: bool) -> Self {
+        self.packed = packed;
+        self
+    }
+
+    /// Shrink the buffer after it was used less than a quarter of its
+    /// capacity during the given number of consecutive frames. Prevents a
+    /// single huge frame from pinning a lot of memory forever.
+    pub fn with_buffer_shrinking(mut self, frames: usize) -> Self {
+        self.shrink_after = frames;
+        self
+    }
+
+    /// Make available only provided characters in font texture instead of
+    /// loading all existing from the font face. With the `shaping` feature
+    /// text shaped into glyphs missing in the texture, e.g. ligatures of the
+    /// provided characters, is drawn char by char.
+    pub fn with_chars(mut self, chars: &'r [char]) -> Self {
+        self.font = self.font.with_chars(chars);
+        self
+    }
+
+    /// Specify distance between tab stops in columns, i.e. widths of the
+    /// space character. Default is 8.
+    pub fn with_tab_width(mut self, columns: u8) -> Self {
+        self.font = self.font.with_tab_width(columns);
+        self
+    }
+
+    /// Set OpenType features applied to all text, e.g. `("tnum", 1)` for
+    /// tabular numbers or `("liga", 0)` to disable standard ligatures. See
+    /// `Renderer::set_features`.
+    pub fn with_features(mut self, features: &[(&str, u32)]) -> Self {
+        self.features = features.iter().map(|&(tag, value)| (opentype_tag(tag), value)).collect();
+        self
+    }
+
+    /// Set design coordinates of variable font axes by their tags, e.g.
+    /// `("wght", 700.0)` for bold weight. Missing axes keep their default
+    /// values, the setting is ignored for fonts without variations.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let text = gfx_text::new()
+    ///     .with_font("/path/to/variable-font.ttf")
+    ///     .with_variation(&[("wght", 700.0), ("wdth", 87.5)])
+    ///     .build(&mut factory)
+    ///     .unwrap();
+    /// ```
+    pub fn with_variation(mut self, axes: &[(&str, f32)]) -> Self {
+        self.font = self.font.with_variation(axes);
+        self
+    }
+
+    /// Embolden glyph outlines, for fonts without a bold face.
+    pub fn with_synthetic_bold(mut self, bold: bool) -> Self {
+        self.font = self.font.with_synthetic_bold(bold);
+        self
+    }
+
+    /// Slant glyph outlines, for fonts without an italic face.
+    pub fn with_synthetic_oblique(mut self, oblique: bool) -> Self {
+        self.font = self.font.with_synthetic_oblique(oblique);
+        self
+    }
+
+    /// Add font of the given style by path, which is then selected with
+    /// `Renderer::set_style` or `Renderer::add_styled`. Glyphs of all styles
+    /// share the same texture, so styled text doesn't need extra draw calls.
+    /// Regular style always uses the font set with `with_font`.
+    ///
+    /// The texture isn't split into pages, so every style takes its share of
+    /// the maximal texture size and building fails with
+    /// `FontError::AtlasTooLarge` if they don't fit together. Limit the chars
+    /// with `with_chars` or raise the limit with `with_max_texture_size` for
+    /// big fonts.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut text = gfx_text::new()
+    ///     .with_font("/path/to/font-regular.ttf")
+    ///     .with_style_font(Style::Bold, "/path/to/font-bold.ttf")
+    ///     .with_synthetic_style(Style::Italic)
+    ///     .build(&mut factory)
+    ///     .unwrap();
+    /// text.add_styled("Important", [10, 10], Style::Bold, [1.0, 0.0, 0.0, 1.0]);
+    /// ```
+    pub fn with_style_font(mut self, style: Style, path: &'r str) -> Self {
+        self.font = self.font.with_style_font(style, path);
+        self
+    }
+
+    /// Add font of the given style by path and index of the face in a font
+    /// collection, see `with_style_font`.
+    pub fn with_style_font_index(mut self, style: Style, path: &'r str, index: u32) -> Self {
+        self.font = self.font.with_style_font_index(style, path, index);
+        self
+    }
+
+    /// Add raw font data of the given style, see `with_style_font`.
+    pub fn with_style_font_data(mut self, style: Style, data: &'r [u8]) -> Self {
+        self.font = self.font.with_style_font_data(style, data);
+        self
+    }
+
+    /// Add raw font data of the given style and index of the face in a font
+    /// collection, see `with_style_font`.
+    pub fn with_style_font_data_index(mut self, style: Style, data: &'r [u8], index: u32) -> Self {
+        self.font = self.font.with_style_font_data_index(style, data, index);
+        self
+    }
+
+    /// Add the given style synthesized from the regular font by emboldening
+    /// and slanting its glyphs, see `with_style_font`.
+    pub fn with_synthetic_style(mut self, style: Style) -> Self {
+        self.font = self.font.with_synthetic_style(style);
+        self
+    }
+
+    /// Store the built font texture and glyph metrics into the given
+    /// directory and load them on subsequent runs with the same font and
+    /// settings. Makes the default of placing all font chars into the texture
+    /// viable for big fonts.
+    pub fn with_cache_dir(mut self, dir: &'r str) -> Self {
+        self.font = self.font.with_cache_dir(dir);
+        self
+    }
+
+    /// Use the font built in advance with `FontBuilder`, e.g. on a worker
+    /// thread, instead of building it from the font settings of this
+    /// builder.
+    pub fn with_built_font(mut self, font: FontAtlas) -> Self {
+        self.built_font = Some(font);
+        self
+    }
+
+    /// Resolve overlaps between labels added with `add_at` using the given
+    /// strategy. Labels with higher priority win, see
+    /// `Renderer::add_at_with_priority`.
+    pub fn with_declutter(mut self, declutter: Declutter) -> Self {
+        self.declutter = declutter;
+        self
+    }
+
+    /// Specify how text is blended with the render target.
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// Specify depth and stencil state used by
+    /// `Renderer::draw_depth_stencil`, e.g. to confine text to a stenciled
+    /// region or to write stencil for the later passes.
+    pub fn with_depth_stencil(mut self, depth: gfx::state::Depth, stencil: gfx::state::Stencil) -> Self {
+        self.depth_stencil = (depth, stencil);
+        self
+    }
+
+    /// Use shaders of the given version. By default all supported versions
+    /// are tried in order from the newest one until the device accepts one.
+    pub fn with_shader_version(mut self, version: ShaderVersion) -> Self {
+        self.shader_version = Some(version);
+        self
+    }
+
+    /// Lay out text against the fixed virtual resolution, which is stretched
+    /// over the whole target (or viewport) on drawing. All screen positions
+    /// and sizes, including the font size, are in virtual pixels then.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // HUD authored for 720p looks the same at any window size.
+    /// let mut text = gfx_text::new().with_virtual_resolution(1280, 720).build(&mut factory).unwrap();
+    /// text.add_anchored("Paused", [640, 360], HorizontalAnchor::Center,
+    ///                   VerticalAnchor::Center, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn with_virtual_resolution(mut self, width: u16, height: u16) -> Self {
+        self.virtual_size = Some((width, height));
+        self
+    }
+
+    /// Set origin of the screen coordinates of the added text. Default is
+    /// `Origin::TopLeft`. Positions still refer to the same point of the text,
+    /// e.g. to the top left corner for `Renderer::add`, only the coordinate
+    /// system is changed.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut text = gfx_text::new().with_origin(Origin::BottomLeft).build(&mut factory).unwrap();
+    /// // Score at the top of the 480 pixels high screen.
+    /// text.add("Score: 0", [10, 470], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn with_origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Move all glyphs by the offset in target pixels (x to the right, y
+    /// down) when drawn. Default is no offset, which maps glyph texels 1:1 to
+    /// pixels on backends with pixel centers at half-integer coordinates,
+    /// like OpenGL, Vulkan, Metal and Direct3D 10+. Backends with pixel
+    /// centers at integer coordinates, like Direct3D 9, need
+    /// `[-0.5, -0.5]` for crisp text.
+    pub fn with_pixel_offset(mut self, offset: [f32; 2]) -> Self {
+        self.pixel_offset = offset;
+        self
+    }
+
+    /// Draw a soft halo of the given color around glyphs, e.g. for sci-fi
+    /// HUDs. Blurred copies of the glyphs spreading by the radius in pixels
+    /// are added to the font texture and drawn behind the text. The color may
+    /// be changed later with `Renderer::set_glow`. A font set with
+    /// `with_built_font` must be built with `FontBuilder::with_glow`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let text = gfx_text::new().with_glow(4, [0.0, 0.8, 1.0, 0.8]).build(&mut factory).unwrap();
+    /// ```
+    pub fn with_glow<C: Into<Color>>(mut self, radius: u8, color: C) -> Self {
+        let color = color.into().0;
+        self.font = self.font.with_glow(radius);
+        self.glow = Some(color);
+        self
+    }
+
+    /// Draw only glyph outlines stroked with the given width in pixels and
+    /// leave them unfilled, e.g. for watermarks and titles. Zero restores
+    /// filled glyphs. Glow of hollow glyphs follows the stroke.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let text = gfx_text::new().with_size(48).with_hollow(2).build(&mut factory).unwrap();
+    /// ```
+    pub fn with_hollow(mut self, stroke_width: u8) -> Self {
+        self.font = self.font.with_hollow(stroke_width);
+        self
+    }
+
+    /// Store multi-channel signed distance fields of the glyphs instead of
+    /// their coverage, so text stays sharp, including glyph corners, when
+    /// drawn much bigger than the font size, e.g. for zoomable titles. The
+    /// range in pixels limits the stored distances and pads every glyph,
+    /// 4 is usually enough. Zero restores coverage.
+    ///
+    /// Building the fields is much slower than rasterizing, so consider
+    /// limiting the chars or caching the texture with `with_cache_dir`.
+    /// Glyphs are not made hollow and don't glow with distance fields.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut text = gfx_text::new().with_size(48).with_msdf(4).build(&mut factory).unwrap();
+    /// text.add_fitted("Title", [0, 0, 800, 200], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn with_msdf(mut self, range: u8) -> Self {
+        self.font = self.font.with_msdf(range);
+        self
+    }
+
+    /// Limit width and height of the font texture, 8192 by default. Glyphs
+    /// are wrapped into more rows to fit, building the font fails with
+    /// `FontError::AtlasTooLarge` if they don't fit anyway, e.g. with huge
+    /// font sizes. Smaller limit of the device is used instead if reported.
+    pub fn with_max_texture_size(mut self, size: u16) -> Self {
+        self.font = self.font.with_max_texture_size(size);
+        self
+    }
+
+    /// Build a new text renderer instance using current settings, creating
+    /// its resources with the factory. The renderer keeps a clone of the
+    /// factory to create resources later, e.g. when the font changes.
+    ///
+    /// The builder is kept, so it may build more renderers with the same
+    /// settings, e.g. one per window. Every renderer gets its own copy of the
+    /// font given with `with_built_font`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut builder = gfx_text::new().with_size(20);
+    /// let mut main_text = builder.build(&mut factory).unwrap();
+    /// let mut tool_text = builder.build(&mut tool_window.factory).unwrap();
+    /// ```
+    pub fn build<R: Resources, F: Factory<R> + Clone>(&mut self, factory: &mut F) -> Result<Renderer<R, F>, Error> {
+        use gfx::buffer;
+        use gfx::memory;
+
+        let instanced = {
+            let caps = factory.get_capabilities();
+            caps.instance_call_supported && caps.instance_rate_supported
+        };
+        // Without instancing the quads of a whole chunk are drawn at once,
+        // their vertices must be addressable by 16-bit indices.
+        let max_buffer_size = if instanced {
+            max(self.max_buffer_size, 1)
+        } else {
+            self.max_buffer_size.clamp(1, MAX_QUADS_16)
+        };
+        let quad_count = if instanced { 1 } else { max_buffer_size };
+        let (quad_vertices, quad_indices) = quad_geometry(quad_count);
+        let quad_buffer = factory.create_buffer_immutable(
+            &quad_vertices,
+            buffer::Role::Vertex,
+            memory::Bind::empty()
+        )?;
+        let quad_index_buffer = factory.create_buffer_immutable(
+            &quad_indices,
+            buffer::Role::Index,
+            memory::Bind::empty()
+        )?;
+        let quad_slice = gfx::Slice {
+            start: 0,
+            end: QUAD_INDICES.len() as gfx::VertexCount,
+            base_vertex: 0,
+            instances: None,
+            buffer: gfx::IndexBuffer::Index16(quad_index_buffer),
+        };
+        let instance_buffers = (0..max(self.buffer_count, 1))
+            .map(|_| create_instance_buffer(factory, self.buffer_size, self.packed, instanced))
+            .collect::<Result<_, _>>()?;
+        let locals = factory.create_constant_buffer(1);
+
+        let max_texture_size = min(self.font.max_texture_size, device_texture_size(factory));
+        let (font, font_settings) = match self.built_font {
+            Some(ref font) => (font.clone(), None),
+            None => (self.font.clone().with_max_texture_size(max_texture_size).build()?, Some(self.font.to_static())),
+        };
+        let font_texture = create_font_texture(factory, &font)?;
+        let distance_field = font.bitmap().get_distance_range() > 0;
+        let sampler = factory.create_sampler(
+            texture::SamplerInfo::new(texture::FilterMethod::Bilinear,
+                                  texture::WrapMode::Clamp)
+        );
+
+        let (shaders, shader_version) = match self.shader_version {
+            Some(version) => {
+                let (vs, fs) = shader_sources(version, distance_field);
+                (factory.create_shader_set(vs, fs)?, version)
+            },
+            None => create_any_shader_set(factory, distance_field)?,
+        };
+        let mut layout = TextLayout::new(font).with_feature_tags(self.features.clone());
+        layout.set_outline_width(self.outline.map_or(0, |outline| outline.0));
+
+        Ok(Renderer {
+            id: NEXT_RENDERER_ID.fetch_add(1, Ordering::Relaxed),
+            factory: factory.clone(),
+            pso_map: HashMap::new(),
+            depth_stencil_pso_map: HashMap::new(),
+            depth_stencil: self.depth_stencil,
+            shaders: shaders,
+            shader_version: shader_version,
+            blend: self.blend,
+            quad_buffer: quad_buffer,
+            quad_slice: quad_slice,
+            instanced: instanced,
+            instance_data: Vec::new(),
+            instance_buffers: instance_buffers,
+            next_buffer: 0,
+            packed: self.packed,
+            locals: locals,
+            spans: Vec::new(),
+            layers: Vec::new(),
+            fractions: Vec::new(),
+            layer: 0,
+            min_buffer_size: max(self.buffer_size, 1),
+            max_buffer_size: max_buffer_size,
+            shrink_after: self.shrink_after,
+            low_usage_frames: 0,
+            low_usage_peak: 0,
+            draw_calls: 0,
+            glyphs_drawn: 0,
+            bytes_uploaded: 0,
+            pipeline_switches: 0,
+            layout_time: ::std::time::Duration::from_secs(0),
+            bound_pso: None,
+            layout: layout,
+            color: (font_texture, sampler),
+            declutter: self.declutter,
+            virtual_size: self.virtual_size,
+            origin: self.origin,
+            pixel_offset: self.pixel_offset,
+            glow: self.glow,
+            outline: self.outline.map(|outline| outline.1),
+            tint: [1.0; 4],
+            default_color: Color::WHITE.0,
+            default_anchor: (HorizontalAnchor::Left, VerticalAnchor::Top),
+            origin_offset: [0, 0],
+            labels: Vec::new(),
+            cached: Vec::new(),
+            scratch: String::new(),
+            font_settings: font_settings,
+            styles: HashMap::new(),
+        })
+    }
+
+    /// Just an alias for `builder.build(factory).unwrap()`.
+    pub fn unwrap<R: Resources, F: Factory<R> + Clone>(&mut self, factory: &mut F) -> Renderer<R, F> {
+        self.build(factory).unwrap()
+    }
+}
+
+impl<R: Resources, F: Factory<R>> Renderer<R, F> {
+    /// Create a new text renderer with default settings from the font built
+    /// in advance, see `FontBuilder`.
+    pub fn from_font(factory: &mut F, font: FontAtlas) -> Result<Renderer<R, F>, Error>
+        where F: Clone
+    {
+        RendererBuilder::new().with_built_font(font).build(factory)
+    }
+
+    // Count the pipeline switch if the pipeline differs from the last one.
+    fn bind_pso(&mut self, key: PsoKey, depth_stencil: bool) {
+        if self.bound_pso != Some((key, depth_stencil)) {
+            self.pipeline_switches += 1;
+            self.bound_pso = Some((key, depth_stencil));
+        }
+    }
+
+    fn prepare_pso(&mut self, format: gfx::format::Format, aa: texture::AaMode)
+                   -> Result<PipelineState<R, pipe::Meta>, Error> {
+        let blend = self.blend;
+        let key = (format, blend, aa != texture::AaMode::Single);
+        self.bind_pso(key, false);
+        if let Entry::Vacant(e) = self.pso_map.entry(key) {
+            let (attributes, stride) = instance_attributes(self.packed);
+            let (locals, globals) = uniform_names(self.shader_version);
+            let init = pipe::Init {
+                vbuf: (),
+                ibuf: (&attributes, stride, instance_rate(self.instanced)),
+                locals: locals,
+                screen_size: globals[0],
+                proj: globals[1],
+                tint: globals[2],
+                srgb: globals[3],
+                color: "t_Color",
+                out_color: (self.shader_version.output_name(), format, gfx::state::ColorMask::all(), blend.to_blend()),
+                scissor: (),
+            };
+            let program = create_program(&mut self.factory, &self.shaders, self.shader_version)?;
+            let pso = self.factory.create_pipeline_from_program(
+                &program,
+                gfx::Primitive::TriangleList,
+                rasterizer(key.2),
+                init
+            ).map_err(PipelineStateError::<String>::from)?;
+            e.insert(pso);
+        }
+        Ok(self.pso_map[&key].clone())
+    }
+
+    fn prepare_depth_stencil_pso(&mut self, format: gfx::format::Format, aa: texture::AaMode)
+                                 -> Result<PipelineState<R, pipe_depth_stencil::Meta>, Error> {
+        let blend = self.blend;
+        let key = (format, blend, aa != texture::AaMode::Single);
+        self.bind_pso(key, true);
+        if let Entry::Vacant(e) = self.depth_stencil_pso_map.entry(key) {
+            let (attributes, stride) = instance_attributes(self.packed);
+            let (locals, globals) = uniform_names(self.shader_version);
+            let init = pipe_depth_stencil::Init {
+                vbuf: (),
+                ibuf: (&attributes, stride, instance_rate(self.instanced)),
+                locals: locals,
+                screen_size: globals[0],
+                proj: globals[1],
+                tint: globals[2],
+                srgb: globals[3],
+                color: "t_Color",
+                out_color: (self.shader_version.output_name(), format, gfx::state::ColorMask::all(), blend.to_blend()),
+                scissor: (),
+                depth_stencil: self.depth_stencil,
+            };
+            let program = create_program(&mut self.factory, &self.shaders, self.shader_version)?;
+            let pso = self.factory.create_pipeline_from_program(
+                &program,
+                gfx::Primitive::TriangleList,
+                rasterizer(key.2),
+                init
+            ).map_err(PipelineStateError::<String>::from)?;
+            e.insert(pso);
+        }
+        Ok(self.depth_stencil_pso_map[&key].clone())
+    }
+
+    /// Create the pipeline for drawing into single-sampled targets of the
+    /// format with the current blend mode, e.g. during a loading screen, so
+    /// the first draw into them doesn't stall on its creation.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.warmup::<gfx::format::Srgba8>().unwrap();
+    /// ```
+    pub fn warmup<T: gfx::format::RenderFormat>(&mut self) -> Result<(), Error> {
+        self.warmup_format(T::get_format(), texture::AaMode::Single)
+    }
+
+    /// Create the pipeline for drawing into targets of the format and
+    /// antialiasing mode, see `warmup`, e.g. for `draw_raw`. Pipelines of
+    /// `draw_depth_stencil` are still created on first use.
+    pub fn warmup_format(&mut self, format: gfx::format::Format, aa: texture::AaMode) -> Result<(), Error> {
+        // Nothing is drawn, so keep the bound pipeline and the statistics.
+        let bound = (self.bound_pso, self.pipeline_switches);
+        let result = self.prepare_pso(format, aa);
+        self.bound_pso = bound.0;
+        self.pipeline_switches = bound.1;
+        result.map(|_| ())
+    }
+
+    /// Change color of the glow drawn behind the text added after this call,
+    /// `None` disables it. Only fonts built with glow have it, see
+    /// `RendererBuilder::with_glow`. A bare `None` needs the color type, e.g.
+    /// `None::<Color>`.
+    pub fn set_glow<C: Into<Color>>(&mut self, glow: Option<C>) {
+        self.glow = glow.map(|color| color.into().0);
+    }
+
+    /// Change width and color of the outline drawn under the text added
+    /// after this call, `None` disables it. Only widths the font was built
+    /// with are drawn, see `RendererBuilder::with_outline`. A bare `None`
+    /// needs the color type, e.g. `None::<(u8, Color)>`.
+    pub fn set_outline<C: Into<Color>>(&mut self, outline: Option<(u8, C)>) {
+        let outline = outline.map(|(width, color)| (width, color.into().0));
+        self.layout.set_outline_width(outline.map_or(0, |outline| outline.0));
+        self.outline = outline.map(|outline| outline.1);
+    }
+
+    /// Return width and color of the outline drawn under the added text.
+    pub fn outline(&self) -> Option<(u8, [f32; 4])> {
+        self.outline.map(|color| (self.layout.outline_width(), color))
+    }
+
+    /// Change blend mode used by the following draws.
+    pub fn set_blend(&mut self, blend: BlendMode) {
+        self.blend = blend;
+    }
+
+    /// Change opacity all text is multiplied by in the following draws, so
+    /// the whole renderer may be faded without adding the text with other
+    /// colors.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Fade the tutorial overlay out over a second.
+    /// overlay_text.set_global_alpha(1.0 - elapsed.min(1.0));
+    /// overlay_text.draw(&mut encoder, &color_output).unwrap();
+    /// ```
+    pub fn set_global_alpha(&mut self, alpha: f32) {
+        self.tint[3] = alpha;
+    }
+
+    /// Change color all text is multiplied by in the following draws, see
+    /// `set_global_alpha`.
+    pub fn set_global_tint<C: Into<Color>>(&mut self, tint: C) {
+        let tint = tint.into().0;
+        self.tint = tint;
+    }
+
+    /// Change color of the text added by `add_default` after this call.
+    /// Default is white.
+    pub fn set_default_color<C: Into<Color>>(&mut self, color: C) {
+        self.default_color = color.into().0;
+    }
+
+    /// Change anchors of the text added by `add_default` after this call,
+    /// see `add_anchored`. Default is the top left corner.
+    pub fn set_default_anchor(&mut self, horizontal: HorizontalAnchor, vertical: VerticalAnchor) {
+        self.default_anchor = (horizontal, vertical);
+    }
+
+    /// Change offset added to the screen positions of the text added after
+    /// this call, so a whole overlay may be moved at once. Default is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Debug panel of name-value rows, placed by the offset only.
+    /// text.set_origin_offset(panel_x, panel_y);
+    /// for (i, &(name, value)) in rows.iter().enumerate() {
+    ///     text.add_default(name, [0, i as i32 * 20]);
+    ///     text.add_default(&value.to_string(), [120, i as i32 * 20]);
+    /// }
+    /// text.set_origin_offset(0, 0);
+    /// ```
+    pub fn set_origin_offset(&mut self, dx: i32, dy: i32) {
+        self.origin_offset = [dx, dy];
+    }
+
+    /// Return offset added to screen positions, see `set_origin_offset`.
+    pub fn origin_offset(&self) -> [i32; 2] {
+        self.origin_offset
+    }
+
+    /// Return cursor adding lines of text below each other starting at the
+    /// position, with the default color, see `TextCursor`.
+    pub fn cursor<'a>(&'a mut self, pos: [i32; 2]) -> TextCursor<'a, R, F> {
+        TextCursor::new(self, pos)
+    }
+
+    /// Add some text to the draw scene with the default color and anchors,
+    /// see `set_default_color` and `set_default_anchor`.
+    pub fn add_default(&mut self, text: &str, pos: [i32; 2]) -> AddedText {
+        let (horizontal, vertical) = self.default_anchor;
+        let color = self.default_color;
+        self.add_anchored(text, pos, horizontal, vertical, color)
+    }
+
+    /// Change layer of the text added after this call. Text on higher layers
+    /// is drawn over text on lower ones regardless of the order it was added
+    /// in, text within the same layer is drawn in the order it was added.
+    /// Default layer is 0.
+    ///
+    /// Layers are not taken into account when drawing a `TextScene`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.set_layer(1);
+    /// text.add("Tooltip", [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// text.set_layer(0);
+    /// text.add("Label under the tooltip", [10, 10], [0.0, 0.0, 0.0, 1.0]);
+    /// ```
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    /// Add some text to the draw scene on the given layer, keeping the layer
+    /// of the text added after it, see `set_layer`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_on_layer("Tooltip", [10, 10], 1, [1.0, 1.0, 1.0, 1.0]);
+    /// text.add("Label under the tooltip", [10, 10], [0.0, 0.0, 0.0, 1.0]);
+    /// ```
+    pub fn add_on_layer<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], layer: i32, color: C) -> AddedText {
+        let prev_layer = self.layer;
+        self.layer = layer;
+        let added = self.add(text, pos, color);
+        self.layer = prev_layer;
+        added
+    }
+
+    /// Change base direction of the text added and measured after this call.
+    /// Mixed left-to-right and right-to-left text is reordered for display
+    /// according to the Unicode Bidirectional Algorithm, the base direction
+    /// defines the order of the runs with different directions. Default is
+    /// `TextDirection::Auto`.
+    pub fn set_direction(&mut self, direction: TextDirection) {
+        self.layout.set_direction(direction);
+    }
+
+    /// Set function used to hyphenate words in wrapped text, see
+    /// `TextLayout::set_hyphenator`.
+    pub fn set_hyphenator(&mut self, hyphenator: Option<Hyphenator>) {
+        self.layout.set_hyphenator(hyphenator);
+    }
+
+    /// Change how control characters in the text added after this call are
+    /// drawn. Default is `ControlChars::Strip`. Either way `"\r\n"` line
+    /// endings are treated as `"\n"`, and zero-width characters like ZWSP
+    /// and ZWJ take no space.
+    pub fn set_control_chars(&mut self, control_chars: ControlChars) {
+        self.layout.set_control_chars(control_chars);
+    }
+
+    /// Change case of the text added and measured after this call, the
+    /// strings passed in are kept as is, e.g. caret positions refer to them.
+    /// Default is `TextTransform::None`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.set_text_transform(TextTransform::SmallCaps);
+    /// text.add("Main Menu", [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn set_text_transform(&mut self, transform: TextTransform) {
+        self.layout.set_text_transform(transform);
+    }
+
+    /// Change font style of the text added and measured after this call.
+    /// Styles without a font set in the builder use the regular one.
+    pub fn set_style(&mut self, style: Style) {
+        self.layout.set_style(style);
+    }
+
+    /// Add some text in the given base direction with the position at the
+    /// edge where it starts, e.g. the top right corner for right-to-left
+    /// text, see `set_direction`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_in_direction("مرحبا", [630, 10], TextDirection::RightToLeft, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_in_direction<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], direction: TextDirection, color: C) {
+        let color = color.into().0;
+        let prev_direction = self.layout.direction();
+        self.layout.set_direction(direction);
+        self.add_anchored(text, pos, HorizontalAnchor::Start, VerticalAnchor::Top, color);
+        self.layout.set_direction(prev_direction);
+    }
+
+    /// Add a single line of text shortened with an ellipsis to fit into the
+    /// given width, see `TextLayout::elide`. The position is at the edge
+    /// where the text starts, as in `add_in_direction`.
+    pub fn add_elided<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], width: i32, color: C) {
+        let color = color.into().0;
+        let elided = self.layout.elide(text, width);
+        self.add_anchored(&elided, pos, HorizontalAnchor::Start, VerticalAnchor::Top, color);
+    }
+
+    /// Add a single line of text cut at the given width, fading out over the
+    /// last `fade` pixels before the cut instead of the hard edge, e.g. for
+    /// marquee-style lists. Text fitting into the width is drawn as by
+    /// `add`. The fade is applied to whole glyphs, so it looks smooth when
+    /// it spans several of them.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_faded("A very long track title", [10, 10], 120, 24, Color::WHITE);
+    /// ```
+    pub fn add_faded<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], width: i32, fade: i32, color: C) {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let right = pos[0] + width;
+        let quads: Vec<GlyphQuad> = self.layout.line_quads(text, pos).into_iter()
+            .filter_map(|quad| clip_quad(quad, pos[0], right))
+            .collect();
+        let instances = quads.iter().map(|quad| {
+            let center = quad.pos[0] as f32 + quad.size[0] as f32 / 2.0;
+            let alpha = ((right as f32 - center) / max(fade, 1) as f32).clamp(0.0, 1.0);
+            let mut instance = quad_instance(quad, [0.0, 0.0, 0.0], 1.0, color);
+            instance.color[3] *= alpha;
+            instance
+        }).collect();
+        self.push_glyphs(None, &quads, instances);
+    }
+
+    /// Add some text using the given font style, see `Renderer::add`.
+    pub fn add_styled<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], style: Style, color: C) {
+        let color = color.into().0;
+        let prev_style = self.layout.style();
+        self.layout.set_style(style);
+        self.add(text, pos, color);
+        self.layout.set_style(prev_style);
+    }
+
+    /// Add a single line of rich text made of spans with their own color,
+    /// style and baseline shift, e.g. with superscripts or subscripts. The
+    /// spans follow each other from left to right.
+    ///
+    /// Returns rectangle (x, y, width, height) covering every span in the
+    /// coordinates of the position, e.g. to detect hovering and clicks on
+    /// links. With `Origin::BottomLeft` the rectangle extends upwards from
+    /// its bottom left corner.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let white = [1.0, 1.0, 1.0, 1.0];
+    /// text.add_spans(&[
+    ///     TextSpan::new("H", white),
+    ///     TextSpan::new("2", white).with_baseline(BaselineShift::Subscript),
+    ///     TextSpan::new("O and x", white),
+    ///     TextSpan::new("2", white).with_baseline(BaselineShift::Superscript),
+    /// ], [10, 10]);
+    /// ```
+    pub fn add_spans(&mut self, spans: &[TextSpan], pos: [i32; 2]) -> Vec<[i32; 4]> {
+        let pos = self.layout_pos(pos);
+        let (quads, rects) = self.layout.layout_spans(spans, pos);
+        // Byte offset of every span in the concatenated text.
+        let starts: Vec<usize> = spans.iter().scan(0, |offset, span| {
+            let start = *offset;
+            *offset += span.text.len();
+            Some(start)
+        }).collect();
+        let instances = quads.iter().map(|quad| {
+            // Empty spans have no glyphs and share the start of the next one.
+            let span = starts.iter().rposition(|&start| start <= quad.source).unwrap_or(0);
+            quad_instance(quad, [0.0, 0.0, 0.0], 1.0, spans[span].color)
+        }).collect();
+        self.push_glyphs(None, &quads, instances);
+        rects.into_iter().map(|rect| self.screen_rect(rect)).collect()
+    }
+
+    /// Change OpenType features applied to the text added and measured after
+    /// this call, replacing the ones set before. Every feature is given by
+    /// its tag and value, e.g. `("smcp", 1)` enables small capitals and
+    /// `("ss01", 1)` the first stylistic set.
+    ///
+    /// Features are applied during text shaping, so they have no effect
+    /// unless the `shaping` feature of the crate is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Digits of the same width, so the counter doesn't wiggle.
+    /// text.set_features(&[("tnum", 1)]);
+    /// text.add(&format!("Score: {}", score), [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// text.set_features(&[]);
+    /// ```
+    pub fn set_features(&mut self, features: &[(&str, u32)]) {
+        self.layout.set_features(features);
+    }
+
+    /// Add an image of the custom glyph for the char, so it can be used in
+    /// strings, e.g. an icon at a private use code point like `'\u{E000}'`.
+    /// The image is given as rows of 8-bit coverage values from the top and
+    /// is tinted with the text color like other glyphs, since the atlas holds
+    /// no colors.
+    ///
+    /// The atlas texture is recreated, quads laid out with the previous
+    /// atlas outside of the renderer must be laid out again.
+    pub fn register_glyph(&mut self, ch: char, width: u16, height: u16, data: &[u8], metrics: GlyphMetrics) -> Result<(), Error> {
+        let scale = self.layout.register_glyph(ch, width, height, data, metrics);
+        self.color.0 = create_font_texture(&mut self.factory, self.layout.atlas())?;
+
+        // Text already added and cached refers to the old texture size.
+        let scale_tex = |tex: &mut [f32; 4]| {
+            tex[0] *= scale[0];
+            tex[1] *= scale[1];
+            tex[2] *= scale[0];
+            tex[3] *= scale[1];
+        };
+        for instance in &mut self.instance_data {
+            scale_tex(&mut instance.tex);
+        }
+        for cached in self.cached.iter_mut().filter_map(|cached| cached.as_mut()) {
+            for quad in &mut cached.quads {
+                scale_tex(&mut quad.tex);
+                for rect in quad.glow.iter_mut().chain(quad.outline.iter_mut()) {
+                    scale_tex(rect);
+                }
+            }
+        }
+        for buffer in &mut self.instance_buffers {
+            buffer.uploaded.clear();
+        }
+        Ok(())
+    }
+
+    /// Replace the font with the one built from the builder settings, e.g.
+    /// to try fonts out live or to switch to a language the current font
+    /// doesn't cover. Buffers, pipelines and layout options are kept, cached
+    /// texts are laid out again with the current options. Text already added
+    /// to the draw scene is discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.reload_font(&gfx_text::FontBuilder::new().with_size(20).with_font("/path/to/font.ttf")).unwrap();
+    /// ```
+    pub fn reload_font(&mut self, font: &FontBuilder) -> Result<(), Error> {
+        let max_texture_size = min(font.max_texture_size, device_texture_size(&self.factory));
+        let atlas = font.clone().with_max_texture_size(max_texture_size).build()?;
+        self.set_font(atlas)?;
+        self.font_settings = Some(font.to_static());
+        Ok(())
+    }
+
+    /// Rasterize the font again at the new size, e.g. when the UI scale
+    /// changes, see `reload_font`. Fails with `FontError::NoFont` if the
+    /// renderer was given a font built in advance, as its settings are not
+    /// known.
+    pub fn set_font_size(&mut self, size: u8) -> Result<(), Error> {
+        let font = match self.font_settings {
+            Some(ref font) => font.clone().with_size(size),
+            None => return Err(Error::FontError(FontError::NoFont)),
+        };
+        self.reload_font(&font)
+    }
+
+    /// Open the FreeType face of the regular font, see
+    /// `FontBuilder::open_face`. Fails with `FontError::NoFont` if the
+    /// renderer was given a font built in advance. Needs the `freetype-face`
+    /// feature.
+    #[cfg(feature = "freetype-face")]
+    pub fn font_face(&self) -> Result<freetype::Face, Error> {
+        match self.font_settings {
+            Some(ref font) => Ok(font.open_face()?),
+            None => Err(Error::FontError(FontError::NoFont)),
+        }
+    }
+
+    /// Replace the font with the one built in advance, see `reload_font`.
+    /// Text already added to the draw scene refers to the old texture, so it
+    /// is discarded and must be added again before `draw`; cached texts are
+    /// laid out again.
+    pub fn set_font(&mut self, font: FontAtlas) -> Result<(), Error> {
+        let texture = create_font_texture(&mut self.factory, &font)?;
+        self.font_settings = None;
+        // Distance fields are drawn by other shaders.
+        let distance_field = font.bitmap().get_distance_range() > 0;
+        if distance_field != (self.layout.atlas().bitmap().get_distance_range() > 0) {
+            let (vs, fs) = shader_sources(self.shader_version, distance_field);
+            self.shaders = self.factory.create_shader_set(vs, fs)?;
+            self.pso_map.clear();
+            self.depth_stencil_pso_map.clear();
+            self.bound_pso = None;
+        }
+        self.color.0 = texture;
+        self.layout.set_atlas(font);
+        self.clear_scene();
+
+        for cached in self.cached.iter_mut().filter_map(|cached| cached.as_mut()) {
+            cached.quads = match cached.block {
+                Some((width, align)) => self.layout.block_quads(&cached.text, [0, 0], width, align),
+                None => self.layout.line_quads(&cached.text, [0, 0]),
+            };
+        }
+        for buffer in &mut self.instance_buffers {
+            buffer.uploaded.clear();
+        }
+        Ok(())
+    }
+
+    /// Repack the font atlas into a smaller texture without the space left
+    /// unused, e.g. by custom glyphs replaced with `register_glyph`, to free
+    /// GPU memory in long running applications. Texture coordinates of the
+    /// queued and cached text are updated, quads laid out outside of the
+    /// renderer must be laid out again.
+    pub fn compact_atlas(&mut self) -> Result<(), Error> {
+        use font::rect_bits;
+
+        let moved: HashMap<[u32; 4], [f32; 4]> = self.layout.compact_atlas().into_iter()
+            .map(|(old, new)| (rect_bits(old), new))
+            .collect();
+        self.color.0 = create_font_texture(&mut self.factory, self.layout.atlas())?;
+
+        let move_tex = |tex: &mut [f32; 4]| {
+            if let Some(&new) = moved.get(&rect_bits(*tex)) {
+                *tex = new;
+            }
+        };
+        for instance in &mut self.instance_data {
+            move_tex(&mut instance.tex);
+        }
+        for cached in self.cached.iter_mut().filter_map(|cached| cached.as_mut()) {
+            for quad in &mut cached.quads {
+                move_tex(&mut quad.tex);
+                for rect in quad.glow.iter_mut().chain(quad.outline.iter_mut()) {
+                    move_tex(rect);
+                }
+            }
+        }
+        for buffer in &mut self.instance_buffers {
+            buffer.uploaded.clear();
+        }
+        Ok(())
+    }
+
+    /// Return the text layout engine of the renderer, e.g. to lay out text
+    /// without adding it.
+    pub fn layout(&self) -> &TextLayout {
+        &self.layout
+    }
+
+    /// Add some text to the current draw scene relative to the top left corner
+    /// of the screen (or to the origin set with `RendererBuilder::with_origin`)
+    /// using pixel coordinates.
+    pub fn add<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], color: C) -> AddedText {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let rect = self.add_generic(text, Ok(pos), color);
+        self.added_text(self.screen_rect(rect))
+    }
+
+    /// Keep the style under the name for `add_with_style`, replacing the
+    /// style defined with that name before. Restyling all text of a kind
+    /// then needs only another definition.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.define_style("warning", TextStyle::new(Color::YELLOW).with_style(Style::Bold)
+    ///     .with_shadow([1, 1], Color::BLACK));
+    /// text.add_with_style("Low health", [10, 10], "warning");
+    /// ```
+    pub fn define_style(&mut self, name: &str, style: TextStyle) {
+        self.styles.insert(name.to_owned(), style);
+    }
+
+    /// Return the style defined with the name.
+    pub fn get_style(&self, name: &str) -> Option<TextStyle> {
+        self.styles.get(name).cloned()
+    }
+
+    /// Add some text to the draw scene, see `add`, with the style defined
+    /// by `define_style`. Returns `None` and adds nothing if no style has
+    /// the name.
+    pub fn add_with_style(&mut self, text: &str, pos: [i32; 2], name: &str) -> Option<AddedText> {
+        let style = match self.styles.get(name) {
+            Some(&style) => style,
+            None => return None,
+        };
+        let prev_style = self.layout.style();
+        let prev_glow = self.glow;
+        let prev_outline = self.outline();
+        self.layout.set_style(style.style);
+        self.glow = style.glow.or(prev_glow);
+        self.set_outline(style.outline.or(prev_outline));
+        if let Some((offset, color)) = style.shadow {
+            self.add(text, [pos[0] + offset[0], pos[1] + offset[1]], color);
+        }
+        let added = self.add(text, pos, style.color);
+        self.layout.set_style(prev_style);
+        self.glow = prev_glow;
+        self.set_outline(prev_outline);
+        Some(added)
+    }
+
+    /// Add formatted text to the draw scene, see `add`. Text is formatted
+    /// into a buffer reused between calls, so dynamic text like counters
+    /// doesn't need a new `String` every frame.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_fmt(format_args!("Score: {}", score), [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_fmt<C: Into<Color>>(&mut self, args: ::std::fmt::Arguments, pos: [i32; 2], color: C) {
+        let color = color.into().0;
+        use std::fmt::Write;
+
+        let mut text = ::std::mem::take(&mut self.scratch);
+        text.clear();
+        // Writing into a `String` fails only if a `Display` impl does.
+        let _ = text.write_fmt(args);
+        self.add_dynamic(&text, pos, color);
+        self.scratch = text;
+    }
+
+    /// Add text made of the chars to the draw scene, see `add_fmt`.
+    pub fn add_chars<I: IntoIterator<Item = char>, C: Into<Color>>(&mut self, chars: I, pos: [i32; 2], color: C) {
+        let color = color.into().0;
+        let mut text = ::std::mem::take(&mut self.scratch);
+        text.clear();
+        text.extend(chars);
+        self.add_dynamic(&text, pos, color);
+        self.scratch = text;
+    }
+
+    // Add text which probably changes every frame, bypassing the layout
+    // cache so it doesn't push out the repeated labels.
+    fn add_dynamic(&mut self, text: &str, pos: [i32; 2], color: [f32; 4]) {
+        let mut quads = Vec::new();
+        self.layout.push_line_quads(text, 0, self.layout_pos(pos), &mut quads);
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Add text to the draw scene by anchoring an edge or mid-point to a
+    /// position defined in screen pixel coordinates.
+    pub fn add_anchored<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], horizontal: HorizontalAnchor, vertical: VerticalAnchor,
+                                        color: C) -> AddedText {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let (quads, rect) = self.layout.anchored_line(text, pos, horizontal, vertical);
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+        self.added_text(self.screen_rect(rect))
+    }
+
+    /// Add text to the draw scene by anchoring an edge or mid-point to a
+    /// position given in fractions of the target (or viewport) size, e.g.
+    /// `[0.5, 0.5]` is the center of the screen. The position is resolved on
+    /// drawing, so the text stays in place when the window is resized.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_normalized("Press any key", [0.5, 0.95], HorizontalAnchor::Center,
+    ///                     VerticalAnchor::Bottom, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_normalized<C: Into<Color>>(&mut self, text: &str, pos: [f32; 2], horizontal: HorizontalAnchor, vertical: VerticalAnchor, color: C) {
+        let color = color.into().0;
+        self.add_anchored(text, [0, 0], horizontal, vertical, color);
+        let fraction = match self.origin {
+            Origin::TopLeft => pos,
+            Origin::BottomLeft => [pos[0], -pos[1]],
+        };
+        // `add_anchored` queues exactly one span.
+        *self.fractions.last_mut().unwrap() = fraction;
+    }
+
+    /// Add some text to the draw scene, see `add`, changing position and
+    /// alpha of every glyph with the given function. The function gets the
+    /// index of the grapheme cluster (user-perceived char) the glyph belongs
+    /// to, in logical order.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Wavy text fading in letter by letter.
+    /// text.add_animated("Wheee!", [10, 10], [1.0, 1.0, 1.0, 1.0], |i| GlyphOffset {
+    ///     pos: [0.0, (time * 5.0 + i as f32).sin() * 3.0],
+    ///     alpha: (time * 10.0 - i as f32).max(0.0).min(1.0),
+    /// });
+    /// ```
+    pub fn add_animated<A, C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], color: C, mut animate: A)
+        where A: FnMut(usize) -> GlyphOffset
+    {
+        let color = color.into().0;
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let cluster_ends: Vec<usize> = text.grapheme_indices(true).map(|(i, cluster)| i + cluster.len()).collect();
+        let quads = self.layout.line_quads(text, self.layout_pos(pos));
+        let y_sign = match self.origin {
+            Origin::TopLeft => 1.0,
+            Origin::BottomLeft => -1.0,
+        };
+        let instances: Vec<Instance> = quads.iter().map(|quad| {
+            let cluster = match cluster_ends.binary_search(&quad.source) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            };
+            let offset = animate(cluster);
+            let mut instance = quad_instance(quad, [0.0, 0.0, 0.0], 1.0, color);
+            instance.pos[0] += offset.pos[0];
+            instance.pos[1] += offset.pos[1] * y_sign;
+            instance.color[3] *= offset.alpha;
+            instance
+        }).collect();
+        self.push_glyphs(None, &quads, instances);
+    }
+
+    /// Add some text to the draw scene, see `add`, over the filled rectangle
+    /// of the text size extended by the padding on every side.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_with_background("Tooltip", [10, 10], [1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 0.7], 4);
+    /// ```
+    pub fn add_with_background<C: Into<Color>, B: Into<Color>>(&mut self, text: &str, pos: [i32; 2], color: C, background: B, padding: i32) {
+        let color = color.into().0;
+        let background = background.into().0;
+        let pos = self.layout_pos(pos);
+        let (width, height) = self.layout.measure(text);
+        let rect = [pos[0] - padding, pos[1] - padding, width + padding * 2, height + padding * 2];
+        self.add_with_rect(text, pos, color, rect, background);
+    }
+
+    /// Add some text to the draw scene, see `add`, highlighting the byte
+    /// range of it, e.g. selected by the user. Range must lie on char
+    /// boundaries.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Highlight "brown".
+    /// text.add_with_selection("The brown fox", [10, 10], [0.0, 0.0, 0.0, 1.0], 4..9, [0.6, 0.8, 1.0, 1.0]);
+    /// ```
+    pub fn add_with_selection<C: Into<Color>, B: Into<Color>>(&mut self, text: &str, pos: [i32; 2], color: C, range: ::std::ops::Range<usize>, highlight: B) {
+        let color = color.into().0;
+        let highlight = highlight.into().0;
+        let pos = self.layout_pos(pos);
+        let rect = self.layout.range_rect(text, pos, range);
+        self.add_with_rect(text, pos, color, rect, highlight);
+    }
+
+    /// Add a table of single line cells to the draw scene as a single text,
+    /// with the top left corner at the position, see `add`. Every column is
+    /// given its width and alignment, extra cells are left aligned in columns
+    /// fitting them. Columns are separated by half of the line height.
+    /// Returns width and height of the table.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let (frame, draw) = (format!("{:.2}", frame_ms), format!("{:.2}", draw_ms));
+    /// text.add_table(&[
+    ///     &["Frame", &frame, "ms"],
+    ///     &["Draw", &draw, "ms"],
+    /// ], [10, 10], &[
+    ///     TableColumn::new(ColumnAlign::Left),
+    ///     TableColumn::new(ColumnAlign::Decimal).with_width(60),
+    /// ], Color::WHITE);
+    /// ```
+    pub fn add_table<C: Into<Color>>(&mut self, rows: &[&[&str]], pos: [i32; 2], columns: &[TableColumn], color: C) -> (i32, i32) {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let line_height = self.layout.atlas().line_height();
+        let gap = line_height / 2;
+        let num_columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        // Width of every cell, split at the decimal point in decimal columns.
+        let widths: Vec<Vec<(i32, i32)>> = rows.iter().map(|row| row.iter().enumerate().map(|(i, cell)| {
+            match columns.get(i) {
+                Some(column) if column.align == ColumnAlign::Decimal => {
+                    let point = cell.find('.').unwrap_or(cell.len());
+                    let before = self.layout.range_rect(cell, [0, 0], 0..point)[2];
+                    (before, self.layout.measure(cell).0 - before)
+                },
+                _ => (self.layout.measure(cell).0, 0),
+            }
+        }).collect()).collect();
+        // Left edge, width and decimal point offset of every column.
+        let mut x = pos[0];
+        let mut layout = Vec::with_capacity(num_columns);
+        for i in 0..num_columns {
+            let cells = || widths.iter().filter_map(|row| row.get(i));
+            let before = cells().map(|&(before, _)| before).max().unwrap_or(0);
+            let after = cells().map(|&(_, after)| after).max().unwrap_or(0);
+            let column = columns.get(i).cloned().unwrap_or(TableColumn::new(ColumnAlign::Left));
+            let width = column.width.unwrap_or(before + after);
+            // Decimal cells are aligned as a block to the right edge.
+            layout.push((x, width, width - after));
+            x += width + gap;
+        }
+        let mut quads = Vec::new();
+        for (row_number, (row, row_widths)) in rows.iter().zip(&widths).enumerate() {
+            let y = pos[1] + row_number as i32 * line_height;
+            for (i, (cell, &(before, _))) in row.iter().zip(row_widths).enumerate() {
+                let (left, width, point) = layout[i];
+                let offset = match columns.get(i).map(|column| column.align) {
+                    Some(ColumnAlign::Right) => width - before,
+                    Some(ColumnAlign::Decimal) => point - before,
+                    _ => 0,
+                };
+                quads.extend(self.layout.line_quads(cell, [left + offset, y]));
+            }
+        }
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+        (max(x - gap - pos[0], 0), rows.len() as i32 * line_height)
+    }
+
+    /// Add the string being composed by an input method to the draw scene,
+    /// see `add`, with every clause underlined according to its conversion
+    /// state. Clause ranges must lie on char boundaries; adjacent clauses
+    /// are separated by a small gap, so they are told apart.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // "日本" is being converted, "ご" is still typed as is.
+    /// text.add_composition("日本ご", [10, 10], &[
+    ///     CompositionClause { range: 0..6, style: ClauseStyle::Target },
+    ///     CompositionClause { range: 6..9, style: ClauseStyle::Unconverted },
+    /// ], Color::WHITE);
+    /// ```
+    pub fn add_composition<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], clauses: &[CompositionClause], color: C) {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let mut quads = self.layout.line_quads(text, pos);
+        // NOTE: Underline metrics of the font are not kept in the atlas, so
+        // they are estimated from the font size.
+        let font_size = self.layout.atlas().baseline;
+        let thickness = max(font_size / 14, 1);
+        let top = pos[1] + font_size + thickness;
+        for clause in clauses {
+            let rect = self.layout.range_rect(text, pos, clause.range.clone());
+            let (left, right) = (rect[0] + 1, rect[0] + rect[2] - 1);
+            match clause.style {
+                ClauseStyle::Unconverted => {
+                    let mut x = left;
+                    while x < right {
+                        quads.push(self.layout.solid_quad([x, top, min(thickness, right - x), thickness]));
+                        x += thickness * 2;
+                    }
+                },
+                ClauseStyle::Converted => quads.push(self.layout.solid_quad([left, top, right - left, thickness])),
+                ClauseStyle::Target => quads.push(self.layout.solid_quad([left, top, right - left, thickness * 2])),
+            }
+        }
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    // Queue the solid rectangle and the text over it as a single text, so
+    // they are always drawn together.
+    fn add_with_rect(&mut self, text: &str, pos: [i32; 2], color: [f32; 4], rect: [i32; 4], rect_color: [f32; 4]) {
+        let solid = self.layout.solid_quad(rect);
+        let quads = self.layout.line_quads(text, pos);
+        let instances = quads.iter().map(|quad| quad_instance(quad, [0.0, 0.0, 0.0], 1.0, color)).collect();
+        self.push_glyphs(Some(quad_instance(&solid, [0.0, 0.0, 0.0], 1.0, rect_color)), &quads, instances);
+    }
+
+    /// Add a single line of text centered in the rectangle (x, y, width,
+    /// height), scaled down to fit into it, e.g. a localized button label.
+    /// Returns the scale used. Text is never scaled up, build the renderer
+    /// with the largest needed font size.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_fitted("Einstellungen speichern", [10, 10, 120, 24], [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_fitted<C: Into<Color>>(&mut self, text: &str, rect: [i32; 4], color: C) -> f32 {
+        let color = color.into().0;
+        let pos = self.layout_pos([rect[0], rect[1]]);
+        // With the bottom left origin the rectangle extends upwards.
+        let top = match self.origin {
+            Origin::TopLeft => pos[1],
+            Origin::BottomLeft => pos[1] - rect[3],
+        };
+        let quads = self.layout.fitted_quads(text, [pos[0], top, rect[2], rect[3]]);
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+        self.layout.fit_scale(text, rect[2], rect[3])
+    }
+
+    /// Add a single line of text along the polyline given by its points,
+    /// e.g. a road on a map. Every glyph is placed at the distance of its
+    /// advance along the path and rotated to follow it, the path goes
+    /// through the vertical middle of the line. Text going past the last
+    /// point continues in the direction of the last segment.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_on_path("Main Street", &[[10, 200], [150, 150], [300, 180]], [0.0, 0.0, 0.0, 1.0]);
+    /// ```
+    pub fn add_on_path<C: Into<Color>>(&mut self, text: &str, points: &[[i32; 2]], color: C) {
+        let color = color.into().0;
+        let points: Vec<[f32; 2]> = points.iter()
+            .map(|&point| {
+                let point = self.layout_pos(point);
+                [point[0] as f32, point[1] as f32]
+            })
+            .collect();
+        if points.len() < 2 {
+            if let Some(point) = points.first() {
+                self.add_generic(text, Ok([point[0] as i32, point[1] as i32 - self.layout.atlas().line_height() / 2]), color);
+            }
+            return;
+        }
+        self.add_along(text, color, |distance| {
+            // Find the segment containing the point, or the last one.
+            let mut start = 0.0;
+            for (i, segment) in points.windows(2).enumerate() {
+                let delta = [segment[1][0] - segment[0][0], segment[1][1] - segment[0][1]];
+                let length = (delta[0] * delta[0] + delta[1] * delta[1]).sqrt();
+                if distance < start + length || i == points.len() - 2 {
+                    let angle = delta[1].atan2(delta[0]);
+                    let along = distance - start;
+                    return ([segment[0][0] + along * angle.cos(), segment[0][1] + along * angle.sin()], angle);
+                }
+                start += length;
+            }
+            unreachable!()
+        });
+    }
+
+    /// Add a single line of text along the circle arc with the given center
+    /// and radius, e.g. for gauges and medals. Text starts at the angle in
+    /// radians, measured from the X axis towards the Y axis, and runs
+    /// clockwise on screen with the tops of glyphs facing outwards. The arc
+    /// goes through the vertical middle of the line.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Along the top of the circle.
+    /// text.add_on_arc("SPEED", [320, 240], 100.0, -2.0, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_on_arc<C: Into<Color>>(&mut self, text: &str, center: [i32; 2], radius: f32, start_angle: f32, color: C) {
+        let color = color.into().0;
+        let center = self.layout_pos(center);
+        let center = [center[0] as f32, center[1] as f32];
+        // Flipping the Y axis turns the counterclockwise angles into
+        // clockwise ones.
+        let start_angle = match self.origin {
+            Origin::TopLeft => start_angle,
+            Origin::BottomLeft => -start_angle,
+        };
+        self.add_along(text, color, |distance| {
+            let angle = start_angle + distance / radius;
+            let point = [center[0] + radius * angle.cos(), center[1] + radius * angle.sin()];
+            (point, angle + ::std::f32::consts::FRAC_PI_2)
+        });
+    }
+
+    // Queue the line placing every glyph center at the point returned by the
+    // curve for its distance from the text start, rotated by the returned
+    // angle of the curve tangent.
+    fn add_along<P>(&mut self, text: &str, color: [f32; 4], mut curve: P)
+        where P: FnMut(f32) -> ([f32; 2], f32)
+    {
+        let middle = self.layout.atlas().line_height() as f32 / 2.0;
+        let quads = self.layout.line_quads(text, [0, 0]);
+        let instances: Vec<Instance> = quads.iter().map(|quad| {
+            let half_size = [quad.size[0] as f32 / 2.0, quad.size[1] as f32 / 2.0];
+            let (point, angle) = curve(quad.pos[0] as f32 + half_size[0]);
+            let (sin, cos) = angle.sin_cos();
+            // Shift across the curve to keep the glyph on its baseline.
+            let shift = quad.pos[1] as f32 + half_size[1] - middle;
+            let center = [point[0] - shift * sin, point[1] + shift * cos];
+            Instance {
+                pos: [center[0] - half_size[0], center[1] - half_size[1]],
+                rotation: [cos, sin, 1.0],
+                ..quad_instance(quad, [0.0, 0.0, 0.0], 1.0, color)
+            }
+        }).collect();
+        self.push_glyphs(None, &quads, instances);
+    }
+
+    /// Add multi-line text to the draw scene. Text is split into lines at
+    /// newlines and wrapped to fit into the block of the given width (see
+    /// `TextLayout::wrap_lines`), each line is aligned within the block.
+    /// Words wider than the block are not broken.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_block("Hello,\nthis line is wrapped when it doesn't fit",
+    ///                [10, 10], 200, TextAlign::Center, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn add_block<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], width: i32, align: TextAlign, color: C) {
+        let color = color.into().0;
+        let quads = self.layout.block_quads(text, self.layout_pos(pos), width, align);
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Add multi-line text to the draw scene, see `add_block`, over the panel
+    /// of the measured text size extended by the padding on every side, as a
+    /// single text. Lines are aligned within the widest of them. Returns
+    /// rectangle (x, y, width, height) of the panel.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Speech bubble from the image registered for U+E000 with 8 pixel corners.
+    /// text.add_block_with_panel("Follow me!", [10, 10], 200, TextAlign::Left, Color::BLACK,
+    ///                           Panel::NineSlice { ch: '\u{E000}', border: 8 }, Color::WHITE, 6);
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_block_with_panel<C: Into<Color>, B: Into<Color>>(&mut self, text: &str, pos: [i32; 2], width: i32, align: TextAlign,
+                                                                color: C, panel: Panel, panel_color: B, padding: i32) -> [i32; 4] {
+        let color = color.into().0;
+        let panel_color = panel_color.into().0;
+        let pos = self.layout_pos(pos);
+        // Lines wrapped at the measured width break as at the given one.
+        let (text_width, text_height) = self.layout.measure_block(text, width);
+        let rect = [pos[0] - padding, pos[1] - padding, text_width + padding * 2, text_height + padding * 2];
+        let background: Vec<Instance> = panel_quads(&self.layout, rect, panel).iter()
+            .filter(|quad| !quad.is_empty())
+            .map(|quad| quad_instance(quad, [0.0, 0.0, 0.0], 1.0, panel_color))
+            .collect();
+        let quads = self.layout.block_quads(text, pos, text_width, align);
+        let instances = quads.iter().map(|quad| quad_instance(quad, [0.0, 0.0, 0.0], 1.0, color)).collect();
+        self.push_glyphs(background, &quads, instances);
+        self.screen_rect(rect)
+    }
+
+    /// Add vertical text to the draw scene, e.g. for CJK scripts. Chars
+    /// advance from top to bottom and every line of the text makes a new
+    /// column to the left of the previous one. The position is the top right
+    /// corner of the first column.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_vertical("縦書き\n二列目", [300, 10], [0.0, 0.0, 0.0, 1.0]);
+    /// ```
+    pub fn add_vertical<C: Into<Color>>(&mut self, text: &str, pos: [i32; 2], color: C) {
+        let color = color.into().0;
+        let quads = self.layout.vertical_quads(text, self.layout_pos(pos));
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Lay out a single line of text once, using the current style, direction
+    /// and features, to add it to the draw scene many times, possibly
+    /// partially, with `add_partial`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Dialogue box revealing one more char every frame.
+    /// let line = text.cache_text("Hello, traveler!");
+    /// // In render loop:
+    /// text.add_partial(line, [10, 400], frame, [1.0, 1.0, 1.0, 1.0]);
+    /// ```
+    pub fn cache_text(&mut self, text: &str) -> CachedText {
+        let quads = self.layout.line_quads(text, [0, 0]);
+        self.cache_quads(text, None, quads)
+    }
+
+    /// Same as `cache_text` but for a text block, see `add_block`.
+    pub fn cache_block(&mut self, text: &str, width: i32, align: TextAlign) -> CachedText {
+        let quads = self.layout.block_quads(text, [0, 0], width, align);
+        self.cache_quads(text, Some((width, align)), quads)
+    }
+
+    fn cache_quads(&mut self, text: &str, block: Option<(i32, TextAlign)>, quads: Vec<GlyphQuad>) -> CachedText {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let layout = CachedLayout {
+            quads: quads,
+            cluster_ends: text.grapheme_indices(true).map(|(i, cluster)| i + cluster.len()).collect(),
+            text: text.to_owned(),
+            block: block,
+        };
+        // Reuse slots of the removed texts.
+        match self.cached.iter().position(Option::is_none) {
+            Some(i) => {
+                self.cached[i] = Some(layout);
+                CachedText(i)
+            },
+            None => {
+                self.cached.push(Some(layout));
+                CachedText(self.cached.len() - 1)
+            },
+        }
+    }
+
+    /// Remove the cached text, its handle must not be used afterwards.
+    pub fn uncache(&mut self, text: CachedText) {
+        self.cached[text.0] = None;
+    }
+
+    /// Return number of grapheme clusters (user-perceived chars) in the
+    /// cached text.
+    pub fn cached_len(&self, text: CachedText) -> usize {
+        self.cached[text.0].as_ref().map_or(0, |layout| layout.cluster_ends.len())
+    }
+
+    /// Add the first `visible` grapheme clusters of the cached text to the
+    /// draw scene, the position is the same as of `add` or `add_block`.
+    /// Clusters are counted in logical order, so the text is revealed in
+    /// reading order for right-to-left scripts too.
+    pub fn add_partial<C: Into<Color>>(&mut self, text: CachedText, pos: [i32; 2], visible: usize, color: C) {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let quads: Vec<GlyphQuad> = match self.cached[text.0] {
+            Some(ref layout) => {
+                let end = match visible {
+                    0 => 0,
+                    n => layout.cluster_ends.get(n - 1).cloned().unwrap_or(usize::MAX),
+                };
+                layout.quads.iter().filter(|quad| quad.source < end).map(|quad| GlyphQuad {
+                    pos: [pos[0] + quad.pos[0], pos[1] + quad.pos[1]],
+                    ..*quad
+                }).collect()
+            },
+            None => Vec::new(),
+        };
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Add the part of the cached text visible through a window of the given
+    /// width at the position, with the text scrolled left by `scroll`
+    /// pixels. Glyphs crossing the window edges are cut, see `Marquee` for
+    /// the text scrolling by itself.
+    pub fn add_window<C: Into<Color>>(&mut self, text: CachedText, pos: [i32; 2], scroll: i32, width: i32, color: C) {
+        let color = color.into().0;
+        let pos = self.layout_pos(pos);
+        let quads: Vec<GlyphQuad> = match self.cached[text.0] {
+            Some(ref layout) => layout.quads.iter().filter_map(|quad| clip_quad(GlyphQuad {
+                pos: [pos[0] - scroll + quad.pos[0], pos[1] + quad.pos[1]],
+                ..*quad
+            }, pos[0], pos[0] + width)).collect(),
+            None => Vec::new(),
+        };
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Get the bounding box size of a text block as laid out by `add_block`
+    /// with the given width.
+    pub fn measure_block(&self, text: &str, width: i32) -> (i32, i32) {
+        self.layout.measure_block(text, width)
+    }
+
+    /// Add some text to the draw scene using absolute world coordinates.
+    pub fn add_at<C: Into<Color>>(&mut self, text: &str, pos: [f32; 3], color: C) -> AddedText {
+        let color = color.into().0;
+        self.add_at_with_priority(text, pos, color, 0)
+    }
+
+    /// Same as `add_at` but also sets the label priority used to resolve
+    /// overlaps when decluttering is enabled. Labels with lower priority are
+    /// hidden or moved away first.
+    pub fn add_at_with_priority<C: Into<Color>>(&mut self, text: &str, pos: [f32; 3], color: C, priority: i32) -> AddedText {
+        let color = color.into().0;
+        let start = self.instance_data.len();
+        let rect = self.add_generic(text, Err(pos), color);
+        let added = self.added_text(rect);
+        if self.declutter == Declutter::Off || start == self.instance_data.len() {
+            return added;
+        }
+
+        let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+        for glyph in &self.instance_data[start..] {
+            bounds[0] = bounds[0].min(glyph.pos[0]);
+            bounds[1] = bounds[1].min(glyph.pos[1]);
+            bounds[2] = bounds[2].max(glyph.pos[0] + glyph.size[0]);
+            bounds[3] = bounds[3].max(glyph.pos[1] + glyph.size[1]);
+        }
+        // `add_generic` queues exactly one span.
+        self.labels.push(WorldLabel {
+            span: self.spans.len() - 1,
+            world_pos: pos,
+            bounds: bounds,
+            priority: priority,
+        });
+        added
+    }
+
+    // Queue a single line of text, returning its line box in the layout
+    // coordinates.
+    fn add_generic(&mut self, text: &str, pos: Result<[i32; 2], [f32; 3]>, color: [f32; 4]) -> [i32; 4] {
+        // `Result` is used here as an `Either` analogue.
+        let (screen_pos, world_pos, screen_rel) = match pos {
+            Ok(screen_pos) => (screen_pos, [0.0, 0.0, 0.0], 1.0),
+            Err(world_pos) => ([0, 0], world_pos, 0.0),
+        };
+        let (quads, rect) = self.layout.anchored_line(text, screen_pos, HorizontalAnchor::Left, VerticalAnchor::Top);
+        self.push_quads(&quads, world_pos, screen_rel, color);
+        rect
+    }
+
+    // Describe the text queued last with its rectangle.
+    fn added_text(&self, rect: [i32; 4]) -> AddedText {
+        AddedText {
+            id: TextId(self.spans.len() - 1),
+            rect: rect,
+        }
+    }
+
+    // Convert rectangle in the layout coordinates into the screen ones, see
+    // `layout_pos`.
+    fn screen_rect(&self, rect: [i32; 4]) -> [i32; 4] {
+        match self.origin {
+            Origin::TopLeft => rect,
+            Origin::BottomLeft => [rect[0], -(rect[1] + rect[3]), rect[2], rect[3]],
+        }
+    }
+
+    /// Return index of the glyph with the name given to
+    /// `RendererBuilder::with_glyph_names`, see `FontAtlas::glyph_index_by_name`.
+    pub fn glyph_index_by_name(&self, name: &str) -> Option<u32> {
+        self.layout.atlas().glyph_index_by_name(name)
+    }
+
+    /// Add a line of glyphs given by their indices in the font to the draw
+    /// scene, see `TextLayout::glyph_quads`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Icon of the icon font without a char mapped to it.
+    /// text.add_glyphs(&[1042], [10, 10], Color::WHITE);
+    /// ```
+    pub fn add_glyphs<C: Into<Color>>(&mut self, indices: &[u32], pos: [i32; 2], color: C) {
+        let color = color.into().0;
+        let quads = self.layout.glyph_quads(indices, self.layout_pos(pos));
+        self.push_quads(&quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Add glyph quads laid out in advance to the draw scene, e.g. by a
+    /// `TextLayout` on another thread. Quads must come from the atlas built
+    /// with the same settings as the renderer's one.
+    ///
+    /// With `Origin::BottomLeft` quads must be laid out at the position with
+    /// negated Y coordinate, since the layout is always done from the top.
+    pub fn add_quads<C: Into<Color>>(&mut self, quads: &[GlyphQuad], color: C) {
+        let color = color.into().0;
+        self.push_quads(quads, [0.0, 0.0, 0.0], 1.0, color);
+    }
+
+    /// Convert screen position into the top-down coordinates used by the
+    /// layout, moved by the origin offset. With the bottom left origin the
+    /// screen height is not known until drawing, so it's added in
+    /// `offset_screen_text`.
+    fn layout_pos(&self, pos: [i32; 2]) -> [i32; 2] {
+        let pos = [pos[0] + self.origin_offset[0], pos[1] + self.origin_offset[1]];
+        match self.origin {
+            Origin::TopLeft => pos,
+            Origin::BottomLeft => [pos[0], -pos[1]],
+        }
+    }
+
+    /// Queue glyphs of a single added text.
+    fn push_quads(&mut self, quads: &[GlyphQuad], world_pos: [f32; 3], screen_rel: f32, color: [f32; 4]) {
+        // Layout makes no quads of whitespace, but quads added by the user
+        // may still be empty, drawing them is wasted.
+        let filtered: Vec<GlyphQuad>;
+        let quads = if quads.iter().any(GlyphQuad::is_empty) {
+            filtered = quads.iter().filter(|quad| !quad.is_empty()).cloned().collect();
+            &filtered[..]
+        } else {
+            quads
+        };
+        let instances = quads.iter().map(|quad| quad_instance(quad, world_pos, screen_rel, color)).collect();
+        self.push_glyphs(None, quads, instances);
+    }
+
+    // Queue glyph instances made from the quads as a single text, preceded
+    // by the background instances, the glows and the outlines of the
+    // glyphs, if any.
+    fn push_glyphs<B: IntoIterator<Item = Instance>>(&mut self, background: B, quads: &[GlyphQuad], instances: Vec<Instance>) {
+        let radius = self.layout.atlas().bitmap().get_glow_radius() as f32;
+        let glows: Vec<Instance> = match self.glow {
+            Some(glow) if radius > 0.0 => quads.iter().zip(&instances).filter_map(|(quad, instance)| {
+                quad.glow.map(|tex| Instance {
+                    pos: [instance.pos[0] - radius, instance.pos[1] - radius],
+                    size: [instance.size[0] + radius * 2.0, instance.size[1] + radius * 2.0],
+                    tex: tex,
+                    // Fade together with the glyph.
+                    color: [glow[0], glow[1], glow[2], glow[3] * instance.color[3]],
+                    ..*instance
+                })
+            }).collect(),
+            _ => Vec::new(),
+        };
+        let outlines: Vec<Instance> = match self.outline {
+            Some(outline) => quads.iter().zip(&instances).filter_map(|(quad, instance)| {
+                quad.outline.map(|tex| {
+                    // Outline is extended by its width on every side, which
+                    // is scaled with the glyph, e.g. of small capitals.
+                    let extent = [
+                        ((tex[2] - quad.tex[2]) / 2.0 * instance.size[0] / quad.tex[2]).round(),
+                        ((tex[3] - quad.tex[3]) / 2.0 * instance.size[1] / quad.tex[3]).round(),
+                    ];
+                    Instance {
+                        pos: [instance.pos[0] - extent[0], instance.pos[1] - extent[1]],
+                        size: [instance.size[0] + extent[0] * 2.0, instance.size[1] + extent[1] * 2.0],
+                        tex: tex,
+                        color: [outline[0], outline[1], outline[2], outline[3] * instance.color[3]],
+                        ..*instance
+                    }
+                })
+            }).collect(),
+            None => Vec::new(),
+        };
+        self.push_instances(background.into_iter().chain(glows).chain(outlines).chain(instances));
+    }
+
+    // Range of the glyphs of the span with the index in the queued glyphs.
+    fn span_range(&self, span: usize) -> ::std::ops::Range<usize> {
+        let end = self.spans.get(span + 1).cloned().unwrap_or(self.instance_data.len());
+        self.spans[span]..end
+    }
+
+    fn push_instances<I: Iterator<Item = Instance>>(&mut self, instances: I) {
+        self.spans.push(self.instance_data.len());
+        self.layers.push(self.layer);
+        self.fractions.push([0.0, 0.0]);
+        self.instance_data.extend(instances);
+    }
+
+    /// Discard the text queued for drawing without drawing it. Text added
+    /// through a `TextScene` must be cleared from the scene as well.
+    pub fn clear(&mut self) {
+        self.clear_scene();
+    }
+
+    /// Return whether no glyphs are queued for drawing.
+    pub fn is_empty(&self) -> bool {
+        self.instance_data.is_empty()
+    }
+
+    /// Return number of glyph quads queued for drawing, including glows and
+    /// backgrounds.
+    pub fn glyph_count(&self) -> usize {
+        self.instance_data.len()
+    }
+
+    /// Draw the current scene and clear state.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add("Test1", [10, 10], [1.0, 0.0, 0.0, 1.0]);
+    /// text.add("Test2", [20, 20], [0.0, 1.0, 0.0, 1.0]);
+    /// text.draw(&mut encoder, &color_output).unwrap();
+    /// ```
+    pub fn draw<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>
+    ) -> Result<(), Error> {
+        self.draw_at(encoder, target, DEFAULT_PROJECTION)
+    }
+
+    /// Project queued world labels onto the screen and hide or move the ones
+    /// overlapping labels with higher priority.
+    fn declutter_labels(&mut self, proj: [[f32; 4]; 4], screen_size: [f32; 2]) {
+        if self.labels.len() < 2 {
+            return
+        }
+
+        // Same transformation as in the vertex shader, but in pixels.
+        let rects: Vec<Option<[f32; 4]>> = self.labels.iter().map(|label| {
+            // Labels behind the camera can't collide with anything visible.
+            project_pixels(proj, label.world_pos, screen_size).map(|[origin_x, origin_y]| {
+                let b = label.bounds;
+                [origin_x + b[0], origin_y + b[1], origin_x + b[2], origin_y + b[3]]
+            })
+        }).collect();
+
+        // Stable sort keeps the earlier added label on top for equal
+        // priorities.
+        let mut order: Vec<usize> = (0..self.labels.len()).collect();
+        order.sort_by(|&a, &b| self.labels[b].priority.cmp(&self.labels[a].priority));
+
+        let overlaps = |a: &[f32; 4], b: &[f32; 4]| {
+            a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
         };
-        let (mut x, y) = (screen_pos[0] as f32, screen_pos[1] as f32);
-        for ch in text.chars() {
-            let ch_info = match self.font_bitmap.find_char(ch) {
-                Some(info) => info,
-                // Skip unknown chars from text string. Probably it would be
-                // better to place some "?" mark instead but it may not exist
-                // in the font too.
+        let mut placed: Vec<[f32; 4]> = Vec::with_capacity(order.len());
+        let mut hidden = vec![false; self.labels.len()];
+
+        for &i in &order {
+            let mut rect = match rects[i] {
+                Some(rect) => rect,
                 None => continue,
             };
-            let x_offset = x + ch_info.x_offset as f32;
-            let y_offset = y + ch_info.y_offset as f32;
-            let tex = ch_info.tex;
-            let index = self.vertex_data.len() as u32;
-
-            // Top-left point, index + 0.
-            self.vertex_data.push(Vertex {
-                pos: [x_offset, y_offset],
-                tex: [tex[0], tex[1]],
-                world_pos: world_pos,
-                screen_rel: screen_rel,
-                color: color,
-            });
-            // Bottom-left point, index + 1.
-            self.vertex_data.push(Vertex {
-                pos: [x_offset, y_offset + ch_info.height as f32],
-                tex: [tex[0], tex[1] + ch_info.tex_height],
-                world_pos: world_pos,
-                screen_rel: screen_rel,
-                color: color,
-            });
-            // Bottom-right point, index + 2.
-            self.vertex_data.push(Vertex {
-                pos: [x_offset + ch_info.width as f32, y_offset + ch_info.height as f32],
-                tex: [tex[0] + ch_info.tex_width, tex[1] + ch_info.tex_height],
-                world_pos: world_pos,
-                screen_rel: screen_rel,
-                color: color,
-            });
-            // Top-right point, index + 3.
-            self.vertex_data.push(Vertex {
-                pos: [x_offset + ch_info.width as f32, y_offset],
-                tex: [tex[0] + ch_info.tex_width, tex[1]],
-                world_pos: world_pos,
-                screen_rel: screen_rel,
-                color: color,
+            let height = rect[3] - rect[1];
+            let mut shift = 0.0;
+            let mut attempts = match self.declutter {
+                Declutter::Offset => DECLUTTER_OFFSET_ATTEMPTS,
+                _ => 0,
+            };
+            while placed.iter().any(|p| overlaps(p, &rect)) {
+                if attempts == 0 {
+                    hidden[i] = true;
+                    break;
+                }
+                attempts -= 1;
+                rect[1] += height;
+                rect[3] += height;
+                shift += height;
+            }
+            if hidden[i] {
+                continue;
+            }
+            placed.push(rect);
+            if shift != 0.0 {
+                let glyphs = self.span_range(self.labels[i].span);
+                for glyph in &mut self.instance_data[glyphs] {
+                    glyph.pos[1] += shift;
+                }
+            }
+        }
+
+        if hidden.iter().any(|&h| h) {
+            let mut hidden_spans = vec![false; self.spans.len()];
+            for (label, _) in self.labels.iter().zip(&hidden).filter(|&(_, &h)| h) {
+                hidden_spans[label.span] = true;
+            }
+            self.retain_glyphs(|span, _| !hidden_spans[span]);
+            let mut hidden = hidden.into_iter();
+            self.labels.retain(|_| !hidden.next().unwrap());
+        }
+    }
+
+    /// Drop queued glyphs the function returns `false` for, given the index
+    /// of the span the glyph belongs to. Spans are moved to the glyphs left,
+    /// spans left without glyphs stay empty, so span indices don't change.
+    fn retain_glyphs<K: FnMut(usize, &Instance) -> bool>(&mut self, mut keep: K) {
+        let mut kept = 0;
+        for span in 0..self.spans.len() {
+            let glyphs = self.span_range(span);
+            self.spans[span] = kept;
+            for i in glyphs {
+                if keep(span, &self.instance_data[i]) {
+                    self.instance_data[kept] = self.instance_data[i];
+                    kept += 1;
+                }
+            }
+        }
+        self.instance_data.truncate(kept);
+    }
+
+    /// Draw using provided projection matrix.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_at("Test1", [6.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]);
+    /// text.add_at("Test2", [0.0, 5.0, 0.0], [0.0, 1.0, 0.0, 1.0]);
+    /// text.draw_at(&mut encoder, &color_output, camera_projection).unwrap();
+    /// ```
+    pub fn draw_at<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        self.draw_viewport(encoder, target, full_viewport(target.get_dimensions()), proj)
+    }
+
+    /// Draw using provided projection matrix into the viewport rectangle of
+    /// the target (x, y, width, height in pixels), e.g. into the region of a
+    /// split-screen player. Screen positions of the added text are relative
+    /// to the top left corner of the viewport, the projection maps onto the
+    /// whole viewport, and nothing is drawn outside of it.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for (i, player) in players.iter().enumerate() {
+    ///     text.add(&player.name, [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    ///     text.draw_viewport(&mut encoder, &color_output, [i as i32 * 400, 0, 400, 600],
+    ///                        player.camera_projection).unwrap();
+    /// }
+    /// ```
+    pub fn draw_viewport<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        viewport: [i32; 4],
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        use gfx::memory::Typed;
+
+        self.draw_viewport_raw(encoder, target.raw(), T::get_format(), viewport, proj)
+    }
+
+    /// Draw the current scene into a target of a format known only at
+    /// runtime, see `draw`, e.g. when the engine keeps untyped views.
+    /// Pipelines are shared with the typed draw calls of the same format.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add("Test1", [10, 10], [1.0, 0.0, 0.0, 1.0]);
+    /// text.draw_raw(&mut encoder, &raw_color_output, gfx::format::Rgba8::get_format()).unwrap();
+    /// ```
+    pub fn draw_raw<C: CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RawRenderTargetView<R>,
+        format: gfx::format::Format
+    ) -> Result<(), Error> {
+        self.draw_viewport_raw(encoder, target, format, full_viewport(target.get_dimensions()), DEFAULT_PROJECTION)
+    }
+
+    fn draw_viewport_raw<C: CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RawRenderTargetView<R>,
+        format: gfx::format::Format,
+        viewport: [i32; 4],
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        self.draw_scene_raw(encoder, target, format, viewport, proj)?;
+        self.clear_scene();
+        Ok(())
+    }
+
+    /// Draw into the viewport like `draw_viewport`, but keep the queued text,
+    /// so the same scene may be drawn into several targets, e.g. for both
+    /// eyes in VR. Call `clear` once it's drawn everywhere.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_at("Label", [0.0, 1.0, 0.0], [1.0, 1.0, 1.0, 1.0]);
+    /// text.draw_preserve(&mut encoder, &left_eye, full_view, left_projection).unwrap();
+    /// text.draw_preserve(&mut encoder, &right_eye, full_view, right_projection).unwrap();
+    /// text.clear();
+    /// ```
+    pub fn draw_preserve<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        viewport: [i32; 4],
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        use gfx::memory::Typed;
+
+        // Drawing moves glyphs into the viewport and drops hidden labels, so
+        // it works on a copy of the sorted glyphs and their spans.
+        self.sort_layers();
+        let instance_data = self.instance_data.clone();
+        let spans = self.spans.clone();
+        let labels = self.labels.clone();
+        let result = self.draw_scene_raw(encoder, target.raw(), T::get_format(), viewport, proj);
+        self.instance_data = instance_data;
+        self.spans = spans;
+        self.labels = labels;
+        result
+    }
+
+    /// Draw the current scene into the targets of both eyes with their
+    /// projections, e.g. for VR, and clear state. Glyphs equal for both eyes,
+    /// which is all of them unless labels are decluttered, are uploaded once.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add_at("Label", [0.0, 1.0, 0.0], [1.0, 1.0, 1.0, 1.0]);
+    /// text.draw_stereo(&mut encoder, &left_eye, &right_eye, left_projection, right_projection).unwrap();
+    /// ```
+    pub fn draw_stereo<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        left_target: &RenderTargetView<R, T>,
+        right_target: &RenderTargetView<R, T>,
+        left_proj: [[f32; 4]; 4],
+        right_proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        let first_buffer = self.next_buffer;
+        self.draw_preserve(encoder, left_target, full_viewport(left_target.get_dimensions()), left_proj)?;
+        let left = self.stats();
+        // Draw from the buffers just filled for the left eye, so only glyphs
+        // which differ are uploaded again.
+        self.next_buffer = first_buffer;
+        self.draw_viewport(encoder, right_target, full_viewport(right_target.get_dimensions()), right_proj)?;
+        self.draw_calls += left.draw_calls;
+        self.glyphs_drawn += left.glyphs_drawn;
+        self.bytes_uploaded += left.bytes_uploaded;
+        self.pipeline_switches += left.pipeline_switches;
+        self.layout_time += left.layout_time;
+        Ok(())
+    }
+
+    /// Draw all queued glyphs into the viewport, without clearing state.
+    fn draw_scene_raw<C: CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RawRenderTargetView<R>,
+        format: gfx::format::Format,
+        viewport: [i32; 4],
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        let proj = viewport_projection(proj, viewport, target_size(target.get_dimensions()));
+        let screen_size = self.screen_size(target.get_dimensions(), viewport);
+        self.prepare_scene(proj, screen_size)?;
+        self.offset_screen_text(viewport);
+        self.cull_glyphs(proj, screen_size, viewport);
+        let len = self.instance_data.len();
+        self.draw_range_raw(encoder, target, format, proj, viewport, 0, len)
+    }
+
+    /// Drop glyphs lying entirely outside of the viewport, e.g. world labels
+    /// behind the camera, so they are neither uploaded nor rasterized. Must
+    /// follow `offset_screen_text`.
+    fn cull_glyphs(&mut self, proj: [[f32; 4]; 4], screen_size: [f32; 2], viewport: [i32; 4]) {
+        let scale = self.pixel_scale(viewport);
+        let visible = [
+            viewport[0] as f32 * scale[0],
+            viewport[1] as f32 * scale[1],
+            (viewport[0] + viewport[2]) as f32 * scale[0],
+            (viewport[1] + viewport[3]) as f32 * scale[1],
+        ];
+        // Glyphs of a label share its world position, project it once.
+        let mut last_world: Option<([f32; 3], Option<[f32; 2]>)> = None;
+        // Spans are compacted with the glyphs, as later passes and the
+        // upload of changed spans rely on them.
+        self.retain_glyphs(|_, instance| {
+            let origin = if instance.rotation[2] > 0.5 {
+                [0.0, 0.0]
+            } else {
+                let world_pos = instance.world_pos;
+                let origin = match last_world {
+                    Some((pos, origin)) if pos == world_pos => origin,
+                    _ => project_pixels(proj, world_pos, screen_size),
+                };
+                last_world = Some((world_pos, origin));
+                match origin {
+                    Some(origin) => origin,
+                    None => return false,
+                }
+            };
+            let half = [instance.size[0] / 2.0, instance.size[1] / 2.0];
+            let center = [origin[0] + instance.pos[0] + half[0], origin[1] + instance.pos[1] + half[1]];
+            // Rotated quads stay within the circle around their center.
+            let extent = if instance.rotation[1] == 0.0 {
+                half
+            } else {
+                let radius = half[0].hypot(half[1]);
+                [radius, radius]
+            };
+            center[0] + extent[0] > visible[0] && center[0] - extent[0] < visible[2] &&
+                center[1] + extent[1] > visible[1] && center[1] - extent[1] < visible[3]
+        });
+    }
+
+    /// Move screen-relative glyphs by the viewport origin and all glyphs by
+    /// the pixel offset.
+    fn offset_screen_text(&mut self, viewport: [i32; 4]) {
+        let scale = self.pixel_scale(viewport);
+        let origin_y = match self.origin {
+            Origin::TopLeft => viewport[1],
+            Origin::BottomLeft => viewport[1] + viewport[3],
+        };
+        let size = [viewport[2] as f32 * scale[0], viewport[3] as f32 * scale[1]];
+        for i in 0..self.spans.len() {
+            let fraction = self.fractions[i];
+            let offset = [
+                viewport[0] as f32 * scale[0] + (fraction[0] * size[0]).round(),
+                origin_y as f32 * scale[1] + (fraction[1] * size[1]).round(),
+            ];
+            if offset == [0.0, 0.0] {
+                continue;
+            }
+            let glyphs = self.span_range(i);
+            for instance in &mut self.instance_data[glyphs] {
+                // The screen relative flag, world labels are not moved.
+                if instance.rotation[2] > 0.5 {
+                    instance.pos[0] += offset[0];
+                    instance.pos[1] += offset[1];
+                }
+            }
+        }
+        if self.pixel_offset != [0.0, 0.0] {
+            let offset = [self.pixel_offset[0] * scale[0], self.pixel_offset[1] * scale[1]];
+            for instance in &mut self.instance_data {
+                instance.pos[0] += offset[0];
+                instance.pos[1] += offset[1];
+            }
+        }
+    }
+
+    /// Return number of layout pixels per target pixel, which differs from
+    /// one if the virtual resolution is set.
+    fn pixel_scale(&self, viewport: [i32; 4]) -> [f32; 2] {
+        match self.virtual_size {
+            Some((width, height)) =>
+                [width as f32 / max(viewport[2], 1) as f32, height as f32 / max(viewport[3], 1) as f32],
+            None => [1.0, 1.0],
+        }
+    }
+
+    /// Return size of the target in layout pixels, as passed to the shaders.
+    fn screen_size(&self, dimensions: texture::Dimensions, viewport: [i32; 4]) -> [f32; 2] {
+        let size = target_size(dimensions);
+        let scale = self.pixel_scale(viewport);
+        [size[0] * scale[0], size[1] * scale[1]]
+    }
+
+    /// Draw using provided projection matrix, testing and updating the given
+    /// depth-stencil target according to the state set with
+    /// `RendererBuilder::with_depth_stencil`. The stencil reference value is
+    /// used for both faces.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Draw text only where stencil was set to 1 by the panel shapes.
+    /// let mut text = gfx_text::new()
+    ///     .with_depth_stencil(Depth::default(),
+    ///                         Stencil::new(Comparison::Equal, 0xFF, (Keep, Keep, Keep)))
+    ///     .build(&mut factory)
+    ///     .unwrap();
+    /// text.add("Inside the panel", [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// text.draw_depth_stencil(&mut encoder, &color_output, &depth_output, 1, projection).unwrap();
+    /// ```
+    pub fn draw_depth_stencil<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        depth_target: &DepthStencilView<R, gfx::format::DepthStencil>,
+        stencil_ref: u8,
+        proj: [[f32; 4]; 4]
+    ) -> Result<(), Error> {
+        use gfx::memory::Typed;
+
+        let screen_size = self.screen_size(target.get_dimensions(), full_viewport(target.get_dimensions()));
+        self.prepare_scene(proj, screen_size)?;
+        self.offset_screen_text(full_viewport(target.get_dimensions()));
+        self.cull_glyphs(proj, screen_size, full_viewport(target.get_dimensions()));
+        let pso = self.prepare_depth_stencil_pso(T::get_format(), target.get_dimensions().3)?;
+        let srgb = srgb_flag(T::get_format());
+        self.update_locals(encoder, proj, screen_size, srgb);
+
+        let quad_buffer = self.quad_buffer.clone();
+        let locals = self.locals.clone();
+        let tint = self.tint;
+        let color = self.color.clone();
+        let len = self.instance_data.len();
+        self.draw_glyphs(encoder, &pso, 0, len, |instance_buffer| pipe_depth_stencil::Data {
+            vbuf: quad_buffer.clone(),
+            ibuf: instance_buffer,
+            locals: locals.clone(),
+            proj: proj,
+            tint: tint,
+            srgb: srgb,
+            screen_size: screen_size,
+            color: color.clone(),
+            out_color: target.raw().clone(),
+            depth_stencil: (depth_target.clone(), (stencil_ref, stencil_ref)),
+            scissor: scissor_rect(full_viewport(target.get_dimensions())),
+        })?;
+        self.clear_scene();
+        Ok(())
+    }
+
+    /// Do the per-frame work needed before drawing the queued glyphs.
+    fn prepare_scene(&mut self, proj: [[f32; 4]; 4], screen_size: [f32; 2]) -> Result<(), Error> {
+        self.sort_layers();
+        if self.declutter != Declutter::Off {
+            self.declutter_labels(proj, screen_size);
+        }
+        self.shrink_buffer()?;
+        self.draw_calls = 0;
+        self.glyphs_drawn = 0;
+        self.bytes_uploaded = 0;
+        self.pipeline_switches = 0;
+        self.layout_time = self.layout.take_layout_time();
+        Ok(())
+    }
+
+    /// Reorder queued texts by their layers.
+    fn sort_layers(&mut self) {
+        if self.layers.windows(2).all(|w| w[0] <= w[1]) {
+            return
+        }
+
+        let len = self.instance_data.len();
+        let span_end = |spans: &[usize], i: usize| spans.get(i + 1).cloned().unwrap_or(len);
+        // Stable sort keeps the order of texts within the same layer.
+        let mut order: Vec<usize> = (0..self.spans.len()).collect();
+        order.sort_by_key(|&i| self.layers[i]);
+
+        let mut instance_data = Vec::with_capacity(len);
+        let mut new_starts = vec![0; self.spans.len()];
+        let mut new_indices = vec![0; self.spans.len()];
+        for (new_index, &i) in order.iter().enumerate() {
+            new_starts[i] = instance_data.len();
+            new_indices[i] = new_index;
+            instance_data.extend_from_slice(&self.instance_data[self.spans[i]..span_end(&self.spans, i)]);
+        }
+
+        for label in &mut self.labels {
+            label.span = new_indices[label.span];
+        }
+        self.labels.sort_by_key(|label| label.span);
+
+        self.spans = order.iter().map(|&i| new_starts[i]).collect();
+        self.layers = order.iter().map(|&i| self.layers[i]).collect();
+        self.fractions = order.iter().map(|&i| self.fractions[i]).collect();
+        self.instance_data = instance_data;
+    }
+
+    fn update_locals<C: CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        proj: [[f32; 4]; 4],
+        screen_size: [f32; 2],
+        srgb: f32,
+    ) {
+        if self.shader_version.uses_constant_buffer() {
+            encoder.update_constant_buffer(&self.locals, &Locals {
+                proj: proj,
+                tint: self.tint,
+                screen_size: screen_size,
+                srgb: srgb,
             });
+        }
+    }
+
+    /// Draw the given range of queued glyphs, without clearing state.
+    fn draw_range<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        proj: [[f32; 4]; 4],
+        viewport: [i32; 4],
+        start: usize,
+        end: usize,
+    ) -> Result<(), Error> {
+        use gfx::memory::Typed;
+
+        self.draw_range_raw(encoder, target.raw(), T::get_format(), proj, viewport, start, end)
+    }
+
+    /// Draw the given range of queued glyphs into a target of the format.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_range_raw<C: CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RawRenderTargetView<R>,
+        format: gfx::format::Format,
+        proj: [[f32; 4]; 4],
+        viewport: [i32; 4],
+        start: usize,
+        end: usize,
+    ) -> Result<(), Error> {
+        let screen_size = self.screen_size(target.get_dimensions(), viewport);
+        let pso = self.prepare_pso(format, target.get_dimensions().3)?;
+        let srgb = srgb_flag(format);
+        self.update_locals(encoder, proj, screen_size, srgb);
 
-            // Top-left triangle.
-            // 0--3
-            // | /
-            // |/
-            // 1
-            self.index_data.push(index + 0);
-            self.index_data.push(index + 1);
-            self.index_data.push(index + 3);
-            // Bottom-right triangle.
-            //    3
-            //   /|
-            //  / |
-            // 1--2
-            self.index_data.push(index + 3);
-            self.index_data.push(index + 1);
-            self.index_data.push(index + 2);
+        let quad_buffer = self.quad_buffer.clone();
+        let locals = self.locals.clone();
+        let tint = self.tint;
+        let color = self.color.clone();
+        self.draw_glyphs(encoder, &pso, start, end, |instance_buffer| pipe::Data {
+            vbuf: quad_buffer.clone(),
+            ibuf: instance_buffer,
+            locals: locals.clone(),
+            proj: proj,
+            tint: tint,
+            srgb: srgb,
+            screen_size: screen_size,
+            color: color.clone(),
+            out_color: target.clone(),
+            scissor: scissor_rect(viewport),
+        })
+    }
 
-            x += ch_info.x_advance as f32;
+    /// Draw the given range of queued glyphs with the given pipeline.
+    fn draw_glyphs<C, D, M>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        pso: &PipelineState<R, D::Meta>,
+        mut start: usize,
+        end: usize,
+        mut make_data: M,
+    ) -> Result<(), Error>
+        where C: CommandBuffer<R>,
+              D: gfx::pso::PipelineData<R>,
+              M: FnMut(gfx::handle::RawBuffer<R>) -> D,
+    {
+        // Split the range into chunks which fit into the instance buffer,
+        // each of them is drawn with its own draw call.
+        while start < end {
+            let chunk_end = min(start + self.max_buffer_size, end);
+            let (slice, buffer) = self.upload_chunk(encoder, start, chunk_end)?;
+            encoder.draw(&slice, pso, &make_data(buffer));
+            self.draw_calls += 1;
+            self.glyphs_drawn += chunk_end - start;
+            start = chunk_end;
         }
+        Ok(())
     }
 
-    /// Draw the current scene and clear state.
-    ///
-    /// # Examples
-    ///
-    /// ```ignore
-    /// text.add("Test1", [10, 10], [1.0, 0.0, 0.0, 1.0]);
-    /// text.add("Test2", [20, 20], [0.0, 1.0, 0.0, 1.0]);
-    /// text.draw(&mut encoder, &color_output).unwrap();
-    /// ```
-    pub fn draw<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+    fn clear_scene(&mut self) {
+        self.instance_data.clear();
+        self.spans.clear();
+        self.layers.clear();
+        self.fractions.clear();
+        self.labels.clear();
+    }
+
+    /// Reallocate the instance buffer with smaller size if it was barely used
+    /// for a while.
+    fn shrink_buffer(&mut self) -> Result<(), Error> {
+        let capacity = self.buffer_capacity();
+        let used = min(self.instance_data.len(), self.max_buffer_size);
+        if self.shrink_after == 0 || capacity <= self.min_buffer_size || used * 4 >= capacity {
+            self.low_usage_frames = 0;
+            self.low_usage_peak = 0;
+            return Ok(());
+        }
+
+        self.low_usage_frames += 1;
+        self.low_usage_peak = max(self.low_usage_peak, used);
+        if self.low_usage_frames >= self.shrink_after {
+            // Leave some space to grow.
+            let len = grow_buffer_size(self.min_buffer_size, self.low_usage_peak * 2);
+            for buffer in &mut self.instance_buffers {
+                *buffer = create_instance_buffer(&mut self.factory, len, self.packed, self.instanced)?;
+            }
+            self.low_usage_frames = 0;
+            self.low_usage_peak = 0;
+        }
+        Ok(())
+    }
+
+    /// Return current buffer and atlas statistics together with the work done
+    /// by the last draw, e.g. to attribute frame time to text rendering.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            buffer_capacity: self.buffer_capacity(),
+            atlas_width: self.layout.atlas().width(),
+            atlas_height: self.layout.atlas().height(),
+            atlas_occupancy: self.layout.atlas().bitmap.get_occupancy(),
+            glyphs_queued: self.instance_data.len(),
+            draw_calls: self.draw_calls,
+            glyphs_drawn: self.glyphs_drawn,
+            bytes_uploaded: self.bytes_uploaded,
+            pipeline_switches: self.pipeline_switches,
+            layout_time: self.layout_time,
+        }
+    }
+
+    /// Return capacity of the largest instance buffer.
+    fn buffer_capacity(&self) -> usize {
+        self.instance_buffers.iter().map(|buffer| buffer.len).max().unwrap_or(0)
+    }
+
+    /// Upload given range of glyph instances into the next instance buffer of
+    /// the ring, reallocating it if there is no enough space, and return the
+    /// slice and the buffer to draw them.
+    fn upload_chunk<C: CommandBuffer<R>>(
         &mut self,
         encoder: &mut Encoder<R, C>,
-        target: &RenderTargetView<R, T>
-    ) -> Result<(), Error> {
-        self.draw_at(encoder, target, DEFAULT_PROJECTION)
+        start: usize,
+        end: usize,
+    ) -> Result<(gfx::Slice<R>, gfx::handle::RawBuffer<R>), Error> {
+        let len = end - start;
+        let index = self.next_buffer;
+        self.next_buffer = (index + 1) % self.instance_buffers.len();
+        let capacity = self.instance_buffers[index].len;
+        if len > capacity {
+            let buf_len = min(grow_buffer_size(capacity, len), self.max_buffer_size);
+            self.instance_buffers[index] = create_instance_buffer(&mut self.factory, buf_len, self.packed, self.instanced)?;
+        }
+        let (packed, instanced) = (self.packed, self.instanced);
+        let instance_buffer = &mut self.instance_buffers[index];
+
+        // Most of the text is usually the same from frame to frame, so upload
+        // only spans which differ from the buffer contents. Adjacent changed
+        // spans are merged into a single update.
+        let first_span = self.spans.iter().position(|&s| s > start).unwrap_or(self.spans.len());
+        let bounds = self.spans[first_span..].iter().cloned()
+            .take_while(|&s| s < end)
+            .chain(Some(end));
+        let mut span_start = start;
+        let mut dirty_start = None;
+        for span_end in bounds {
+            let span = &self.instance_data[span_start..span_end];
+            let uploaded = instance_buffer.uploaded.get(span_start - start..span_end - start);
+            if uploaded != Some(span) {
+                dirty_start = dirty_start.or(Some(span_start));
+            } else if let Some(dirty) = dirty_start.take() {
+                self.bytes_uploaded += update_instances(encoder, &instance_buffer.buffer, packed, instanced,
+                                                        &self.instance_data[dirty..span_start], dirty - start)?;
+            }
+            span_start = span_end;
+        }
+        if let Some(dirty) = dirty_start {
+            self.bytes_uploaded += update_instances(encoder, &instance_buffer.buffer, packed, instanced,
+                                                    &self.instance_data[dirty..end], dirty - start)?;
+        }
+        instance_buffer.uploaded.clear();
+        instance_buffer.uploaded.extend_from_slice(&self.instance_data[start..end]);
+
+        let mut slice = self.quad_slice.clone();
+        if instanced {
+            slice.instances = Some((len as gfx::InstanceCount, 0));
+        } else {
+            slice.end = (len * QUAD_INDICES.len()) as gfx::VertexCount;
+        }
+        Ok((slice, instance_buffer.buffer.clone()))
     }
 
-    /// Draw using provided projection matrix.
+    /// Render text into a new offscreen RGBA texture of the exactly needed
+    /// size and return its view together with the size. Drawing such texture
+    /// as a single quad is much cheaper than drawing the static text each
+    /// frame. Colors in the texture are premultiplied by alpha.
+    ///
+    /// Text queued for the next draw is kept intact.
     ///
     /// # Examples
     ///
     /// ```ignore
-    /// text.add_at("Test1", [6.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]);
-    /// text.add_at("Test2", [0.0, 5.0, 0.0], [0.0, 1.0, 0.0, 1.0]);
-    /// text.draw_at(&mut encoder, &color_output, camera_projection).unwrap();
+    /// let (credits, size) = text.render_to_texture(&mut encoder, "Credits", [1.0; 4]).unwrap();
+    /// encoder.flush(&mut device);
     /// ```
-    pub fn draw_at<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+    #[allow(clippy::type_complexity)]
+    pub fn render_to_texture<C: CommandBuffer<R>, K: Into<Color>>(
         &mut self,
         encoder: &mut Encoder<R, C>,
-        target: &RenderTargetView<R, T>,
-        proj: [[f32; 4]; 4]
-    ) -> Result<(), Error> {
-        use gfx::memory::{self, Typed};
-        use gfx::buffer;
+        text: &str,
+        color: K,
+    ) -> Result<(ShaderResourceView<R, [f32; 4]>, (u16, u16)), Error> {
+        use std::mem::replace;
 
-        let ver_len = self.vertex_data.len();
-        let ver_buf_len = self.vertex_buffer.len();
-        let ind_len = self.index_data.len();
-        let ind_buf_len = self.index_buffer.len();
+        let color = color.into().0;
+        let (width, height) = self.measure(text);
+        let size = (max(width, 1) as u16, max(height, 1) as u16);
+        let (_, view, target) =
+            self.factory.create_render_target::<gfx::format::Rgba8>(size.0, size.1)?;
+        encoder.clear(&target, [0.0, 0.0, 0.0, 0.0]);
 
-        // Reallocate buffers if there is no enough space for data.
-        if ver_len > ver_buf_len {
-            let len = grow_buffer_size(ver_buf_len, ver_len);
-            self.vertex_buffer = self.factory.create_buffer(
-                    len, buffer::Role::Vertex, memory::Usage::Dynamic, memory::Bind::empty()
-                ).expect("Could not reallocate vertex buffer");
-        }
-        if ind_len > ind_buf_len {
-            let len = grow_buffer_size(ind_buf_len, ind_len);
-            self.index_buffer = self.factory.create_buffer(
-                    len, buffer::Role::Index, memory::Usage::Dynamic, memory::Bind::empty()
-                ).expect("Could not reallocate index buffer");
-        }
+        // Texture has the size of the text, so it's never scaled.
+        let blend = replace(&mut self.blend, BlendMode::Premultiplied);
+        let virtual_size = self.virtual_size.take();
+        let origin = replace(&mut self.origin, Origin::TopLeft);
+        let result = self.draw_isolated(encoder, &target, |renderer| {
+            renderer.add(text, [0, 0], color);
+        });
+        self.blend = blend;
+        self.virtual_size = virtual_size;
+        self.origin = origin;
+        result.map(|_| (view, size))
+    }
 
-        encoder.update_buffer(&self.vertex_buffer, &self.vertex_data, 0)?;
-        encoder.update_buffer(&self.index_buffer, &self.index_data, 0)?;
+    /// Draw the whole font texture stretched to the given screen rectangle
+    /// (x, y, width, height in pixels). Useful for debugging charset and
+    /// texture packing settings.
+    ///
+    /// Text queued for the next draw is kept intact.
+    pub fn draw_atlas<C: CommandBuffer<R>, T: gfx::format::RenderFormat>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        rect: [i32; 4],
+    ) -> Result<(), Error> {
+        self.draw_isolated(encoder, target, |renderer| {
+            let pos = renderer.layout_pos([rect[0], rect[1]]);
+            renderer.push_instances(Some(Instance {
+                pos: [pos[0] as f32, pos[1] as f32],
+                size: [rect[2] as f32, rect[3] as f32],
+                tex: [0.0, 0.0, 1.0, 1.0],
+                world_pos: [0.0, 0.0, 0.0],
+                color: [1.0, 1.0, 1.0, 1.0],
+                rotation: [1.0, 0.0, 1.0],
+            }).into_iter());
+        })
+    }
 
-        let ni = self.index_data.len() as gfx::VertexCount;
-        let mut slice: gfx::Slice<R> = gfx::Slice {
-            base_vertex: 0,
-            start: 0,
-            end: self.index_buffer.len() as u32,
-            instances: None,
-            buffer: gfx::IndexBuffer::Index32(self.index_buffer.clone()),
-        };
-        slice.end = ni;
+    /// Draw only the glyphs queued by the given function, keeping the current
+    /// scene for the next draw.
+    fn draw_isolated<C, T, A>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        add: A,
+    ) -> Result<(), Error>
+        where C: CommandBuffer<R>,
+              T: gfx::format::RenderFormat,
+              A: FnOnce(&mut Self),
+    {
+        use std::mem::take;
 
-        let data = pipe::Data {
-            vbuf: self.vertex_buffer.clone(),
-            proj: proj,
-            screen_size: {
-                let (w, h, _, _) = target.get_dimensions();
-                [w as f32, h as f32]
-            },
-            color: self.color.clone(),
-            out_color: target.raw().clone(),
-        };
+        let instance_data = take(&mut self.instance_data);
+        let spans = take(&mut self.spans);
+        let layers = take(&mut self.layers);
+        let fractions = take(&mut self.fractions);
+        let labels = take(&mut self.labels);
 
-        self.prepare_pso(T::get_format())?;
-        let pso = &self.pso_map[&T::get_format()];
+        add(self);
+        let result = self.draw(encoder, target);
 
-        // Clear state.
-        self.vertex_data.clear();
-        self.index_data.clear();
+        self.instance_data = instance_data;
+        self.spans = spans;
+        self.layers = layers;
+        self.fractions = fractions;
+        self.labels = labels;
+        result
+    }
 
-        encoder.draw(&slice, pso, &data);
-        Ok(())
+    /// Return byte offsets of all grapheme cluster boundaries of the string,
+    /// including its start and end, together with the caret positions
+    /// relative to the text origin. Positions are measured in logical order,
+    /// so they are exact only for text of a single direction.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Caret positions before "e", after "e\u{301}" and after "t".
+    /// let carets = text.caret_positions("e\u{301}t");
+    /// assert_eq!(carets.iter().map(|c| c.0).collect::<Vec<_>>(), [0, 3, 4]);
+    /// ```
+    pub fn caret_positions(&self, text: &str) -> Vec<(usize, i32)> {
+        self.layout.caret_positions(text)
     }
 
     /// Get the bounding box size of a string as rendered by this font.
     pub fn measure(&self, text: &str) -> (i32, i32) {
-        let mut width = 0;
-        let mut last_char = None;
+        self.layout.measure(text)
+    }
 
-        for ch in text.chars() {
-            let ch_info = match self.font_bitmap.find_char(ch) {
-                Some(info) => info,
-                None => continue,
-            };
-            last_char = Some(ch_info);
+    /// Return id of the text added last to the current draw scene, to
+    /// compare with the result of `hit_test`.
+    pub fn last_text_id(&self) -> Option<TextId> {
+        self.spans.len().checked_sub(1).map(TextId)
+    }
 
-            width += ch_info.x_advance;
-        }
+    /// Return the topmost text added since the last draw whose glyph quad
+    /// (or background) contains the point, given in the same coordinates as
+    /// the text position, e.g. to find the clicked label.
+    ///
+    /// World labels and texts added with `add_normalized` are not tested,
+    /// their screen position is known only when drawing. Rotated glyphs are
+    /// tested with their unrotated quads.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// text.add("Quit", [10, 10], [1.0, 1.0, 1.0, 1.0]);
+    /// let quit = text.last_text_id();
+    /// if clicked && text.hit_test(mouse_pos) == quit {
+    ///     running = false;
+    /// }
+    /// ```
+    pub fn hit_test(&self, point: [i32; 2]) -> Option<TextId> {
+        let point = self.layout_pos(point);
+        let (x, y) = (point[0] as f32, point[1] as f32);
+        let len = self.instance_data.len();
+        (0..self.spans.len()).filter(|&i| {
+            let end = self.spans.get(i + 1).cloned().unwrap_or(len);
+            self.fractions[i] == [0.0, 0.0] && self.instance_data[self.spans[i]..end].iter().any(|instance| {
+                instance.rotation[2] > 0.5 &&
+                    x >= instance.pos[0] && x < instance.pos[0] + instance.size[0] &&
+                    y >= instance.pos[1] && y < instance.pos[1] + instance.size[1]
+            })
+        }).max_by_key(|&i| self.layers[i]).map(TextId)
+    }
 
-        match last_char {
-            Some(info) => width += info.x_offset + info.width - info.x_advance,
-            None => (),
-        }
+    /// Return how far the pen moves after the char in the current style, or
+    /// `None` if the font doesn't have it. Together with `kerning` it allows
+    /// to compute widths incrementally, e.g. for column alignment.
+    pub fn char_advance(&self, ch: char) -> Option<i32> {
+        self.layout.char_advance(ch)
+    }
 
-        (width, self.font_bitmap.get_font_height() as i32)
+    /// Return the kerning adjustment added to the advance of the left char
+    /// when it's followed by the right one. Kerning is only applied with
+    /// the `shaping` feature, it's zero otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let width = text.char_advance('A').unwrap() + text.kerning('A', 'V') + text.char_advance('V').unwrap();
+    /// ```
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        self.layout.kerning(left, right)
     }
 }
 
 // Some missing helpers.
 
+fn quad_instance(quad: &GlyphQuad, world_pos: [f32; 3], screen_rel: f32, color: [f32; 4]) -> Instance {
+    // Layers of color glyphs fade together with the text.
+    let color = match quad.color {
+        Some(layer) => [layer[0], layer[1], layer[2], layer[3] * color[3]],
+        None => color,
+    };
+    Instance {
+        pos: [quad.pos[0] as f32, quad.pos[1] as f32],
+        size: [quad.size[0] as f32, quad.size[1] as f32],
+        tex: quad.tex,
+        world_pos: world_pos,
+        color: color,
+        rotation: [1.0, 0.0, screen_rel],
+    }
+}
+
+// Quads of the panel filling the rectangle (x, y, width, height).
+fn panel_quads(layout: &TextLayout, rect: [i32; 4], panel: Panel) -> Vec<GlyphQuad> {
+    let (x, y, width, height) = (rect[0], rect[1], rect[2], rect[3]);
+    match panel {
+        Panel::Solid => vec![layout.solid_quad(rect)],
+        Panel::Rounded(radius) => {
+            let radius = max(min(radius, min(width, height) / 2), 0);
+            let mut quads = vec![layout.solid_quad([x, y + radius, width, height - radius * 2])];
+            for row in 0..radius {
+                // Distance of the row center from the center of the corner.
+                let dy = (radius - row) as f32 - 0.5;
+                let inset = radius - ((radius * radius) as f32 - dy * dy).max(0.0).sqrt().round() as i32;
+                quads.push(layout.solid_quad([x + inset, y + row, width - inset * 2, 1]));
+                quads.push(layout.solid_quad([x + inset, y + height - row - 1, width - inset * 2, 1]));
+            }
+            quads
+        },
+        Panel::NineSlice { ch, border } => {
+            let image = match layout.atlas().bitmap().find_custom(ch) {
+                Some(image) if image.width > 0 && image.height > 0 => image,
+                _ => return Vec::new(),
+            };
+            // Border in the image and on the screen, either may be smaller
+            // than asked if the image or the panel is.
+            let src = [min(border, image.width / 2), min(border, image.height / 2)];
+            let dst = [min(src[0], width / 2), min(src[1], height / 2)];
+            let tex_px = [image.tex_width / image.width as f32, image.tex_height / image.height as f32];
+            // Screen and texture edges of the slices along every axis.
+            let slices = |pos: i32, size: i32, tex: f32, tex_size: f32, src: i32, dst: i32, px: f32| [
+                (pos, dst, tex, src as f32 * px),
+                (pos + dst, size - dst * 2, tex + src as f32 * px, tex_size - src as f32 * px * 2.0),
+                (pos + size - dst, dst, tex + tex_size - src as f32 * px, src as f32 * px),
+            ];
+            let columns = slices(x, width, image.tex[0], image.tex_width, src[0], dst[0], tex_px[0]);
+            let rows = slices(y, height, image.tex[1], image.tex_height, src[1], dst[1], tex_px[1]);
+            let mut quads = Vec::with_capacity(9);
+            for &(y, height, tex_y, tex_height) in &rows {
+                for &(x, width, tex_x, tex_width) in &columns {
+                    quads.push(GlyphQuad {
+                        pos: [x, y],
+                        size: [width, height],
+                        tex: [tex_x, tex_y, tex_width, tex_height],
+                        ..layout.solid_quad(rect)
+                    });
+                }
+            }
+            quads
+        },
+    }
+}
+
+// Cut the quad to the horizontal range together with its texture, `None` if
+// it lies outside. Glows can't be cut, so cut quads have none.
+fn clip_quad(quad: GlyphQuad, left: i32, right: i32) -> Option<GlyphQuad> {
+    let start = max(quad.pos[0], left);
+    let end = min(quad.pos[0] + quad.size[0], right);
+    if start >= end {
+        return None;
+    }
+    if start == quad.pos[0] && end == quad.pos[0] + quad.size[0] {
+        return Some(quad);
+    }
+    let tex_scale = quad.tex[2] / quad.size[0] as f32;
+    Some(GlyphQuad {
+        pos: [start, quad.pos[1]],
+        size: [end - start, quad.size[1]],
+        tex: [quad.tex[0] + (start - quad.pos[0]) as f32 * tex_scale, quad.tex[1], (end - start) as f32 * tex_scale, quad.tex[3]],
+        glow: None,
+        outline: None,
+        ..quad
+    })
+}
+
+// Return distance between tab stops of the given number of columns. Column
+// width is the advance of space or the half of the font size if there is no
+// space.
+fn tab_stop_width(bitmap: &BitmapFont, font_size: i32, columns: u8) -> i32 {
+    let column_width = bitmap.find_char(0, ' ').map_or(font_size / 2, |ch| ch.x_advance);
+    max(column_width * columns as i32, 1)
+}
+
+// Convert feature or axis name into OpenType tag, shorter names are padded
+// with spaces.
+fn opentype_tag(name: &str) -> [u8; 4] {
+    let mut tag = [b' '; 4];
+    for (dst, &src) in tag.iter_mut().zip(name.as_bytes()) {
+        *dst = src;
+    }
+    tag
+}
+
 fn grow_buffer_size(mut current_size: usize, desired_size: usize) -> usize {
     if current_size < 1 {
         current_size = 1;
@@ -540,16 +4252,281 @@ fn grow_buffer_size(mut current_size: usize, desired_size: usize) -> usize {
     current_size
 }
 
-fn create_texture_r8_static<R: Resources, F: Factory<R>>(
+fn target_size(dimensions: texture::Dimensions) -> [f32; 2] {
+    let (w, h, _, _) = dimensions;
+    [w as f32, h as f32]
+}
+
+// Project the world position onto the target like the vertex shader, in
+// pixels from the top left corner. `None` if it's behind the camera.
+fn project_pixels(proj: [[f32; 4]; 4], p: [f32; 3], screen_size: [f32; 2]) -> Option<[f32; 2]> {
+    let project = |row: usize| {
+        proj[0][row] * p[0] + proj[1][row] * p[1] + proj[2][row] * p[2] + proj[3][row]
+    };
+    let (x, y, z) = (project(0), project(1), project(2));
+    if z <= 0.0 {
+        return None;
+    }
+    Some([(x / z + 1.0) / 2.0 * screen_size[0], (1.0 - y / z) / 2.0 * screen_size[1]])
+}
+
+fn full_viewport(dimensions: texture::Dimensions) -> [i32; 4] {
+    let (w, h, _, _) = dimensions;
+    [0, 0, w as i32, h as i32]
+}
+
+// Whether the shader converts colors to linear ones, which targets with an
+// sRGB format encode again when written.
+fn srgb_flag(format: gfx::format::Format) -> f32 {
+    match format.1 {
+        gfx::format::ChannelType::Srgb => 1.0,
+        _ => 0.0,
+    }
+}
+
+fn scissor_rect(viewport: [i32; 4]) -> gfx::Rect {
+    let x = max(viewport[0], 0);
+    let y = max(viewport[1], 0);
+    gfx::Rect {
+        x: x as u16,
+        y: y as u16,
+        w: max(viewport[0] + viewport[2] - x, 0) as u16,
+        h: max(viewport[1] + viewport[3] - y, 0) as u16,
+    }
+}
+
+// Make the projection map onto the viewport instead of the whole target.
+// NOTE: Shaders divide by `z` rather than by `w`, so the translation goes
+// into the `z` column.
+fn viewport_projection(proj: [[f32; 4]; 4], viewport: [i32; 4], screen_size: [f32; 2]) -> [[f32; 4]; 4] {
+    let sx = viewport[2] as f32 / screen_size[0];
+    let sy = viewport[3] as f32 / screen_size[1];
+    let tx = (2 * viewport[0] + viewport[2]) as f32 / screen_size[0] - 1.0;
+    let ty = 1.0 - (2 * viewport[1] + viewport[3]) as f32 / screen_size[1];
+    let mut result = proj;
+    for column in &mut result {
+        column[0] = sx * column[0] + tx * column[2];
+        column[1] = sy * column[1] + ty * column[2];
+    }
+    result
+}
+
+fn rasterizer(multisample: bool) -> gfx::state::Rasterizer {
+    let mut rasterizer = gfx::state::Rasterizer::new_fill().with_cull_back();
+    if multisample {
+        rasterizer.samples = Some(gfx::state::MultiSample);
+    }
+    rasterizer
+}
+
+fn shader_sources(version: ShaderVersion, distance_field: bool) -> (&'static [u8], &'static [u8]) {
+    if distance_field {
+        version.distance_field_sources()
+    } else {
+        version.sources()
+    }
+}
+
+fn create_any_shader_set<R: Resources, F: Factory<R>>(
+    factory: &mut F,
+    distance_field: bool,
+) -> Result<(gfx::ShaderSet<R>, ShaderVersion), ProgramError> {
+    let mut last_error = None;
+    for &version in ShaderVersion::ALL {
+        let (vs, fs) = shader_sources(version, distance_field);
+        match factory.create_shader_set(vs, fs) {
+            Ok(shaders) => return Ok((shaders, version)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap())
+}
+
+// Names of the uniforms linked to the pipeline, the constant buffer and the
+// global constants. Programs use either of them, so the other one is left
+// unnamed and unlinked.
+fn uniform_names(version: ShaderVersion) -> (&'static str, [&'static str; 4]) {
+    if version.uses_constant_buffer() {
+        ("Locals", [""; 4])
+    } else {
+        ("", ["u_Screen_Size", "u_Proj", "u_Tint", "u_Srgb"])
+    }
+}
+
+// Link the program, checking that vertex attributes of programs with fixed
+// slots are where the attribute table puts them.
+fn create_program<R: Resources, F: Factory<R>>(
+    factory: &mut F,
+    shaders: &gfx::ShaderSet<R>,
+    version: ShaderVersion,
+) -> Result<gfx::handle::Program<R>, PipelineStateError<String>> {
+    use gfx::pso::InitError;
+
+    let program = factory.create_program(shaders).map_err(ProgramError::Link)?;
+    if version.has_fixed_attribute_slots() {
+        for attribute in &program.get_info().vertex_attributes {
+            let slot = shaders::ATTRIBUTES.iter().position(|&name| name == attribute.name);
+            if slot != Some(attribute.slot as usize) {
+                return Err(PipelineStateError::DescriptorInit(InitError::VertexImport(attribute.name.clone(), None)));
+            }
+        }
+    }
+    Ok(program)
+}
+
+fn create_instance_buffer<R: Resources, F: Factory<R>>(
+    factory: &mut F,
+    len: usize,
+    packed: bool,
+    instanced: bool,
+) -> Result<InstanceBuffer<R>, gfx::buffer::CreationError> {
+    use gfx::buffer;
+    use gfx::memory::{self, Typed};
+
+    // NOTE: gfx maps only upload and download buffers, vertex buffers are
+    // updated by the encoder.
+    let (role, usage, bind) = (buffer::Role::Vertex, memory::Usage::Dynamic, memory::Bind::empty());
+    let elements = if instanced { len } else { len * QUAD_VERTICES.len() };
+    let buffer = if packed {
+        factory.create_buffer::<PackedInstance>(elements, role, usage, bind)?.raw().clone()
+    } else {
+        factory.create_buffer::<Instance>(elements, role, usage, bind)?.raw().clone()
+    };
+    Ok(InstanceBuffer { buffer: buffer, len: len, uploaded: Vec::new() })
+}
+
+// Write the instances into the buffer at the given instance offset, packing
+// them and repeating them for every corner if needed, and return the number
+// of bytes written.
+fn update_instances<R: Resources, C: CommandBuffer<R>>(
+    encoder: &mut Encoder<R, C>,
+    buffer: &gfx::handle::RawBuffer<R>,
+    packed: bool,
+    instanced: bool,
+    instances: &[Instance],
+    offset: usize,
+) -> Result<usize, UpdateError<usize>> {
+    use std::mem::{size_of, size_of_val};
+    use gfx::memory::Typed;
+
+    let corners = if instanced { 1 } else { QUAD_VERTICES.len() };
+    if packed {
+        let packed: Vec<PackedInstance> = instances.iter()
+            .flat_map(|instance| ::std::iter::repeat_n(pack_instance(instance), corners))
+            .collect();
+        encoder.update_buffer(&Buffer::<R, PackedInstance>::new(buffer.clone()), &packed, offset * corners)?;
+        Ok(packed.len() * size_of::<PackedInstance>())
+    } else if instanced {
+        encoder.update_buffer(&Buffer::<R, Instance>::new(buffer.clone()), instances, offset)?;
+        Ok(size_of_val(instances))
+    } else {
+        let repeated: Vec<Instance> = instances.iter()
+            .flat_map(|&instance| ::std::iter::repeat_n(instance, corners))
+            .collect();
+        encoder.update_buffer(&Buffer::<R, Instance>::new(buffer.clone()), &repeated, offset * corners)?;
+        Ok(repeated.len() * size_of::<Instance>())
+    }
+}
+
+// Rate of the instance attributes, per vertex if instancing isn't supported.
+fn instance_rate(instanced: bool) -> gfx::pso::buffer::InstanceRate {
+    if instanced { 1 } else { 0 }
+}
+
+// Vertices and indices of the given number of unit quads.
+fn quad_geometry(count: usize) -> (Vec<Vertex>, Vec<u16>) {
+    let vertices = (0..count).flat_map(|_| QUAD_VERTICES.iter().cloned()).collect();
+    let indices = (0..count)
+        .flat_map(|quad| QUAD_INDICES.iter().map(move |&i| (quad * QUAD_VERTICES.len()) as u16 + i))
+        .collect();
+    (vertices, indices)
+}
+
+// Return names and formats of the instance attributes and their stride.
+fn instance_attributes(packed: bool) -> (Vec<(&'static str, gfx::pso::buffer::Element<gfx::format::Format>)>, gfx::pso::buffer::ElemStride) {
+    use std::mem::size_of;
+    use gfx::pso::buffer::Structure;
+
+    // All attributes but the corner of the quad.
+    let names = &shaders::ATTRIBUTES[1..];
+    if packed {
+        let attributes = names.iter().map(|&name| (name, PackedInstance::query(name).unwrap())).collect();
+        (attributes, size_of::<PackedInstance>() as gfx::pso::buffer::ElemStride)
+    } else {
+        let attributes = names.iter().map(|&name| (name, Instance::query(name).unwrap())).collect();
+        (attributes, size_of::<Instance>() as gfx::pso::buffer::ElemStride)
+    }
+}
+
+fn pack_instance(instance: &Instance) -> PackedInstance {
+    use gfx::format::{F16, I8Norm, U16Norm, U8Norm};
+
+    let half = |value: f32| F16(half_float(value));
+    let unorm16 = |value: f32| U16Norm((value.clamp(0.0, 1.0) * 65535.0).round() as u16);
+    let unorm8 = |value: f32| U8Norm((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+    let inorm8 = |value: f32| I8Norm((value.clamp(-1.0, 1.0) * 127.0).round() as i8);
+    PackedInstance {
+        pos: [half(instance.pos[0]), half(instance.pos[1])],
+        size: [half(instance.size[0]), half(instance.size[1])],
+        tex: [unorm16(instance.tex[0]), unorm16(instance.tex[1]), unorm16(instance.tex[2]), unorm16(instance.tex[3])],
+        world_pos: instance.world_pos,
+        color: [unorm8(instance.color[0]), unorm8(instance.color[1]), unorm8(instance.color[2]), unorm8(instance.color[3])],
+        rotation: [inorm8(instance.rotation[0]), inorm8(instance.rotation[1]), inorm8(instance.rotation[2]), I8Norm(0)],
+    }
+}
+
+// Convert to the nearest half float, too large values become infinite.
+fn half_float(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16 & 0x8000) as u16;
+    let exponent = (bits >> 23 & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    if exponent >= 31 {
+        sign | 0x7C00
+    } else if exponent <= 0 {
+        // Subnormal or zero.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent);
+        sign | ((mantissa + 0x1000) >> 13) as u16
+    } else {
+        // Rounding may carry into the exponent, which is still correct.
+        sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+    }
+}
+
+// Maximal texture width and height reported by the device.
+fn device_texture_size<R: Resources, F: Factory<R>>(factory: &F) -> u16 {
+    match factory.get_capabilities().max_texture_size {
+        // Unknown, e.g. for dummy devices.
+        0 => u16::MAX,
+        size => min(size, u16::MAX as usize) as u16,
+    }
+}
+
+fn create_font_texture<R: Resources, F: Factory<R>>(
     factory: &mut F,
-    width: u16,
-    height: u16,
-    data: &[u8],
-) -> Result<gfx::handle::ShaderResourceView<R, f32>, CombinedError> {
-    let kind = texture::Kind::D2(width, height, texture::AaMode::Single);
-    let (_, texture_view) =
+    font: &FontAtlas,
+) -> Result<gfx::handle::ShaderResourceView<R, f32>, Error> {
+    // NOTE: Glyphs registered later may grow the texture past the limit.
+    font::check_size(max(font.width(), font.height()) as usize, device_texture_size(factory))?;
+    let kind = texture::Kind::D2(font.width(), font.height(), texture::AaMode::Single);
+    let data = &[font.image()];
+    // Distance fields take three channels, RGB textures are poorly
+    // supported, so they are stored as RGBA.
+    let texture_view = if font.bitmap().get_distance_range() > 0 {
+        use gfx::memory::Typed;
+
+        let (_, view) = factory.create_texture_immutable_u8::<(gfx::format::R8_G8_B8_A8, gfx::format::Unorm)>(
+            kind, texture::Mipmap::Provided, data)?;
+        // NOTE: Pipeline doesn't check the view type, so the view is kept
+        // with the same type as of the single channel one.
+        Typed::new(view.raw().clone())
+    } else {
         factory.create_texture_immutable_u8::<(gfx::format::R8, gfx::format::Unorm)>(
-            kind, texture::Mipmap::Provided, &[data])?;
+            kind, texture::Mipmap::Provided, data)?.1
+    };
     Ok(texture_view)
 }
 
@@ -557,68 +4534,425 @@ fn create_texture_r8_static<R: Resources, F: Factory<R>>(
 mod shader_structs {
     extern crate gfx;
 
+    use gfx::format::{F16, I8Norm, U16Norm, U8Norm};
+
     gfx_vertex_struct!( Vertex {
+        // Corner of the unit quad, (0, 0) is the top-left one.
+        corner: [f32; 2] = "a_Corner",
+    });
+
+    gfx_vertex_struct!( Instance {
         pos: [f32; 2] = "a_Pos",
-        tex: [f32; 2] = "a_TexCoord",
+        size: [f32; 2] = "a_Size",
+        // Top-left corner, width and height of the glyph in the texture.
+        tex: [f32; 4] = "a_TexRect",
         world_pos: [f32; 3] = "a_World_Pos",
-        // Should be bool but gfx-rs doesn't support it.
-        screen_rel: i32 = "a_Screen_Rel",
         color: [f32; 4] = "a_Color",
+        // Cosine and sine of the quad rotation around its center, and 1.0 if
+        // the position is relative to the screen (0.0 relative to the world
+        // position). The flag should be bool but gfx-rs doesn't support it
+        // and integer attributes are not available in older GLSL versions.
+        rotation: [f32; 3] = "a_Rotation",
+    });
+
+    // Same as `Instance` with smaller types, see
+    // `RendererBuilder::with_packed_instances`.
+    gfx_vertex_struct!( PackedInstance {
+        pos: [F16; 2] = "a_Pos",
+        size: [F16; 2] = "a_Size",
+        tex: [U16Norm; 4] = "a_TexRect",
+        world_pos: [f32; 3] = "a_World_Pos",
+        color: [U8Norm; 4] = "a_Color",
+        // The last component is unused.
+        rotation: [I8Norm; 4] = "a_Rotation",
+    });
+
+    // Same uniforms as the globals below, for backends which don't support
+    // global constants.
+    gfx_constant_struct!( Locals {
+        proj: [[f32; 4]; 4] = "u_Proj",
+        tint: [f32; 4] = "u_Tint",
+        screen_size: [f32; 2] = "u_Screen_Size",
+        srgb: f32 = "u_Srgb",
     });
 
+    // Instances are given by a raw buffer, since their format is chosen at
+    // runtime.
     gfx_pipeline_base!( pipe {
         vbuf: gfx::VertexBuffer<Vertex>,
+        ibuf: gfx::RawVertexBuffer,
+        locals: gfx::ConstantBuffer<Locals>,
+        screen_size: gfx::Global<[f32; 2]>,
+        proj: gfx::Global<[[f32; 4]; 4]>,
+        tint: gfx::Global<[f32; 4]>,
+        srgb: gfx::Global<f32>,
+        color: gfx::TextureSampler<f32>,
+        out_color: gfx::RawRenderTarget,
+        scissor: gfx::Scissor,
+    });
+
+    gfx_pipeline_base!( pipe_depth_stencil {
+        vbuf: gfx::VertexBuffer<Vertex>,
+        ibuf: gfx::RawVertexBuffer,
+        locals: gfx::ConstantBuffer<Locals>,
         screen_size: gfx::Global<[f32; 2]>,
         proj: gfx::Global<[[f32; 4]; 4]>,
+        tint: gfx::Global<[f32; 4]>,
+        srgb: gfx::Global<f32>,
         color: gfx::TextureSampler<f32>,
         out_color: gfx::RawRenderTarget,
+        scissor: gfx::Scissor,
+        depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil>,
     });
 }
-use shader_structs::{Vertex, pipe};
-
-const VERTEX_SRC: &'static [u8] = b"
-    #version 150 core
-
-    in vec2 a_Pos;
-    in vec4 a_Color;
-    in vec2 a_TexCoord;
-    in vec4 a_World_Pos;
-    in int a_Screen_Rel;
-    out vec4 v_Color;
-    out vec2 v_TexCoord;
-    uniform vec2 u_Screen_Size;
-    uniform mat4 u_Proj;
-
-    void main() {
-        // On-screen offset from text origin.
-        vec2 v_Screen_Offset = vec2(
-            2 * a_Pos.x / u_Screen_Size.x - 1,
-            1 - 2 * a_Pos.y / u_Screen_Size.y
-        );
-        vec4 v_Screen_Pos = u_Proj * a_World_Pos;
-        vec2 v_World_Offset = a_Screen_Rel == 0
-            // Perspective divide to get normalized device coords.
-            ? vec2 (
-                v_Screen_Pos.x / v_Screen_Pos.z + 1,
-                v_Screen_Pos.y / v_Screen_Pos.z - 1
-            ) : vec2(0.0, 0.0);
+use shader_structs::{Vertex, Instance, PackedInstance, Locals, pipe, pipe_depth_stencil};
+
+const QUAD_VERTICES: [Vertex; 4] = [
+    Vertex { corner: [0.0, 0.0] },
+    Vertex { corner: [0.0, 1.0] },
+    Vertex { corner: [1.0, 1.0] },
+    Vertex { corner: [1.0, 0.0] },
+];
+
+// Top-left triangle.
+// 0--3
+// | /
+// |/
+// 1
+//
+// Bottom-right triangle.
+//    3
+//   /|
+//  / |
+// 1--2
+const QUAD_INDICES: [u16; 6] = [0, 1, 3, 3, 1, 2];
+
+// Maximum number of quads drawn without instancing, whose vertices are all
+// addressable by 16-bit indices.
+const MAX_QUADS_16: usize = 65536 / 4;
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use test_factory::{self, TestFactory, R};
+    use super::*;
+
+    fn renderer(declutter: Declutter) -> Renderer<R, TestFactory> {
+        // A single instance buffer, so the glyphs of the last draw call are
+        // the ones it holds.
+        RendererBuilder::new()
+            .with_buffer_count(1)
+            .with_declutter(declutter)
+            .build(&mut TestFactory::new())
+            .unwrap()
+    }
+
+    fn spans(text: &Renderer<R, TestFactory>) -> Vec<Range<usize>> {
+        (0..text.spans.len()).map(|i| text.span_range(i)).collect()
+    }
+
+    fn drawn(text: &Renderer<R, TestFactory>) -> Vec<Instance> {
+        text.instance_buffers[0].uploaded.clone()
+    }
+
+    fn moved(glyphs: &[Instance], offset: [f32; 2]) -> Vec<Instance> {
+        glyphs.iter().map(|&glyph| Instance {
+            pos: [glyph.pos[0] + offset[0], glyph.pos[1] + offset[1]],
+            ..glyph
+        }).collect()
+    }
+
+    #[test]
+    fn hidden_label_keeps_text_spans_in_offset_viewport() {
+        let mut text = renderer(Declutter::Hide);
+        text.add("Top", [10, 10], Color::WHITE);
+        text.add_at_with_priority("Shown", [0.0, 0.0, 1.0], Color::WHITE, 1);
+        text.add_at_with_priority("Hidden", [0.0, 0.0, 1.0], Color::WHITE, 0);
+        text.add("Bottom", [10, 30], Color::WHITE);
+        let queued = text.instance_data.clone();
+        let spans = spans(&text);
+
+        let target = text.factory.render_target(200, 100).unwrap();
+        text.draw_viewport(&mut test_factory::encoder(), &target, [100, 0, 100, 100], DEFAULT_PROJECTION).unwrap();
+
+        // Screen text is moved by the viewport origin, the shown label is
+        // kept in place and the hidden one is dropped.
+        let mut expected = moved(&queued[spans[0].clone()], [100.0, 0.0]);
+        expected.extend_from_slice(&queued[spans[1].clone()]);
+        expected.extend(moved(&queued[spans[3].clone()], [100.0, 0.0]));
+        assert_eq!(drawn(&text), expected);
+    }
+
+    #[test]
+    fn label_follows_its_glyphs_across_layers() {
+        let mut text = renderer(Declutter::Hide);
+        text.set_layer(1);
+        text.add_at_with_priority("Label", [0.0, 0.0, 1.0], Color::WHITE, 0);
+        // Empty text queues an empty span after the label's one.
+        text.add("", [10, 10], Color::WHITE);
+        text.add_on_layer("Under", [10, 30], 0, Color::WHITE);
+        assert_eq!(text.layers, [1, 1, 0]);
+        let queued = text.instance_data.clone();
+        let spans = spans(&text);
+
+        // Text of the lower layer goes first, the label keeps its glyphs.
+        text.sort_layers();
+        assert_eq!(text.labels[0].span, 1);
+        assert_eq!(text.span_range(1), spans[2].len()..queued.len());
+
+        let target = text.factory.render_target(200, 100).unwrap();
+        text.draw(&mut test_factory::encoder(), &target).unwrap();
+        let mut expected = queued[spans[2].clone()].to_vec();
+        expected.extend_from_slice(&queued[spans[0].clone()]);
+        assert_eq!(drawn(&text), expected);
+    }
+
+    #[test]
+    fn glyphs_are_repeated_per_corner_without_instancing() {
+        use std::mem::size_of;
+
+        let mut text = RendererBuilder::new()
+            .with_buffer_count(1)
+            .with_max_buffer_size(100000)
+            .build(&mut TestFactory::without_instancing())
+            .unwrap();
+        assert!(!text.instanced);
+        // Vertices of all quads of a draw call are addressable by 16-bit
+        // indices.
+        assert_eq!(text.max_buffer_size, MAX_QUADS_16);
+
+        text.add("Corners", [10, 10], Color::WHITE);
+        let queued = text.instance_data.clone();
+        let target = text.factory.render_target(200, 100).unwrap();
+        text.draw(&mut test_factory::encoder(), &target).unwrap();
+
+        assert_eq!(drawn(&text), queued);
+        assert_eq!(text.bytes_uploaded, queued.len() * QUAD_VERTICES.len() * size_of::<Instance>());
+    }
+
+    #[test]
+    fn culled_glyphs_keep_spans_in_step() {
+        let mut text = renderer(Declutter::Off);
+        text.add("Off", [500, 10], Color::WHITE);
+        text.add("On", [10, 10], Color::WHITE);
+        let queued = text.instance_data.clone();
+        let on = spans(&text)[1].clone();
+
+        text.cull_glyphs(DEFAULT_PROJECTION, [200.0, 100.0], [0, 0, 200, 100]);
+
+        // The span of the culled text is left empty.
+        assert_eq!(spans(&text), [0..0, 0..on.len()]);
+        assert_eq!(text.instance_data, &queued[on]);
+    }
+
+    #[test]
+    fn outlines_are_queued_under_glyphs() {
+        let mut text = RendererBuilder::new()
+            .with_outline(2, Color::BLACK)
+            .build(&mut TestFactory::new())
+            .unwrap();
+        text.add("Hi", [10, 10], Color::WHITE);
+        let (outlines, glyphs) = text.instance_data.split_at(text.instance_data.len() / 2);
+        assert_eq!(glyphs.len(), 2);
+        for (outline, glyph) in outlines.iter().zip(glyphs) {
+            assert_eq!(outline.color, Color::BLACK.0);
+            assert_eq!(outline.pos, [glyph.pos[0] - 2.0, glyph.pos[1] - 2.0]);
+            assert_eq!(outline.size, [glyph.size[0] + 4.0, glyph.size[1] + 4.0]);
+        }
+
+        // Widths the font wasn't built with are not drawn.
+        text.define_style("thick", TextStyle::new(Color::WHITE).with_outline(5, Color::BLACK));
+        let queued = text.instance_data.len();
+        text.add_with_style("Hi", [10, 30], "thick").unwrap();
+        assert_eq!(text.instance_data.len(), queued + 2);
+        assert_eq!(text.outline(), Some((2, Color::BLACK.0)));
+    }
+
+    #[test]
+    fn builder_builds_many_renderers() {
+        let mut factory = TestFactory::new();
+        let mut builder = RendererBuilder::new().with_size(20);
+        let first: Renderer<R, TestFactory> = builder.build(&mut factory).unwrap();
+        let second: Renderer<R, TestFactory> = builder.build(&mut factory).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.measure("Same"), second.measure("Same"));
+    }
+
+    #[test]
+    fn styles_get_their_own_faces() {
+        let font = include_bytes!("../assets/NotoSans-Regular.ttf");
+        let atlas = FontBuilder::new()
+            .with_chars(&['a'])
+            .with_style_font_data_index(Style::BoldItalic, font, 0)
+            .with_synthetic_style(Style::Italic)
+            .build()
+            .unwrap();
+        assert_eq!(atlas.style_faces, [0, 0, 2, 1]);
+        let mut layout = TextLayout::new(atlas);
+        for &(style, face) in &[(Style::Regular, 0), (Style::Bold, 0), (Style::Italic, 2), (Style::BoldItalic, 1)] {
+            layout.set_style(style);
+            assert!(layout.atlas().bitmap().find_char(face, 'a').is_some());
+            assert_eq!(layout.line_quads("a", [0, 0]).len(), 1);
+        }
+    }
+
+    #[test]
+    fn font_index_stays_with_its_source() {
+        let font = include_bytes!("../assets/NotoSans-Regular.ttf");
+        // The data replaces the collection along with its face index.
+        let builder = FontBuilder::new().with_chars(&['a']).with_font_index("fonts.ttc", 3).with_font_data(font);
+        assert!(builder.build().is_ok());
+        assert!(builder.with_font_data_index(font, 3).build().is_err());
+    }
+
+    #[test]
+    fn shared_font_data_is_not_copied() {
+        let font: Arc<[u8]> = Arc::from(&include_bytes!("../assets/NotoSans-Regular.ttf")[..]);
+        let mut text = RendererBuilder::new()
+            .with_buffer_count(1)
+            .with_chars(&['a'])
+            .with_shared_font_data(font.clone(), 0)
+            .build(&mut TestFactory::new())
+            .unwrap();
+        // Kept by the renderer to build the font again.
+        assert_eq!(Arc::strong_count(&font), 2);
+        text.set_font_size(20).unwrap();
+        assert_eq!(Arc::strong_count(&font), 2);
+        drop(text);
+        assert_eq!(Arc::strong_count(&font), 1);
+    }
+
+    #[test]
+    fn built_font_is_used_by_every_build() {
+        let font = FontBuilder::new().with_size(40).build().unwrap();
+        let line_height = font.line_height();
+        let mut builder = RendererBuilder::new().with_size(10).with_built_font(font);
+        for _ in 0..2 {
+            let text = builder.build(&mut TestFactory::new()).unwrap();
+            assert_eq!(text.layout().atlas().line_height(), line_height);
+        }
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(half_float(0.0), 0);
+        assert_eq!(half_float(1.0), 0x3C00);
+        assert_eq!(half_float(-2.0), 0xC000);
+        assert_eq!(half_float(65504.0), 0x7BFF);
+        // Rounds past the largest half float.
+        assert_eq!(half_float(65520.0), 0x7C00);
+        assert_eq!(half_float(1.0e9), 0x7C00);
+        assert_eq!(half_float(-1.0e9), 0xFC00);
+        // The smallest subnormal, and a value too small even for it.
+        assert_eq!(half_float(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(half_float(2.0f32.powi(-26)), 0);
+        // A quarter and three quarters of the step above one.
+        assert_eq!(half_float(1.0 + 2.0f32.powi(-12)), 0x3C00);
+        assert_eq!(half_float(1.0 + 3.0 * 2.0f32.powi(-12)), 0x3C01);
+    }
+
+    #[test]
+    fn packed_instances_keep_their_values() {
+        let packed = pack_instance(&Instance {
+            pos: [1.0, -2.0],
+            size: [0.5, 10.0],
+            tex: [0.0, 1.0, 0.5, 2.0],
+            world_pos: [1.0, 2.0, 3.0],
+            color: [1.0, 0.0, 0.5, -1.0],
+            rotation: [1.0, -1.0, 1.0],
+        });
+        assert_eq!([packed.pos[0].0, packed.pos[1].0], [0x3C00, 0xC000]);
+        assert_eq!([packed.size[0].0, packed.size[1].0], [0x3800, 0x4900]);
+        // Values are clamped to the range of the normalized types.
+        let tex: Vec<u16> = packed.tex.iter().map(|value| value.0).collect();
+        assert_eq!(tex, [0, 65535, 32768, 65535]);
+        let color: Vec<u8> = packed.color.iter().map(|value| value.0).collect();
+        assert_eq!(color, [255, 0, 128, 0]);
+        let rotation: Vec<i8> = packed.rotation.iter().map(|value| value.0).collect();
+        assert_eq!(rotation, [127, -127, 127, 0]);
+        assert_eq!(packed.world_pos, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn overlapping_labels_yield_to_higher_priority() {
+        for &declutter in &[Declutter::Off, Declutter::Hide, Declutter::Offset] {
+            let mut text = renderer(declutter);
+            // The label added first has the lower priority.
+            text.add_at_with_priority("Low", [0.0, 0.0, 1.0], Color::WHITE, 0);
+            text.add_at_with_priority("Hit", [0.0, 0.0, 1.0], Color::WHITE, 5);
+            let queued = text.instance_data.clone();
+            let spans = spans(&text);
+            // Labels are only tracked with decluttering on.
+            let height = text.labels.first().map_or(0.0, |label| label.bounds[3] - label.bounds[1]);
+
+            let target = text.factory.render_target(200, 100).unwrap();
+            text.draw(&mut test_factory::encoder(), &target).unwrap();
+
+            let low = &queued[spans[0].clone()];
+            let mut expected = match declutter {
+                Declutter::Off => low.to_vec(),
+                Declutter::Hide => Vec::new(),
+                Declutter::Offset => moved(low, [0.0, height]),
+            };
+            expected.extend_from_slice(&queued[spans[1].clone()]);
+            assert_eq!(drawn(&text), expected, "{:?}", declutter);
+        }
+    }
+
+    #[test]
+    fn hit_test_finds_text_under_point() {
+        let mut text = renderer(Declutter::Off);
+        text.add("Quit", [10, 10], Color::WHITE);
+        let quit = text.last_text_id();
+        text.add("Play", [10, 50], Color::WHITE);
+        let play = text.last_text_id();
+        assert!(quit != play);
 
-        v_Color = a_Color;
-        v_TexCoord = a_TexCoord;
-        gl_Position = vec4(v_World_Offset + v_Screen_Offset, 0.0, 1.0);
+        // Center of the glyph quad of the 'Q'.
+        let q = text.instance_data[text.span_range(0)][0];
+        let center = [(q.pos[0] + q.size[0] / 2.0) as i32, (q.pos[1] + q.size[1] / 2.0) as i32];
+        assert_eq!(text.hit_test(center), quit);
+        let p = text.instance_data[text.span_range(1)][0];
+        assert_eq!(text.hit_test([p.pos[0] as i32, p.pos[1] as i32]), play);
     }
-";
 
-const FRAGMENT_SRC: &'static [u8] = b"
-    #version 150 core
+    #[test]
+    fn hit_test_misses_outside_glyphs() {
+        let mut text = renderer(Declutter::Off);
+        text.add("Quit", [10, 10], Color::WHITE);
+        assert_eq!(text.hit_test([5, 5]), None);
+        assert_eq!(text.hit_test([150, 90]), None);
+        // Just past the last glyph of the line, the right edge is outside.
+        let t = *text.instance_data.last().unwrap();
+        assert_eq!(text.hit_test([(t.pos[0] + t.size[0]) as i32, t.pos[1] as i32]), None);
+    }
+
+    #[test]
+    fn only_changed_spans_are_uploaded() {
+        use std::mem::size_of;
 
-    in vec4 v_Color;
-    in vec2 v_TexCoord;
-    out vec4 o_Color;
-    uniform sampler2D t_Color;
+        let mut text = renderer(Declutter::Off);
+        let target = text.factory.render_target(200, 100).unwrap();
+        let frame = |text: &mut Renderer<R, TestFactory>, colors: [Color; 3]| -> (Vec<Range<usize>>, usize) {
+            text.add("First", [10, 10], colors[0]);
+            text.add("Second", [10, 30], colors[1]);
+            text.add("Third", [10, 50], colors[2]);
+            let spans = spans(text);
+            text.draw(&mut test_factory::encoder(), &target).unwrap();
+            assert_eq!(drawn(text).len(), spans[2].end);
+            (spans, text.stats().bytes_uploaded)
+        };
+        let bytes = |range: &Range<usize>| range.len() * size_of::<Instance>();
 
-    void main() {
-        vec4 t_Font_Color = texture(t_Color, v_TexCoord);
-        o_Color = vec4(v_Color.rgb, t_Font_Color.r * v_Color.a);
+        let (spans, uploaded) = frame(&mut text, [Color::WHITE; 3]);
+        assert_eq!(uploaded, bytes(&(0..spans[2].end)));
+        let (_, uploaded) = frame(&mut text, [Color::WHITE; 3]);
+        assert_eq!(uploaded, 0);
+        let (_, uploaded) = frame(&mut text, [Color::WHITE, Color::RED, Color::WHITE]);
+        assert_eq!(uploaded, bytes(&spans[1]));
+        // Both changed spans are uploaded, the unchanged one between them
+        // isn't.
+        let (_, uploaded) = frame(&mut text, [Color::BLACK, Color::RED, Color::BLACK]);
+        assert_eq!(uploaded, bytes(&spans[0]) + bytes(&spans[2]));
     }
-";
+}
@@ -0,0 +1,179 @@
+//! Single line of text scrolling through a window narrower than the text.
+
+use gfx::{Factory, Resources};
+use super::{CachedText, Color, Renderer};
+
+const DEFAULT_SPEED: f32 = 40.0;
+const DEFAULT_GAP: i32 = 40;
+
+/// Text scrolling to the left through a window of the given width if it
+/// doesn't fit into it, repeating after a gap, e.g. a track title of a
+/// music player. Text which fits is drawn still. The text is laid out once
+/// and cached by the renderer it's first added to.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut title = gfx_text::Marquee::new("Artist - A very long track title", 120);
+///
+/// // In render loop:
+/// title.tick(dt);
+/// title.add_to(&mut text, [10, 10], Color::WHITE);
+/// text.draw(&mut encoder, &color_output).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Marquee {
+    text: String,
+    width: i32,
+    // Scroll speed in pixels per second.
+    speed: f32,
+    // Distance between the end of the text and its repeated start.
+    gap: i32,
+    // Distance the text is scrolled by, in pixels.
+    offset: f32,
+    // Layout cached by the renderer, released by the marquee which cached
+    // it.
+    cached: Option<CachedText>,
+}
+
+// NOTE: Clones don't share the cached layout, which is released when the
+// original is done with it, they cache their own.
+impl Clone for Marquee {
+    fn clone(&self) -> Marquee {
+        Marquee {
+            text: self.text.clone(),
+            width: self.width,
+            speed: self.speed,
+            gap: self.gap,
+            offset: self.offset,
+            cached: None,
+        }
+    }
+}
+
+impl Marquee {
+    /// Create a marquee of the text in the window of the given width,
+    /// scrolling by 40 pixels per second with 40 pixels between repeats.
+    pub fn new(text: &str, width: i32) -> Marquee {
+        Marquee {
+            text: text.to_owned(),
+            width: width,
+            speed: DEFAULT_SPEED,
+            gap: DEFAULT_GAP,
+            offset: 0.0,
+            cached: None,
+        }
+    }
+
+    /// Set scroll speed in pixels per second.
+    pub fn with_speed(mut self, speed: f32) -> Marquee {
+        self.speed = speed;
+        self
+    }
+
+    /// Set distance in pixels between the end of the text and its repeated
+    /// start.
+    pub fn with_gap(mut self, gap: i32) -> Marquee {
+        self.gap = gap;
+        self
+    }
+
+    /// Return the scrolled text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the text and scroll back to its start. The renderer must be
+    /// the one the marquee was added to, if any, to drop the old layout.
+    pub fn set_text<R: Resources, F: Factory<R>>(&mut self, renderer: &mut Renderer<R, F>, text: &str) {
+        self.release(renderer);
+        self.text = text.to_owned();
+        self.offset = 0.0;
+    }
+
+    /// Change width of the window.
+    pub fn set_width(&mut self, width: i32) {
+        self.width = width;
+    }
+
+    /// Return distance in pixels the text is scrolled by.
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// Scroll back to the start of the text.
+    pub fn reset(&mut self) {
+        self.offset = 0.0;
+    }
+
+    /// Advance scrolling by the time since the last frame in seconds.
+    pub fn tick(&mut self, dt: f32) {
+        self.offset += self.speed * dt;
+    }
+
+    /// Add the visible part of the text to the renderer, the position is the
+    /// top left corner of the window as in `Renderer::add`.
+    pub fn add_to<R: Resources, F: Factory<R>, C: Into<Color>>(
+        &mut self,
+        renderer: &mut Renderer<R, F>,
+        pos: [i32; 2],
+        color: C,
+    ) {
+        let color = color.into();
+        let cached = match self.cached {
+            Some(cached) => cached,
+            None => {
+                let cached = renderer.cache_text(&self.text);
+                self.cached = Some(cached);
+                cached
+            },
+        };
+        // NOTE: Measured every time, as the font may be changed since the
+        // text was cached. Measured lines are cached by the layout anyway.
+        let text_width = renderer.measure(&self.text).0;
+        if text_width <= self.width {
+            renderer.add_window(cached, pos, 0, self.width, color);
+            return;
+        }
+        // Keep the offset within a single repeat, so it doesn't lose
+        // precision over time.
+        let period = (text_width + self.gap.max(0)) as f32;
+        self.offset %= period;
+        if self.offset < 0.0 {
+            self.offset += period;
+        }
+        let scroll = self.offset as i32;
+        renderer.add_window(cached, pos, scroll, self.width, color);
+        if scroll + self.width > text_width {
+            renderer.add_window(cached, pos, scroll - period as i32, self.width, color);
+        }
+    }
+
+    /// Drop the cached layout from the renderer the marquee was added to.
+    /// It's laid out again if the marquee is added after that.
+    pub fn release<R: Resources, F: Factory<R>>(&mut self, renderer: &mut Renderer<R, F>) {
+        if let Some(cached) = self.cached.take() {
+            renderer.uncache(cached);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_factory::TestFactory;
+    use RendererBuilder;
+    use super::*;
+
+    #[test]
+    fn clones_cache_their_own_layout() {
+        let mut renderer = RendererBuilder::new().with_buffer_count(1).build(&mut TestFactory::new()).unwrap();
+        let mut marquee = Marquee::new("Scrolling title", 20);
+        marquee.add_to(&mut renderer, [0, 0], Color::WHITE);
+        let mut copy = marquee.clone();
+        assert!(copy.cached.is_none());
+        marquee.release(&mut renderer);
+        copy.add_to(&mut renderer, [0, 0], Color::WHITE);
+        assert!(copy.cached.is_some());
+        copy.release(&mut renderer);
+    }
+}
@@ -0,0 +1,499 @@
+//! Multi-channel signed distance fields of glyph outlines, see
+//! `RendererBuilder::with_msdf`.
+//!
+//! Every channel holds the distance to the nearest outline edge of its
+//! color. Edges meeting at a sharp corner get different colors, so the
+//! median of the channels keeps the corner sharp when the field is
+//! magnified, while a single distance would round it.
+
+use std::f64;
+use freetype::Outline;
+
+// Edge colors are sets of the channels the edge contributes to.
+const RED: u8 = 1;
+const GREEN: u8 = 2;
+const BLUE: u8 = 4;
+const WHITE: u8 = RED | GREEN | BLUE;
+const CYAN: u8 = GREEN | BLUE;
+const MAGENTA: u8 = RED | BLUE;
+const YELLOW: u8 = RED | GREEN;
+
+// Edges meet at a corner if the angle between their directions is bigger
+// than about 8 degrees, this is the sine of it.
+const CORNER_CROSS_THRESHOLD: f64 = 0.141;
+// Curves are flattened into pieces about that long, in pixels.
+const FLATTEN_STEP: f64 = 1.5;
+const MAX_CURVE_PIECES: usize = 16;
+const EPSILON: f64 = 1e-9;
+
+// FreeType point tags.
+const ON_CURVE: u8 = 1;
+const CUBIC: u8 = 2;
+const CONIC: u8 = 0;
+
+type Point = [f64; 2];
+
+/// Distance field of a glyph, placed relative to the pen position like the
+/// bitmaps rendered by FreeType.
+pub struct DistanceField {
+    /// Distance from the pen position to the left column.
+    pub left: i32,
+    /// Distance from the baseline up to the top row.
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+    /// RGBA pixels row by row from the top, alpha is always opaque.
+    pub data: Vec<u8>,
+}
+
+// Outline edge between two corners or curve joints, curves are flattened
+// into a polyline.
+struct Edge {
+    points: Vec<Point>,
+    // Directions at the ends, taken from the curve rather than the polyline.
+    start_direction: Point,
+    end_direction: Point,
+    color: u8,
+    // Bounding box: min x, min y, max x, max y.
+    bounds: [f64; 4],
+}
+
+// Distance from a point to an edge.
+#[derive(Clone, Copy)]
+struct EdgeDistance {
+    distance: f64,
+    // How much the nearest point is off the edge normal, which tells apart
+    // edges sharing the nearest corner.
+    dot: f64,
+    // Signed distance to the edge extended along its end tangents, positive
+    // on the left side.
+    pseudo: f64,
+}
+
+impl EdgeDistance {
+    fn far() -> EdgeDistance {
+        EdgeDistance { distance: f64::INFINITY, dot: 0.0, pseudo: f64::INFINITY }
+    }
+
+    fn closer_than(&self, other: &EdgeDistance) -> bool {
+        self.distance < other.distance - EPSILON ||
+            (self.distance <= other.distance + EPSILON && self.dot < other.dot)
+    }
+}
+
+/// Generate the field of the outline given in pixels, distances up to the
+/// range are stored and the field is padded by the range on every side.
+pub fn generate(outline: &Outline, range: u8) -> DistanceField {
+    let mut contours = contours(outline);
+    for edges in &mut contours {
+        color_edges(edges);
+    }
+    let all_edges: Vec<&Edge> = contours.iter().flat_map(|edges| edges.iter()).collect();
+    if all_edges.is_empty() {
+        return DistanceField { left: 0, top: 0, width: 0, height: 0, data: Vec::new() };
+    }
+
+    let range = range as f64;
+    let bounds = all_edges.iter().fold(
+        [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+        |b, edge| [b[0].min(edge.bounds[0]), b[1].min(edge.bounds[1]),
+                   b[2].max(edge.bounds[2]), b[3].max(edge.bounds[3])]);
+    let left = (bounds[0] - range).floor() as i32;
+    let bottom = (bounds[1] - range).floor() as i32;
+    let right = (bounds[2] + range).ceil() as i32;
+    let top = (bounds[3] + range).ceil() as i32;
+    let (width, height) = (right - left, top - bottom);
+
+    // Outer contours of TrueType fonts go clockwise and of PostScript ones
+    // counter-clockwise, the total area tells which side is filled.
+    let area: f64 = all_edges.iter()
+        .flat_map(|edge| edge.points.windows(2))
+        .map(|piece| cross(piece[0], piece[1]))
+        .sum();
+    let inside_sign = if area > 0.0 { 1.0 } else { -1.0 };
+
+    let encode = |distance: f64| {
+        let value = (0.5 + distance / (2.0 * range)).clamp(0.0, 1.0);
+        (value * 255.0).round() as u8
+    };
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for row in 0..height {
+        for col in 0..width {
+            let p = [left as f64 + col as f64 + 0.5, top as f64 - row as f64 - 0.5];
+            let mut nearest = [EdgeDistance::far(); 3];
+            for edge in &all_edges {
+                // Skip the edges which are surely farther than the nearest
+                // ones of all their channels.
+                let bound = box_distance(&edge.bounds, p);
+                let worst = (0..3)
+                    .filter(|&channel| edge.color & 1 << channel != 0)
+                    .map(|channel| nearest[channel].distance)
+                    .fold(0.0, f64::max);
+                if bound > worst + EPSILON {
+                    continue;
+                }
+                let distance = edge_distance(edge, p);
+                for (channel, nearest) in nearest.iter_mut().enumerate() {
+                    if edge.color & 1 << channel != 0 && distance.closer_than(nearest) {
+                        *nearest = distance;
+                    }
+                }
+            }
+            let mut channels = [0.0; 3];
+            for channel in 0..3 {
+                channels[channel] = nearest[channel].pseudo * inside_sign;
+            }
+            // Overlapping contours and clashing edge colors may give the
+            // median a wrong sign, fall back to the true distance there.
+            let inside = winding(&all_edges, p) != 0;
+            if (median(channels) > 0.0) != inside {
+                let distance = all_edges.iter()
+                    .map(|edge| edge_distance(edge, p).distance)
+                    .fold(f64::INFINITY, f64::min);
+                let distance = if inside { distance } else { -distance };
+                channels = [distance; 3];
+            }
+            data.extend(channels.iter().map(|&distance| encode(distance)));
+            data.push(255);
+        }
+    }
+    DistanceField { left: left, top: top, width: width, height: height, data: data }
+}
+
+// Split the outline into contours of edges, in pixels.
+fn contours(outline: &Outline) -> Vec<Vec<Edge>> {
+    let points = outline.points();
+    let tags = outline.tags();
+    let mut result = Vec::new();
+    let mut start = 0;
+    for &end in outline.contours() {
+        let end = end as usize;
+        let mut nodes: Vec<(Point, u8)> = (start..end + 1).map(|i| {
+            let point = [points[i].x as f64 / 64.0, points[i].y as f64 / 64.0];
+            let tag = tags[i] as u8;
+            let kind = if tag & ON_CURVE != 0 { ON_CURVE } else if tag & CUBIC != 0 { CUBIC } else { CONIC };
+            (point, kind)
+        }).collect();
+        start = end + 1;
+
+        // There is an implied on-curve point between two conic control
+        // points.
+        let count = nodes.len();
+        let mut expanded = Vec::with_capacity(count * 2);
+        for i in 0..count {
+            let (point, kind) = nodes[i];
+            let (next, next_kind) = nodes[(i + 1) % count];
+            expanded.push((point, kind));
+            if kind == CONIC && next_kind == CONIC {
+                expanded.push(([(point[0] + next[0]) / 2.0, (point[1] + next[1]) / 2.0], ON_CURVE));
+            }
+        }
+        nodes = expanded;
+        let first = match nodes.iter().position(|&(_, kind)| kind == ON_CURVE) {
+            Some(first) => first,
+            None => continue,
+        };
+        nodes.rotate_left(first);
+        let closing = nodes[0];
+        nodes.push(closing);
+
+        let mut edges = Vec::new();
+        let mut i = 0;
+        while i + 1 < nodes.len() {
+            let from = nodes[i].0;
+            let control = match nodes[i + 1].1 {
+                ON_CURVE => vec![from, nodes[i + 1].0],
+                CONIC if i + 2 < nodes.len() => vec![from, nodes[i + 1].0, nodes[i + 2].0],
+                CUBIC if i + 3 < nodes.len() => vec![from, nodes[i + 1].0, nodes[i + 2].0, nodes[i + 3].0],
+                _ => break,
+            };
+            i += control.len() - 1;
+            let polyline = if control.len() == 2 { control.clone() } else { flatten(&control) };
+            let start_direction = control.iter()
+                .map(|&point| sub(point, control[0]))
+                .find(|&direction| length(direction) > EPSILON);
+            let end_direction = control.iter().rev()
+                .map(|&point| sub(control[control.len() - 1], point))
+                .find(|&direction| length(direction) > EPSILON);
+            if let (Some(start_direction), Some(end_direction)) = (start_direction, end_direction) {
+                if let Some(edge) = Edge::new(polyline, normalize(start_direction), normalize(end_direction)) {
+                    edges.push(edge);
+                }
+            }
+        }
+        if !edges.is_empty() {
+            result.push(edges);
+        }
+    }
+    result
+}
+
+// Flatten the Bezier curve given by its control points.
+fn flatten(control: &[Point]) -> Vec<Point> {
+    let length: f64 = control.windows(2).map(|pair| distance(pair[0], pair[1])).sum();
+    let pieces = ((length / FLATTEN_STEP).ceil() as usize).clamp(1, MAX_CURVE_PIECES);
+    (0..pieces + 1).map(|i| {
+        // De Casteljau's algorithm.
+        let t = i as f64 / pieces as f64;
+        let mut points = control.to_vec();
+        while points.len() > 1 {
+            points = points.windows(2)
+                .map(|pair| [pair[0][0] + (pair[1][0] - pair[0][0]) * t,
+                             pair[0][1] + (pair[1][1] - pair[0][1]) * t])
+                .collect();
+        }
+        points[0]
+    }).collect()
+}
+
+impl Edge {
+    fn new(mut points: Vec<Point>, start_direction: Point, end_direction: Point) -> Option<Edge> {
+        points.dedup_by(|a, b| distance(*a, *b) < EPSILON);
+        if points.len() < 2 {
+            return None;
+        }
+        let bounds = points.iter().fold(
+            [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+            |b, p| [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])]);
+        Some(Edge {
+            points: points,
+            start_direction: start_direction,
+            end_direction: end_direction,
+            color: WHITE,
+            bounds: bounds,
+        })
+    }
+
+    // Split the edge in two halves, `None` if it's too short for that.
+    fn split(&self) -> Option<(Edge, Edge)> {
+        let mut points = self.points.clone();
+        if points.len() == 2 {
+            let (a, b) = (points[0], points[1]);
+            points.insert(1, [(a[0] + b[0]) / 2.0, (a[1] + b[1]) / 2.0]);
+        }
+        let middle = points.len() / 2;
+        let second = points[middle..].to_vec();
+        points.truncate(middle + 1);
+        let direction = sub(second[1], second[0]);
+        if length(direction) <= EPSILON {
+            return None;
+        }
+        let direction = normalize(direction);
+        match (Edge::new(points, self.start_direction, direction),
+               Edge::new(second, direction, self.end_direction)) {
+            (Some(first), Some(second)) => Some((first, second)),
+            _ => None,
+        }
+    }
+}
+
+// Color the edges of the contour so that the edges meeting at a corner
+// share only one channel, like msdfgen's simple edge coloring does.
+fn color_edges(edges: &mut Vec<Edge>) {
+    let count = edges.len();
+    let corners: Vec<usize> = (0..count)
+        .filter(|&i| is_corner(edges[(i + count - 1) % count].end_direction, edges[i].start_direction))
+        .collect();
+    match corners.len() {
+        0 => {
+            // Smooth contour, a single distance is enough.
+            for edge in edges.iter_mut() {
+                edge.color = WHITE;
+            }
+        },
+        1 => {
+            // Teardrop shape, color the edges in three parts from the corner,
+            // splitting them if there are less than three.
+            edges.rotate_left(corners[0]);
+            while edges.len() < 3 {
+                let longest = (0..edges.len()).max_by_key(|&i| edges[i].points.len()).unwrap();
+                match edges[longest].split() {
+                    Some((first, second)) => {
+                        edges[longest] = second;
+                        edges.insert(longest, first);
+                    },
+                    None => {
+                        // Too small to split, the corner is rounded by a
+                        // single distance.
+                        for edge in edges.iter_mut() {
+                            edge.color = WHITE;
+                        }
+                        return;
+                    },
+                }
+            }
+            let count = edges.len();
+            for (i, edge) in edges.iter_mut().enumerate() {
+                edge.color = [MAGENTA, WHITE, YELLOW][3 * i / count];
+            }
+        },
+        corner_count => {
+            // Switch the color at every corner, the last part must differ
+            // from the first one, which it meets at the first corner.
+            let colors = [CYAN, MAGENTA, YELLOW];
+            let mut is_corner = vec![false; count];
+            for &corner in &corners {
+                is_corner[corner] = true;
+            }
+            edges.rotate_left(corners[0]);
+            is_corner.rotate_left(corners[0]);
+            let mut part = 0;
+            for (i, edge) in edges.iter_mut().enumerate() {
+                if i > 0 && is_corner[i] {
+                    part += 1;
+                }
+                edge.color = if part == corner_count - 1 && part % 3 == 0 {
+                    colors[1]
+                } else {
+                    colors[part % 3]
+                };
+            }
+        },
+    }
+}
+
+fn is_corner(a: Point, b: Point) -> bool {
+    dot(a, b) <= 0.0 || cross(a, b).abs() > CORNER_CROSS_THRESHOLD
+}
+
+fn edge_distance(edge: &Edge, p: Point) -> EdgeDistance {
+    let mut result = EdgeDistance::far();
+    let last = edge.points.len() - 2;
+    for (i, piece) in edge.points.windows(2).enumerate() {
+        let (a, b) = (piece[0], piece[1]);
+        let ab = sub(b, a);
+        let ap = sub(p, a);
+        let t = dot(ap, ab) / dot(ab, ab);
+        let t = t.clamp(0.0, 1.0);
+        let nearest = [a[0] + ab[0] * t, a[1] + ab[1] * t];
+        let to_point = sub(p, nearest);
+        let distance = length(to_point);
+        if distance >= result.distance {
+            continue;
+        }
+        // Perpendicular distance to the line through the piece.
+        let side = cross(ab, ap) / length(ab);
+        result = if (t < 0.0 && i == 0) || (t > 1.0 && i == last) {
+            // Beyond the edge end, where the pseudo-distance continues along
+            // the end tangent.
+            EdgeDistance {
+                distance: distance,
+                dot: dot(normalize(ab), normalize(to_point)).abs(),
+                pseudo: side,
+            }
+        } else {
+            EdgeDistance {
+                distance: distance,
+                dot: 0.0,
+                pseudo: if side < 0.0 { -distance } else { distance },
+            }
+        };
+    }
+    result
+}
+
+// Nonzero winding number of the outline around the point.
+fn winding(edges: &[&Edge], p: Point) -> i32 {
+    let mut winding = 0;
+    for piece in edges.iter().flat_map(|edge| edge.points.windows(2)) {
+        let (a, b) = (piece[0], piece[1]);
+        let side = cross(sub(b, a), sub(p, a));
+        if a[1] <= p[1] {
+            if b[1] > p[1] && side > 0.0 {
+                winding += 1;
+            }
+        } else if b[1] <= p[1] && side < 0.0 {
+            winding -= 1;
+        }
+    }
+    winding
+}
+
+fn box_distance(bounds: &[f64; 4], p: Point) -> f64 {
+    let dx = (bounds[0] - p[0]).max(p[0] - bounds[2]).max(0.0);
+    let dy = (bounds[1] - p[1]).max(p[1] - bounds[3]).max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn median(values: [f64; 3]) -> f64 {
+    values[0].min(values[1]).max(values[0].max(values[1]).min(values[2]))
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: Point, b: Point) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: Point, b: Point) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn length(a: Point) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    length(sub(a, b))
+}
+
+fn normalize(a: Point) -> Point {
+    let length = length(a);
+    if length > 0.0 { [a[0] / length, a[1] / length] } else { a }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(from: Point, to: Point) -> Edge {
+        let direction = normalize(sub(to, from));
+        Edge::new(vec![from, to], direction, direction).unwrap()
+    }
+
+    // Whether every pair of adjacent edges shares exactly one channel.
+    fn corners_share_one_channel(edges: &[Edge]) -> bool {
+        (0..edges.len()).all(|i| {
+            let shared = edges[i].color & edges[(i + 1) % edges.len()].color;
+            shared.count_ones() == 1
+        })
+    }
+
+    #[test]
+    fn square_corners_get_different_colors() {
+        let mut edges = vec![
+            line([0.0, 0.0], [10.0, 0.0]),
+            line([10.0, 0.0], [10.0, 10.0]),
+            line([10.0, 10.0], [0.0, 10.0]),
+            line([0.0, 10.0], [0.0, 0.0]),
+        ];
+        color_edges(&mut edges);
+        assert!(edges.iter().all(|edge| edge.color.count_ones() == 2));
+        assert!(corners_share_one_channel(&edges));
+    }
+
+    #[test]
+    fn teardrop_is_split_into_three_colors() {
+        // A single curve starting and ending at the sharp tip.
+        let control = [[0.0, 0.0], [20.0, 10.0], [-20.0, 10.0], [0.0, 0.0]];
+        let polyline = flatten(&control);
+        let start_direction = normalize(sub(control[1], control[0]));
+        let end_direction = normalize(sub(control[3], control[2]));
+        let mut edges = vec![Edge::new(polyline, start_direction, end_direction).unwrap()];
+        color_edges(&mut edges);
+        let colors: Vec<u8> = edges.iter().map(|edge| edge.color).collect();
+        assert_eq!(colors, [MAGENTA, WHITE, YELLOW]);
+    }
+
+    #[test]
+    fn tiny_teardrop_falls_back_to_single_color() {
+        let mut edges = vec![
+            Edge::new(vec![[0.0, 0.0], [1e-9, 0.0]], [1.0, 0.0], [-1.0, 0.0]).unwrap(),
+        ];
+        color_edges(&mut edges);
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].color, WHITE);
+    }
+}
@@ -0,0 +1,157 @@
+//! Shared scene which keeps text from several renderers in submission order.
+
+use gfx::{CommandBuffer, Encoder, Factory, Resources};
+use gfx::handle::RenderTargetView;
+use super::{full_viewport, target_size, viewport_projection, Color, Error, Renderer, DEFAULT_PROJECTION};
+
+/// Text scene shared by several renderers (e.g. with different fonts or
+/// sizes). Text is drawn in the order it was added to the scene regardless
+/// of which renderer it belongs to, so overlapping text from different fonts
+/// is layered correctly. Consecutive text of the same renderer is batched
+/// into a single draw call.
+///
+/// Renderers used with a scene should get all their text through it, text
+/// added to them directly is discarded by `TextScene::draw`. Labels are not
+/// decluttered when drawing a scene.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut scene = gfx_text::TextScene::new();
+/// scene.add(&mut normal_text, "Behind", [10, 10], [1.0, 0.0, 0.0, 1.0]);
+/// scene.add(&mut big_text, "In front", [12, 12], [0.0, 0.0, 1.0, 1.0]);
+/// scene.draw(&mut encoder, &color_output, &mut [&mut normal_text, &mut big_text]).unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct TextScene {
+    // Renderer ID and the range of its queued glyphs.
+    batches: Vec<(usize, usize, usize)>,
+}
+
+impl TextScene {
+    /// Create a new empty scene.
+    pub fn new() -> Self {
+        TextScene { batches: Vec::new() }
+    }
+
+    /// Add text to the scene using the given renderer, see `Renderer::add`.
+    pub fn add<R: Resources, F: Factory<R>, C: Into<Color>>(
+        &mut self,
+        renderer: &mut Renderer<R, F>,
+        text: &str,
+        pos: [i32; 2],
+        color: C,
+    ) {
+        let start = renderer.instance_data.len();
+        renderer.add(text, pos, color);
+        self.push(renderer.id, start, renderer.instance_data.len());
+    }
+
+    /// Add text to the scene using the given renderer and absolute world
+    /// coordinates, see `Renderer::add_at`.
+    pub fn add_at<R: Resources, F: Factory<R>, C: Into<Color>>(
+        &mut self,
+        renderer: &mut Renderer<R, F>,
+        text: &str,
+        pos: [f32; 3],
+        color: C,
+    ) {
+        let start = renderer.instance_data.len();
+        renderer.add_at(text, pos, color);
+        self.push(renderer.id, start, renderer.instance_data.len());
+    }
+
+    /// Discard the queued text of the scene and of all given renderers
+    /// without drawing it.
+    pub fn clear<R: Resources, F: Factory<R>>(&mut self, renderers: &mut [&mut Renderer<R, F>]) {
+        self.batches.clear();
+        for renderer in renderers.iter_mut() {
+            renderer.clear_scene();
+        }
+    }
+
+    /// Return whether no text is queued in the scene.
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    fn push(&mut self, id: usize, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        if let Some(last) = self.batches.last_mut() {
+            if last.0 == id && last.2 == start {
+                last.2 = end;
+                return;
+            }
+        }
+        self.batches.push((id, start, end));
+    }
+
+    /// Draw the scene and clear state of the scene and all given renderers.
+    pub fn draw<R, F, C, T>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        renderers: &mut [&mut Renderer<R, F>],
+    ) -> Result<(), Error>
+        where R: Resources,
+              F: Factory<R>,
+              C: CommandBuffer<R>,
+              T: ::gfx::format::RenderFormat,
+    {
+        self.draw_at(encoder, target, renderers, DEFAULT_PROJECTION)
+    }
+
+    /// Draw the scene using provided projection matrix and clear state of
+    /// the scene and all given renderers.
+    pub fn draw_at<R, F, C, T>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        renderers: &mut [&mut Renderer<R, F>],
+        proj: [[f32; 4]; 4],
+    ) -> Result<(), Error>
+        where R: Resources,
+              F: Factory<R>,
+              C: CommandBuffer<R>,
+              T: ::gfx::format::RenderFormat,
+    {
+        self.draw_viewport(encoder, target, renderers, full_viewport(target.get_dimensions()), proj)
+    }
+
+    /// Draw the scene into the viewport rectangle of the target, see
+    /// `Renderer::draw_viewport`, and clear state of the scene and all given
+    /// renderers.
+    pub fn draw_viewport<R, F, C, T>(
+        &mut self,
+        encoder: &mut Encoder<R, C>,
+        target: &RenderTargetView<R, T>,
+        renderers: &mut [&mut Renderer<R, F>],
+        viewport: [i32; 4],
+        proj: [[f32; 4]; 4],
+    ) -> Result<(), Error>
+        where R: Resources,
+              F: Factory<R>,
+              C: CommandBuffer<R>,
+              T: ::gfx::format::RenderFormat,
+    {
+        let proj = viewport_projection(proj, viewport, target_size(target.get_dimensions()));
+        for renderer in renderers.iter_mut() {
+            renderer.shrink_buffer()?;
+            renderer.offset_screen_text(viewport);
+            renderer.draw_calls = 0;
+        }
+        for &(id, start, end) in &self.batches {
+            // Batches of unknown renderers are skipped.
+            if let Some(renderer) = renderers.iter_mut().find(|r| r.id == id) {
+                renderer.draw_range(encoder, target, proj, viewport, start, end)?;
+            }
+        }
+        self.batches.clear();
+        for renderer in renderers.iter_mut() {
+            renderer.clear_scene();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+//! Shader programs in all supported shading languages.
+//!
+//! Every variant implements the same interface (see `shader_structs`), so the
+//! renderer may pick any of them which compiles on the current device. Glyph
+//! attributes are ordinary vertex attributes in every variant, so the same
+//! programs draw instanced quads and, on devices without instancing, quads
+//! whose vertices repeat the glyph data.
+
+/// Shading language version used for the renderer programs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShaderVersion {
+    /// GLSL 1.50 core, for OpenGL 3.2+
+    Glsl150,
+    /// GLSL 1.20, for OpenGL 2.1
+    Glsl120,
+    /// GLSL ES 1.00, for OpenGL ES 2.0 and WebGL
+    GlslEs100,
+    /// Metal shading language, for gfx_device_metal
+    Msl,
+    /// SPIR-V binaries, for Vulkan
+    SpirV,
+}
+
+impl ShaderVersion {
+    /// All versions in order of preference.
+    pub const ALL: &'static [ShaderVersion] = &[
+        ShaderVersion::Glsl150,
+        ShaderVersion::Glsl120,
+        ShaderVersion::GlslEs100,
+        ShaderVersion::Msl,
+        ShaderVersion::SpirV,
+    ];
+
+    /// Return vertex and fragment shader sources.
+    pub fn sources(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            ShaderVersion::Glsl150 => (VERTEX_SRC_150, FRAGMENT_SRC_150),
+            ShaderVersion::Glsl120 => (VERTEX_SRC_120, FRAGMENT_SRC_120),
+            ShaderVersion::GlslEs100 => (VERTEX_SRC_ES100, FRAGMENT_SRC_ES100),
+            ShaderVersion::Msl => (VERTEX_SRC_MSL, FRAGMENT_SRC_MSL),
+            ShaderVersion::SpirV => (VERTEX_SPIRV, FRAGMENT_SPIRV),
+        }
+    }
+
+    /// Return vertex and fragment shader sources for fonts with distance
+    /// fields, see `RendererBuilder::with_msdf`.
+    pub fn distance_field_sources(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            ShaderVersion::Glsl150 => (VERTEX_SRC_150, MSDF_FRAGMENT_SRC_150),
+            ShaderVersion::Glsl120 => (VERTEX_SRC_120, MSDF_FRAGMENT_SRC_120),
+            ShaderVersion::GlslEs100 => (VERTEX_SRC_ES100, MSDF_FRAGMENT_SRC_ES100),
+            ShaderVersion::Msl => (VERTEX_SRC_MSL, MSDF_FRAGMENT_SRC_MSL),
+            ShaderVersion::SpirV => (VERTEX_SPIRV, MSDF_FRAGMENT_SPIRV),
+        }
+    }
+
+    /// Whether programs take uniforms from the `Locals` constant buffer
+    /// rather than from separate global constants.
+    pub fn uses_constant_buffer(self) -> bool {
+        matches!(self, ShaderVersion::Msl | ShaderVersion::SpirV)
+    }
+
+    /// Whether vertex attributes are bound at the fixed slots of
+    /// `ATTRIBUTES` rather than at slots assigned by the driver.
+    pub fn has_fixed_attribute_slots(self) -> bool {
+        self.uses_constant_buffer()
+    }
+
+    /// Return name of the fragment shader output.
+    pub fn output_name(self) -> &'static str {
+        match self {
+            ShaderVersion::Glsl150 | ShaderVersion::Msl | ShaderVersion::SpirV => "o_Color",
+            // `gl_FragColor` is exposed under that name by gfx_device_gl.
+            ShaderVersion::Glsl120 | ShaderVersion::GlslEs100 => "Target0",
+        }
+    }
+}
+
+/// Names of the vertex attributes, in the order of their slots in programs
+/// with fixed slots (`[[attribute(n)]]` in MSL, `location = n` in SPIR-V).
+pub const ATTRIBUTES: &'static [&'static str] = &[
+    "a_Corner",
+    "a_Pos",
+    "a_Size",
+    "a_TexRect",
+    "a_World_Pos",
+    "a_Color",
+    "a_Rotation",
+];
+
+const VERTEX_SRC_150: &'static [u8] = b"
+    #version 150 core
+
+    in vec2 a_Corner;
+    in vec2 a_Pos;
+    in vec2 a_Size;
+    in vec4 a_Color;
+    in vec3 a_Rotation;
+    in vec4 a_TexRect;
+    in vec4 a_World_Pos;
+    out vec4 v_Color;
+    out vec2 v_TexCoord;
+    uniform vec2 u_Screen_Size;
+    uniform vec4 u_Tint;
+    uniform float u_Srgb;
+    uniform mat4 u_Proj;
+
+    void main() {
+        // Rotate the quad around its center, (cos, sin) of the angle is given
+        // followed by the screen relative flag.
+        vec2 v_Half_Size = 0.5 * a_Size;
+        vec2 v_Offset = (a_Corner - 0.5) * a_Size;
+        vec2 v_Pos = a_Pos + v_Half_Size + vec2(
+            v_Offset.x * a_Rotation.x - v_Offset.y * a_Rotation.y,
+            v_Offset.x * a_Rotation.y + v_Offset.y * a_Rotation.x
+        );
+        // On-screen offset from text origin.
+        vec2 v_Screen_Offset = vec2(
+            2.0 * v_Pos.x / u_Screen_Size.x - 1.0,
+            1.0 - 2.0 * v_Pos.y / u_Screen_Size.y
+        );
+        vec4 v_Screen_Pos = u_Proj * a_World_Pos;
+        vec2 v_World_Offset = a_Rotation.z < 0.5
+            // Perspective divide to get normalized device coords.
+            ? vec2 (
+                v_Screen_Pos.x / v_Screen_Pos.z + 1.0,
+                v_Screen_Pos.y / v_Screen_Pos.z - 1.0
+            ) : vec2(0.0, 0.0);
+
+        v_Color = a_Color * u_Tint;
+        // sRGB targets encode the output, so they are given linear colors.
+        vec3 v_Linear = mix(
+            v_Color.rgb / 12.92,
+            pow((v_Color.rgb + 0.055) / 1.055, vec3(2.4)),
+            step(vec3(0.04045), v_Color.rgb)
+        );
+        v_Color.rgb = mix(v_Color.rgb, v_Linear, u_Srgb);
+        v_TexCoord = a_TexRect.xy + a_Corner * a_TexRect.zw;
+        gl_Position = vec4(v_World_Offset + v_Screen_Offset, 0.0, 1.0);
+    }
+";
+
+const FRAGMENT_SRC_150: &'static [u8] = b"
+    #version 150 core
+
+    in vec4 v_Color;
+    in vec2 v_TexCoord;
+    out vec4 o_Color;
+    uniform sampler2D t_Color;
+
+    void main() {
+        vec4 t_Font_Color = texture(t_Color, v_TexCoord);
+        o_Color = vec4(v_Color.rgb, t_Font_Color.r * v_Color.a);
+    }
+";
+
+// Coverage is taken from the median of the distance field channels, scaled
+// to screen pixels with its derivatives, so the edge stays one pixel wide at
+// any magnification.
+
+const MSDF_FRAGMENT_SRC_150: &'static [u8] = b"
+    #version 150 core
+
+    in vec4 v_Color;
+    in vec2 v_TexCoord;
+    out vec4 o_Color;
+    uniform sampler2D t_Color;
+
+    float median(vec3 v) {
+        return max(min(v.r, v.g), min(max(v.r, v.g), v.b));
+    }
+
+    void main() {
+        float v_Distance = median(texture(t_Color, v_TexCoord).rgb) - 0.5;
+        float v_Alpha = clamp(v_Distance / max(fwidth(v_Distance), 0.0001) + 0.5, 0.0, 1.0);
+        o_Color = vec4(v_Color.rgb, v_Alpha * v_Color.a);
+    }
+";
+
+// NOTE: There are no integer attributes and no `in`/`out` qualifiers in the
+// older GLSL versions, the rest is the same.
+
+const VERTEX_SRC_120: &'static [u8] = b"
+    #version 120
+
+    attribute vec2 a_Corner;
+    attribute vec2 a_Pos;
+    attribute vec2 a_Size;
+    attribute vec4 a_Color;
+    attribute vec3 a_Rotation;
+    attribute vec4 a_TexRect;
+    attribute vec4 a_World_Pos;
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform vec2 u_Screen_Size;
+    uniform vec4 u_Tint;
+    uniform float u_Srgb;
+    uniform mat4 u_Proj;
+
+    void main() {
+        vec2 v_Half_Size = 0.5 * a_Size;
+        vec2 v_Offset = (a_Corner - 0.5) * a_Size;
+        vec2 v_Pos = a_Pos + v_Half_Size + vec2(
+            v_Offset.x * a_Rotation.x - v_Offset.y * a_Rotation.y,
+            v_Offset.x * a_Rotation.y + v_Offset.y * a_Rotation.x
+        );
+        vec2 v_Screen_Offset = vec2(
+            2.0 * v_Pos.x / u_Screen_Size.x - 1.0,
+            1.0 - 2.0 * v_Pos.y / u_Screen_Size.y
+        );
+        vec4 v_Screen_Pos = u_Proj * a_World_Pos;
+        vec2 v_World_Offset = a_Rotation.z < 0.5
+            ? vec2 (
+                v_Screen_Pos.x / v_Screen_Pos.z + 1.0,
+                v_Screen_Pos.y / v_Screen_Pos.z - 1.0
+            ) : vec2(0.0, 0.0);
+
+        v_Color = a_Color * u_Tint;
+        // sRGB targets encode the output, so they are given linear colors.
+        vec3 v_Linear = mix(
+            v_Color.rgb / 12.92,
+            pow((v_Color.rgb + 0.055) / 1.055, vec3(2.4)),
+            step(vec3(0.04045), v_Color.rgb)
+        );
+        v_Color.rgb = mix(v_Color.rgb, v_Linear, u_Srgb);
+        v_TexCoord = a_TexRect.xy + a_Corner * a_TexRect.zw;
+        gl_Position = vec4(v_World_Offset + v_Screen_Offset, 0.0, 1.0);
+    }
+";
+
+const FRAGMENT_SRC_120: &'static [u8] = b"
+    #version 120
+
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform sampler2D t_Color;
+
+    void main() {
+        vec4 t_Font_Color = texture2D(t_Color, v_TexCoord);
+        gl_FragColor = vec4(v_Color.rgb, t_Font_Color.r * v_Color.a);
+    }
+";
+
+const MSDF_FRAGMENT_SRC_120: &'static [u8] = b"
+    #version 120
+
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform sampler2D t_Color;
+
+    float median(vec3 v) {
+        return max(min(v.r, v.g), min(max(v.r, v.g), v.b));
+    }
+
+    void main() {
+        float v_Distance = median(texture2D(t_Color, v_TexCoord).rgb) - 0.5;
+        float v_Alpha = clamp(v_Distance / max(fwidth(v_Distance), 0.0001) + 0.5, 0.0, 1.0);
+        gl_FragColor = vec4(v_Color.rgb, v_Alpha * v_Color.a);
+    }
+";
+
+const VERTEX_SRC_ES100: &'static [u8] = b"
+    #version 100
+
+    attribute vec2 a_Corner;
+    attribute vec2 a_Pos;
+    attribute vec2 a_Size;
+    attribute vec4 a_Color;
+    attribute vec3 a_Rotation;
+    attribute vec4 a_TexRect;
+    attribute vec4 a_World_Pos;
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform vec2 u_Screen_Size;
+    uniform vec4 u_Tint;
+    uniform float u_Srgb;
+    uniform mat4 u_Proj;
+
+    void main() {
+        vec2 v_Half_Size = 0.5 * a_Size;
+        vec2 v_Offset = (a_Corner - 0.5) * a_Size;
+        vec2 v_Pos = a_Pos + v_Half_Size + vec2(
+            v_Offset.x * a_Rotation.x - v_Offset.y * a_Rotation.y,
+            v_Offset.x * a_Rotation.y + v_Offset.y * a_Rotation.x
+        );
+        vec2 v_Screen_Offset = vec2(
+            2.0 * v_Pos.x / u_Screen_Size.x - 1.0,
+            1.0 - 2.0 * v_Pos.y / u_Screen_Size.y
+        );
+        vec4 v_Screen_Pos = u_Proj * a_World_Pos;
+        vec2 v_World_Offset = a_Rotation.z < 0.5
+            ? vec2 (
+                v_Screen_Pos.x / v_Screen_Pos.z + 1.0,
+                v_Screen_Pos.y / v_Screen_Pos.z - 1.0
+            ) : vec2(0.0, 0.0);
+
+        v_Color = a_Color * u_Tint;
+        // sRGB targets encode the output, so they are given linear colors.
+        vec3 v_Linear = mix(
+            v_Color.rgb / 12.92,
+            pow((v_Color.rgb + 0.055) / 1.055, vec3(2.4)),
+            step(vec3(0.04045), v_Color.rgb)
+        );
+        v_Color.rgb = mix(v_Color.rgb, v_Linear, u_Srgb);
+        v_TexCoord = a_TexRect.xy + a_Corner * a_TexRect.zw;
+        gl_Position = vec4(v_World_Offset + v_Screen_Offset, 0.0, 1.0);
+    }
+";
+
+const FRAGMENT_SRC_ES100: &'static [u8] = b"
+    #version 100
+    precision mediump float;
+
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform sampler2D t_Color;
+
+    void main() {
+        vec4 t_Font_Color = texture2D(t_Color, v_TexCoord);
+        gl_FragColor = vec4(v_Color.rgb, t_Font_Color.r * v_Color.a);
+    }
+";
+
+// Derivatives are an extension in GLSL ES 1.00.
+const MSDF_FRAGMENT_SRC_ES100: &'static [u8] = b"
+    #version 100
+    #extension GL_OES_standard_derivatives : enable
+    precision mediump float;
+
+    varying vec4 v_Color;
+    varying vec2 v_TexCoord;
+    uniform sampler2D t_Color;
+
+    float median(vec3 v) {
+        return max(min(v.r, v.g), min(max(v.r, v.g), v.b));
+    }
+
+    void main() {
+        float v_Distance = median(texture2D(t_Color, v_TexCoord).rgb) - 0.5;
+        float v_Alpha = clamp(v_Distance / max(fwidth(v_Distance), 0.0001) + 0.5, 0.0, 1.0);
+        gl_FragColor = vec4(v_Color.rgb, v_Alpha * v_Color.a);
+    }
+";
+
+// Metal has no global constants, so uniforms are placed into the `Locals`
+// buffer. Entry points are named as gfx_device_metal expects them.
+
+const VERTEX_SRC_MSL: &'static [u8] = b"
+    #include <metal_stdlib>
+    using namespace metal;
+
+    struct Locals {
+        float4x4 u_Proj;
+        float4 u_Tint;
+        float2 u_Screen_Size;
+        float u_Srgb;
+    };
+
+    struct VsInput {
+        float2 a_Corner [[attribute(0)]];
+        float2 a_Pos [[attribute(1)]];
+        float2 a_Size [[attribute(2)]];
+        float4 a_TexRect [[attribute(3)]];
+        float3 a_World_Pos [[attribute(4)]];
+        float4 a_Color [[attribute(5)]];
+        float3 a_Rotation [[attribute(6)]];
+    };
+
+    struct VsOutput {
+        float4 pos [[position]];
+        float4 v_Color;
+        float2 v_TexCoord;
+    };
+
+    vertex VsOutput vert(VsInput in [[stage_in]],
+                         constant Locals &Locals [[buffer(2)]]) {
+        float2 v_Half_Size = 0.5 * in.a_Size;
+        float2 v_Offset = (in.a_Corner - 0.5) * in.a_Size;
+        float2 v_Pos = in.a_Pos + v_Half_Size + float2(
+            v_Offset.x * in.a_Rotation.x - v_Offset.y * in.a_Rotation.y,
+            v_Offset.x * in.a_Rotation.y + v_Offset.y * in.a_Rotation.x
+        );
+        float2 v_Screen_Offset = float2(
+            2.0 * v_Pos.x / Locals.u_Screen_Size.x - 1.0,
+            1.0 - 2.0 * v_Pos.y / Locals.u_Screen_Size.y
+        );
+        float4 v_Screen_Pos = Locals.u_Proj * float4(in.a_World_Pos, 1.0);
+        float2 v_World_Offset = in.a_Rotation.z < 0.5
+            ? float2(
+                v_Screen_Pos.x / v_Screen_Pos.z + 1.0,
+                v_Screen_Pos.y / v_Screen_Pos.z - 1.0
+            ) : float2(0.0, 0.0);
+
+        VsOutput out;
+        out.v_Color = in.a_Color * Locals.u_Tint;
+        float3 v_Linear = mix(
+            out.v_Color.rgb / 12.92,
+            pow((out.v_Color.rgb + 0.055) / 1.055, float3(2.4)),
+            step(float3(0.04045), out.v_Color.rgb)
+        );
+        out.v_Color.rgb = mix(out.v_Color.rgb, v_Linear, Locals.u_Srgb);
+        out.v_TexCoord = in.a_TexRect.xy + in.a_Corner * in.a_TexRect.zw;
+        out.pos = float4(v_World_Offset + v_Screen_Offset, 0.0, 1.0);
+        return out;
+    }
+";
+
+const FRAGMENT_SRC_MSL: &'static [u8] = b"
+    #include <metal_stdlib>
+    using namespace metal;
+
+    struct VsOutput {
+        float4 pos [[position]];
+        float4 v_Color;
+        float2 v_TexCoord;
+    };
+
+    fragment float4 frag(VsOutput in [[stage_in]],
+                         texture2d<float> t_Color [[texture(0)]],
+                         sampler t_Color_ [[sampler(0)]]) {
+        float4 t_Font_Color = t_Color.sample(t_Color_, in.v_TexCoord);
+        return float4(in.v_Color.rgb, t_Font_Color.r * in.v_Color.a);
+    }
+";
+
+const MSDF_FRAGMENT_SRC_MSL: &'static [u8] = b"
+    #include <metal_stdlib>
+    using namespace metal;
+
+    struct VsOutput {
+        float4 pos [[position]];
+        float4 v_Color;
+        float2 v_TexCoord;
+    };
+
+    fragment float4 frag(VsOutput in [[stage_in]],
+                         texture2d<float> t_Color [[texture(0)]],
+                         sampler t_Color_ [[sampler(0)]]) {
+        float3 t_Field = t_Color.sample(t_Color_, in.v_TexCoord).rgb;
+        float v_Distance = median3(t_Field.r, t_Field.g, t_Field.b) - 0.5;
+        float v_Alpha = saturate(v_Distance / max(fwidth(v_Distance), 0.0001) + 0.5);
+        return float4(in.v_Color.rgb, v_Alpha * in.v_Color.a);
+    }
+";
+
+// Compiled from `assets/shaders/text.{vert,frag}` and `text_msdf.frag`, run
+// `scripts/compile-shaders.sh` after changing them.
+
+const VERTEX_SPIRV: &'static [u8] = include_bytes!("../assets/shaders/text.vert.spv");
+
+const FRAGMENT_SPIRV: &'static [u8] = include_bytes!("../assets/shaders/text.frag.spv");
+
+const MSDF_FRAGMENT_SPIRV: &'static [u8] = include_bytes!("../assets/shaders/text_msdf.frag.spv");
@@ -0,0 +1,140 @@
+//! Text shaping using rustybuzz, a port of HarfBuzz, enabled with the
+//! `shaping` feature. Converts strings into positioned glyph indices, which
+//! handles ligatures, contextual forms and mark positioning the per-char
+//! lookup can't do.
+
+use std::fs::File;
+use std::io::Read;
+use ::rustybuzz as hb;
+use ::rustybuzz::ttf_parser::{GlyphId, Tag};
+use font::{FontError, FontSource};
+
+/// Shaped glyph, all values are in pixels.
+pub struct ShapedGlyph {
+    pub index: u32,
+    // Byte offset of the first char of the glyph's cluster in the text.
+    pub cluster: usize,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+#[derive(Clone)]
+pub struct Shaper {
+    data: Vec<u8>,
+    index: u32,
+    variations: Vec<hb::Variation>,
+    // Pixel size in 26.6 fixed point, as FreeType positions glyphs.
+    scale: i64,
+    units_per_em: i64,
+    // Advance added to every glyph by synthetic emboldening, in 26.6 fixed
+    // point pixels.
+    embolden: i32,
+}
+
+impl Shaper {
+    /// Load the font for shaping, `None` if it's not an OpenType font, e.g.
+    /// a BDF or PCF one, which is laid out char by char then.
+    pub fn from_source(source: FontSource, index: u32, font_size: u8, synthetic_bold: bool,
+                       variations: &[([u8; 4], f32)]) -> Result<Option<Shaper>, FontError> {
+        let data = match source {
+            FontSource::Path(path) => {
+                let mut data = Vec::new();
+                File::open(path)?.read_to_end(&mut data)?;
+                data
+            },
+            FontSource::Data(data) => data.to_vec(),
+        };
+        let units_per_em = match hb::Face::from_slice(&data, index) {
+            Some(face) => face.units_per_em() as i64,
+            None => return Ok(None),
+        };
+        Ok(Some(Shaper {
+            data: data,
+            index: index,
+            // Advances depend on the variation axes too, so they must match
+            // the rasterized glyphs.
+            variations: variations.iter()
+                .map(|&(tag, value)| hb::Variation { tag: Tag::from_bytes(&tag), value: value })
+                .collect(),
+            scale: font_size as i64 * 64,
+            units_per_em: units_per_em.max(1),
+            // The strength `FT_GlyphSlot_Embolden` widens the glyphs by.
+            embolden: if synthetic_bold { font_size as i32 * 64 / 24 } else { 0 },
+        }))
+    }
+
+    // NOTE: The face borrows the font data, so it's parsed again for every
+    // run. Only the table directory is read, lines are cached by the layout.
+    fn face(&self) -> hb::Face<'_> {
+        let mut face = hb::Face::from_slice(&self.data, self.index).expect("font was parsed before");
+        if !self.variations.is_empty() {
+            face.set_variations(&self.variations);
+        }
+        face
+    }
+
+    /// Shape a single direction run of text using the given OpenType
+    /// features. Glyphs are returned in visual order.
+    pub fn shape(&self, text: &str, rtl: bool, features: &[([u8; 4], u32)]) -> Vec<ShapedGlyph> {
+        let mut buffer = hb::UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.guess_segment_properties();
+        buffer.set_direction(if rtl { hb::Direction::RightToLeft } else { hb::Direction::LeftToRight });
+        let features: Vec<hb::Feature> = features.iter().map(|&(tag, value)| {
+            hb::Feature::new(Tag::from_bytes(&tag), value, ..)
+        }).collect();
+        let output = hb::shape(&self.face(), &features, buffer);
+        output.glyph_infos().iter().zip(output.glyph_positions()).map(|(info, pos)| {
+            ShapedGlyph {
+                index: info.glyph_id,
+                cluster: info.cluster as usize,
+                x_advance: self.advance_px(self.to_fixed(pos.x_advance)),
+                x_offset: self.to_fixed(pos.x_offset) >> 6,
+                y_offset: self.to_fixed(pos.y_offset) >> 6,
+            }
+        }).collect()
+    }
+
+    /// Return adjustment of the glyph advances of two chars shaped together
+    /// with the given features, in pixels.
+    pub fn kerning(&self, left: char, right: char, features: &[([u8; 4], u32)]) -> i32 {
+        let mut pair = String::with_capacity(8);
+        pair.push(left);
+        pair.push(right);
+        let glyphs = self.shape(&pair, false, features);
+        // A ligature replaces the pair, there's nothing between them.
+        if glyphs.len() != 2 {
+            return 0;
+        }
+        let face = self.face();
+        glyphs.iter().map(|glyph| {
+            let advance = face.glyph_hor_advance(GlyphId(glyph.index as u16)).unwrap_or(0);
+            glyph.x_advance - self.advance_px(self.to_fixed(advance as i32))
+        }).sum()
+    }
+
+    /// Return glyph the feature substitutes for the char, if any, e.g. to
+    /// check whether the font has the feature.
+    pub fn substitute(&self, ch: char, feature: [u8; 4]) -> Option<u32> {
+        let mut buf = [0; 4];
+        let text = ch.encode_utf8(&mut buf);
+        let plain = self.shape(text, false, &[]);
+        let substituted = self.shape(text, false, &[(feature, 1)]);
+        match (plain.first(), substituted.first()) {
+            (Some(plain), Some(substituted)) if plain.index != substituted.index => Some(substituted.index),
+            _ => None,
+        }
+    }
+
+    // Convert font units to 26.6 fixed point pixels.
+    fn to_fixed(&self, units: i32) -> i32 {
+        (units as i64 * self.scale / self.units_per_em) as i32
+    }
+
+    // Convert the advance to pixels, widened like the emboldened glyph
+    // unless it's a zero-width mark.
+    fn advance_px(&self, advance: i32) -> i32 {
+        if advance != 0 { (advance + self.embolden) >> 6 } else { 0 }
+    }
+}
@@ -0,0 +1,173 @@
+//! Factory of the dummy backend, so the renderer can be built and drawn in
+//! tests without a graphics device. Resources are plain handles, drawing
+//! records nothing, but buffer updates are checked by the encoder.
+
+extern crate gfx_core;
+
+use gfx::{buffer, format, handle, mapping, memory, texture, CombinedError, Encoder, Factory, ResourceViewError,
+          ShaderSet, TargetViewError};
+use gfx::handle::Producer;
+use gfx::handle::RenderTargetView;
+use self::gfx_core::{pso, shade, Capabilities};
+pub use self::gfx_core::dummy::{DummyCommandBuffer, DummyResources};
+
+pub type R = DummyResources;
+
+pub struct TestFactory {
+    capabilities: Capabilities,
+    manager: handle::Manager<R>,
+}
+
+impl TestFactory {
+    pub fn new() -> TestFactory {
+        TestFactory {
+            capabilities: Capabilities {
+                max_vertex_count: 0,
+                max_index_count: 0,
+                max_texture_size: 4096,
+                max_patch_size: 0,
+                instance_base_supported: true,
+                instance_call_supported: true,
+                instance_rate_supported: true,
+                vertex_base_supported: true,
+                srgb_color_supported: true,
+                constant_buffer_supported: true,
+                unordered_access_view_supported: false,
+                separate_blending_slots_supported: false,
+                copy_buffer_supported: true,
+            },
+            manager: handle::Manager::new(),
+        }
+    }
+
+    /// Same as `new`, for a device without instancing.
+    pub fn without_instancing() -> TestFactory {
+        let mut factory = TestFactory::new();
+        factory.capabilities.instance_call_supported = false;
+        factory.capabilities.instance_rate_supported = false;
+        factory
+    }
+
+    pub fn render_target(&mut self, width: u16, height: u16)
+                         -> Result<RenderTargetView<R, format::Srgba8>, CombinedError> {
+        self.create_render_target::<format::Srgba8>(width, height).map(|(_, _, view)| view)
+    }
+}
+
+// Dummy resources refer to nothing, so clones may track theirs separately.
+impl Clone for TestFactory {
+    fn clone(&self) -> TestFactory {
+        TestFactory {
+            capabilities: self.capabilities,
+            manager: handle::Manager::new(),
+        }
+    }
+}
+
+pub fn encoder() -> Encoder<R, DummyCommandBuffer> {
+    DummyCommandBuffer.into()
+}
+
+impl Factory<R> for TestFactory {
+    fn get_capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    fn create_buffer_raw(&mut self, info: buffer::Info) -> Result<handle::RawBuffer<R>, buffer::CreationError> {
+        Ok(self.manager.make_buffer((), info, None))
+    }
+
+    fn create_buffer_immutable_raw(&mut self, data: &[u8], stride: usize, role: buffer::Role, bind: memory::Bind)
+                                   -> Result<handle::RawBuffer<R>, buffer::CreationError> {
+        let info = buffer::Info {
+            role: role,
+            usage: memory::Usage::Data,
+            bind: bind,
+            size: data.len(),
+            stride: stride,
+        };
+        Ok(self.manager.make_buffer((), info, None))
+    }
+
+    fn create_pipeline_state_raw(&mut self, program: &handle::Program<R>, _: &pso::Descriptor)
+                                 -> Result<handle::RawPipelineState<R>, pso::CreationError> {
+        Ok(self.manager.make_pso((), program))
+    }
+
+    fn create_program(&mut self, _: &ShaderSet<R>) -> Result<handle::Program<R>, shade::CreateProgramError> {
+        let info = shade::ProgramInfo {
+            vertex_attributes: Vec::new(),
+            globals: Vec::new(),
+            constant_buffers: Vec::new(),
+            textures: Vec::new(),
+            unordereds: Vec::new(),
+            samplers: Vec::new(),
+            outputs: Vec::new(),
+            output_depth: false,
+            knows_outputs: false,
+        };
+        Ok(self.manager.make_program((), info))
+    }
+
+    fn create_shader(&mut self, _: shade::Stage, _: &[u8]) -> Result<handle::Shader<R>, shade::CreateShaderError> {
+        Ok(self.manager.make_shader(()))
+    }
+
+    fn create_sampler(&mut self, info: texture::SamplerInfo) -> handle::Sampler<R> {
+        self.manager.make_sampler((), info)
+    }
+
+    fn read_mapping<'b, T>(&mut self, buf: &'b handle::Buffer<R, T>)
+                        -> Result<mapping::Reader<'b, R, T>, mapping::Error>
+        where T: Copy
+    {
+        // Buffers have no memory to map.
+        Err(mapping::Error::InvalidAccess(memory::Access::READ, buf.get_info().usage))
+    }
+
+    fn write_mapping<'b, T>(&mut self, buf: &'b handle::Buffer<R, T>)
+                         -> Result<mapping::Writer<'b, R, T>, mapping::Error>
+        where T: Copy
+    {
+        // Buffers have no memory to map.
+        Err(mapping::Error::InvalidAccess(memory::Access::WRITE, buf.get_info().usage))
+    }
+
+    fn create_texture_raw(&mut self, info: texture::Info, _: Option<format::ChannelType>,
+                          _: Option<(&[&[u8]], texture::Mipmap)>)
+                          -> Result<handle::RawTexture<R>, texture::CreationError> {
+        Ok(self.manager.make_texture((), info))
+    }
+
+    fn view_buffer_as_shader_resource_raw(&mut self, buffer: &handle::RawBuffer<R>, _: format::Format)
+                                          -> Result<handle::RawShaderResourceView<R>, ResourceViewError> {
+        Ok(self.manager.make_buffer_srv((), buffer))
+    }
+
+    fn view_buffer_as_unordered_access_raw(&mut self, buffer: &handle::RawBuffer<R>)
+                                           -> Result<handle::RawUnorderedAccessView<R>, ResourceViewError> {
+        Ok(self.manager.make_buffer_uav((), buffer))
+    }
+
+    fn view_texture_as_shader_resource_raw(&mut self, texture: &handle::RawTexture<R>, _: texture::ResourceDesc)
+                                           -> Result<handle::RawShaderResourceView<R>, ResourceViewError> {
+        Ok(self.manager.make_texture_srv((), texture))
+    }
+
+    fn view_texture_as_unordered_access_raw(&mut self, texture: &handle::RawTexture<R>)
+                                            -> Result<handle::RawUnorderedAccessView<R>, ResourceViewError> {
+        Ok(self.manager.make_texture_uav((), texture))
+    }
+
+    fn view_texture_as_render_target_raw(&mut self, texture: &handle::RawTexture<R>, _: texture::RenderDesc)
+                                         -> Result<handle::RawRenderTargetView<R>, TargetViewError> {
+        let dimensions = texture.get_info().kind.get_dimensions();
+        Ok(self.manager.make_rtv((), texture, dimensions))
+    }
+
+    fn view_texture_as_depth_stencil_raw(&mut self, texture: &handle::RawTexture<R>, _: texture::DepthStencilDesc)
+                                         -> Result<handle::RawDepthStencilView<R>, TargetViewError> {
+        let dimensions = texture.get_info().kind.get_dimensions();
+        Ok(self.manager.make_dsv((), texture, dimensions))
+    }
+}
@@ -0,0 +1,145 @@
+//! Selection highlight and blinking caret of a single line text input.
+
+use std::ops::Range;
+use gfx::{Factory, Resources};
+use super::{Color, Renderer};
+
+const DEFAULT_SELECTION_COLOR: [f32; 4] = [0.2, 0.4, 0.8, 0.6];
+const DEFAULT_BLINK_PERIOD: f32 = 1.0;
+
+/// Visual state of a single line text input: draws the text with the
+/// selected range highlighted and the caret blinking between grapheme
+/// clusters. Editing the string itself is left to the user.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut visual = gfx_text::TextEditVisual::new();
+///
+/// // After every edit, so the caret doesn't blink while typing:
+/// visual.reset_blink();
+///
+/// // In render loop:
+/// visual.tick(dt);
+/// visual.add_to(&mut text, &input, [10, 10], selection.clone(), caret, Color::WHITE);
+/// text.draw(&mut encoder, &color_output).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TextEditVisual {
+    selection_color: [f32; 4],
+    // Caret is drawn with the text color if not set.
+    caret_color: Option<[f32; 4]>,
+    caret_width: i32,
+    // Duration of a whole blink cycle in seconds, the caret is shown in the
+    // first half of it. Zero for a steady caret.
+    blink_period: f32,
+    // Time since the caret was shown last, in seconds.
+    blink_time: f32,
+}
+
+impl TextEditVisual {
+    /// Create the visual with translucent blue selection and one pixel wide
+    /// caret of the text color, blinking once a second.
+    pub fn new() -> TextEditVisual {
+        TextEditVisual {
+            selection_color: DEFAULT_SELECTION_COLOR,
+            caret_color: None,
+            caret_width: 1,
+            blink_period: DEFAULT_BLINK_PERIOD,
+            blink_time: 0.0,
+        }
+    }
+
+    /// Set color of the selection highlight.
+    pub fn with_selection_color<C: Into<Color>>(mut self, color: C) -> TextEditVisual {
+        self.selection_color = color.into().0;
+        self
+    }
+
+    /// Set color of the caret, which is the text color by default.
+    pub fn with_caret_color<C: Into<Color>>(mut self, color: C) -> TextEditVisual {
+        self.caret_color = Some(color.into().0);
+        self
+    }
+
+    /// Set width of the caret in pixels.
+    pub fn with_caret_width(mut self, width: i32) -> TextEditVisual {
+        self.caret_width = width;
+        self
+    }
+
+    /// Set duration of a whole blink cycle in seconds, zero disables
+    /// blinking.
+    pub fn with_blink_period(mut self, period: f32) -> TextEditVisual {
+        self.blink_period = period;
+        self
+    }
+
+    /// Advance blinking by the time since the last frame in seconds.
+    pub fn tick(&mut self, dt: f32) {
+        self.blink_time += dt;
+        if self.blink_period > 0.0 {
+            self.blink_time %= self.blink_period;
+        }
+    }
+
+    /// Show the caret and restart blinking, e.g. after the caret is moved.
+    pub fn reset_blink(&mut self) {
+        self.blink_time = 0.0;
+    }
+
+    /// Whether the caret is shown in the current frame.
+    pub fn caret_visible(&self) -> bool {
+        self.blink_period <= 0.0 || self.blink_time < self.blink_period / 2.0
+    }
+
+    /// Return rectangle (x, y, width, height) of the caret before the
+    /// grapheme cluster at the byte offset of the text added at the
+    /// position, in the coordinates of the position. Offsets inside a
+    /// cluster are moved to its start.
+    pub fn caret_rect<R: Resources, F: Factory<R>>(&self, renderer: &Renderer<R, F>, text: &str,
+                                                 pos: [i32; 2], caret: usize) -> [i32; 4] {
+        renderer.screen_rect(self.layout_caret_rect(renderer, text, renderer.layout_pos(pos), caret))
+    }
+
+    // Caret rectangle in the layout coordinates, see `Renderer::layout_pos`.
+    fn layout_caret_rect<R: Resources, F: Factory<R>>(&self, renderer: &Renderer<R, F>, text: &str,
+                                                    pos: [i32; 2], caret: usize) -> [i32; 4] {
+        let x = renderer.caret_positions(text).into_iter()
+            .take_while(|&(offset, _)| offset <= caret)
+            .last()
+            .map_or(0, |(_, x)| x);
+        [pos[0] + x - self.caret_width / 2, pos[1], self.caret_width, renderer.layout.atlas().line_height()]
+    }
+
+    /// Add the text with the byte range highlighted and the caret before
+    /// the given byte offset to the renderer, the position is the same as
+    /// of `Renderer::add`. Range and caret must lie on char boundaries.
+    pub fn add_to<R: Resources, F: Factory<R>, C: Into<Color>>(
+        &self,
+        renderer: &mut Renderer<R, F>,
+        text: &str,
+        pos: [i32; 2],
+        selection: Range<usize>,
+        caret: usize,
+        color: C,
+    ) {
+        let color = color.into().0;
+        if selection.start < selection.end {
+            renderer.add_with_selection(text, pos, color, selection, self.selection_color);
+        } else {
+            renderer.add(text, pos, color);
+        }
+        if self.caret_visible() && self.caret_width > 0 {
+            let rect = self.layout_caret_rect(renderer, text, renderer.layout_pos(pos), caret);
+            let quad = renderer.layout.solid_quad(rect);
+            renderer.add_quads(&[quad], self.caret_color.unwrap_or(color));
+        }
+    }
+}
+
+impl Default for TextEditVisual {
+    fn default() -> TextEditVisual {
+        TextEditVisual::new()
+    }
+}